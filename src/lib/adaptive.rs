@@ -0,0 +1,112 @@
+use glam::DVec3;
+
+// running mean and variance of a pixel's samples (welford's algorithm)
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PixelStats {
+    pub n: u32,
+    pub mean: DVec3,
+    // sum of squared differences from the mean
+    pub m2: DVec3,
+}
+impl PixelStats {
+    pub fn add(&mut self, x: DVec3) {
+        self.n += 1;
+        let delta = x - self.mean;
+        self.mean += delta / self.n as f64;
+        self.m2 += delta * (x - self.mean);
+    }
+    // unbiased sample variance, zero until there are two samples
+    pub fn variance(&self) -> DVec3 {
+        match self.n {
+            0 | 1 => DVec3::ZERO,
+            n => self.m2 / (n - 1) as f64,
+        }
+    }
+    // standard error of the mean relative to the mean, worst channel. dark
+    // pixels are judged against a floor so they don't sample forever
+    pub fn error(&self) -> f64 {
+        match self.n {
+            0 | 1 => f64::INFINITY,
+            n => ((self.variance() / n as f64).powf(0.5) / (self.mean.abs() + DVec3::splat(0.01))).max_element(),
+        }
+    }
+}
+// keeps shooting camera rays at a pixel until its estimated error drops
+// under the threshold, never fewer than min_spp or more than max_spp.
+// replaces Scene::spp when set
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Adaptive {
+    pub min_spp: u32,
+    pub max_spp: u32,
+    pub threshold: f64,
+}
+impl Default for Adaptive {
+    fn default() -> Self {
+        Self { min_spp: 8, max_spp: 256, threshold: 0.02 }
+    }
+}
+#[allow(dead_code)]
+impl Adaptive {
+    pub fn is_done(&self, stats: &PixelStats) -> bool {
+        stats.n >= self.max_spp.max(1) || (stats.n >= self.min_spp.max(2) && stats.error() < self.threshold)
+    }
+    // sample counts as linear colours, black at min_spp through red and
+    // yellow to white at max_spp
+    pub fn heatmap(&self, samples: &[u32]) -> Vec<DVec3> {
+        let range = (self.max_spp as f64 - self.min_spp as f64).max(1.);
+        samples.iter().map(|n| {
+            let t = 3. * ((*n as f64 - self.min_spp as f64) / range).clamp(0., 1.);
+            DVec3::new(t.min(1.), (t - 1.).clamp(0., 1.), (t - 2.).clamp(0., 1.))
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::DVec3;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::{PixelStats, Adaptive};
+
+    #[test]
+    fn test_pixel_stats() {
+        let xs = [1., 2., 4., 7.];
+        let mut stats = PixelStats::default();
+        assert_eq!(stats.error(), f64::INFINITY);
+        xs.iter().for_each(|x| stats.add(DVec3::new(*x, 2. * x, 0.)));
+        assert_eq!(stats.n, 4);
+        assert!((stats.mean.x - 3.5).abs() < 1e-12);
+        // sum of squares 6.25 + 2.25 + 0.25 + 12.25 over n - 1
+        assert!((stats.variance().x - 7.).abs() < 1e-12);
+        assert!((stats.variance().y - 28.).abs() < 1e-12);
+        assert_eq!(stats.variance().z, 0.);
+        assert!((stats.error() - (28f64 / 4.).sqrt() / 7.01).abs() < 1e-12);
+    }
+    #[test]
+    fn test_stopping() {
+        let adaptive = Adaptive { min_spp: 4, max_spp: 64, threshold: 0.05 };
+        let mut rng = StdRng::seed_from_u64(5);
+        let mut count = |spread: f64| {
+            let mut stats = PixelStats::default();
+            while !adaptive.is_done(&stats) {
+                stats.add(DVec3::splat(0.5 + spread * (rng.gen::<f64>() - 0.5)));
+            }
+            stats.n
+        };
+        // a flat pixel stops at the minimum, a noisy one runs to the maximum
+        assert_eq!(count(0.), 4);
+        assert_eq!(count(1.), 64);
+        let some = count(0.4);
+        assert!(some > 4 && some < 64, "{}", some);
+    }
+    #[test]
+    fn test_heatmap() {
+        let adaptive = Adaptive { min_spp: 4, max_spp: 64, threshold: 0.05 };
+        let map = adaptive.heatmap(&[4, 24, 44, 64, 100]);
+        assert_eq!(map[0], DVec3::ZERO);
+        assert_eq!(map[1], DVec3::X);
+        assert_eq!(map[2], DVec3::new(1., 1., 0.));
+        assert_eq!(map[3], DVec3::ONE);
+        assert_eq!(map[4], DVec3::ONE);
+    }
+}
@@ -0,0 +1,218 @@
+use std::f64::consts::PI;
+use glam::{DVec2, DVec3};
+
+use super::{LightSource, LightSample, coordinate_system, sample_cone, solve_quadratic};
+
+// converts a point q picked on the emitter with density 1/area into a
+// contribution from the shading point p (area measure to solid angle)
+fn area_sample(p: DVec3, q: DVec3, nq: DVec3, radiance: DVec3, area: f64, two_sided: bool) -> Option<LightSample> {
+    let d = q - p;
+    let d2 = d.length_squared();
+    if d2 <= 0. {
+        return None;
+    }
+    let dir = d / d2.sqrt();
+    let cos_l = -nq.dot(dir);
+    let cos_l = match two_sided {
+        true => cos_l.abs(),
+        false => cos_l,
+    };
+    match cos_l > 0. {
+        true => Some(LightSample { dir, distance: d2.sqrt(), radiance: radiance * cos_l * area / d2 }),
+        false => None,
+    }
+}
+// concentric mapping of the unit square onto the unit disk
+pub fn sample_disk(u: DVec2) -> DVec2 {
+    let o = u * 2. - DVec2::ONE;
+    if o == DVec2::ZERO {
+        return DVec2::ZERO;
+    }
+    let (r, theta) = match o.x.abs() > o.y.abs() {
+        true => (o.x, PI / 4. * (o.y / o.x)),
+        false => (o.y, PI / 2. - PI / 4. * (o.x / o.y)),
+    };
+    DVec2::new(theta.cos(), theta.sin()) * r
+}
+// uniform barycentrics (b0, b1) on a triangle
+pub fn sample_triangle(u: DVec2) -> DVec2 {
+    let su = u.x.sqrt();
+    DVec2::new(1. - su, u.y * su)
+}
+
+// sphere of uniform emitted radiance (W/sr/m^2). like the other area lights here
+// it is only reached by shadow rays, camera rays do not see it
+#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq, Copy)]
+pub struct SphereLight {
+    pub center: DVec3,
+    pub radius: f64,
+    pub radiance: DVec3,
+}
+impl LightSource for SphereLight {
+    fn sample_li(&self, p: DVec3, u: DVec2) -> Option<LightSample> {
+        let dc = self.center - p;
+        let d2 = dc.length_squared();
+        let r2 = self.radius * self.radius;
+        if d2 <= r2 {
+            // inside the emitter: fall back to sampling the surface by area
+            let (t, b) = coordinate_system(dc.try_normalize().unwrap_or(DVec3::Z));
+            let z = 1. - 2. * u.x;
+            let r = (1. - z * z).max(0.).sqrt();
+            let phi = 2. * PI * u.y;
+            let nq = t * r * phi.cos() + b * r * phi.sin() + dc.try_normalize().unwrap_or(DVec3::Z) * z;
+            return area_sample(p, self.center + nq * self.radius, nq, self.radiance, 4. * PI * r2, true);
+        }
+        // sample the cone the sphere subtends, pdf = 1 / solid angle
+        let w = dc / d2.sqrt();
+        let cos_max = (1. - r2 / d2).max(0.).sqrt();
+        let dir = sample_cone(w, cos_max, u);
+        let l = -dc;
+        let distance = match solve_quadratic(1., 2. * l.dot(dir), l.dot(l) - r2) {
+            Ok((t0, _)) if t0 > 0. => t0,
+            Ok((_, t1)) => t1.max(0.),
+            // grazing ray at the silhouette, take the closest approach
+            Err(_) => dc.dot(dir),
+        };
+        Some(LightSample { dir, distance, radiance: self.radiance * 2. * PI * (1. - cos_max) })
+    }
+    fn is_delta(&self) -> bool {
+        false
+    }
+}
+// parallelogram spanned by edge_u and edge_v at corner, emitting along edge_u x edge_v
+#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq, Copy)]
+pub struct RectLight {
+    pub corner: DVec3,
+    pub edge_u: DVec3,
+    pub edge_v: DVec3,
+    pub radiance: DVec3,
+    pub two_sided: bool,
+}
+impl LightSource for RectLight {
+    fn sample_li(&self, p: DVec3, u: DVec2) -> Option<LightSample> {
+        let c = self.edge_u.cross(self.edge_v);
+        let q = self.corner + self.edge_u * u.x + self.edge_v * u.y;
+        area_sample(p, q, c.normalize(), self.radiance, c.length(), self.two_sided)
+    }
+    fn is_delta(&self) -> bool {
+        false
+    }
+}
+#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq, Copy)]
+pub struct DiskLight {
+    pub center: DVec3,
+    pub normal: DVec3,
+    pub radius: f64,
+    pub radiance: DVec3,
+    pub two_sided: bool,
+}
+impl LightSource for DiskLight {
+    fn sample_li(&self, p: DVec3, u: DVec2) -> Option<LightSample> {
+        let n = self.normal.normalize();
+        let (t, b) = coordinate_system(n);
+        let d = sample_disk(u) * self.radius;
+        let q = self.center + t * d.x + b * d.y;
+        area_sample(p, q, n, self.radiance, PI * self.radius * self.radius, self.two_sided)
+    }
+    fn is_delta(&self) -> bool {
+        false
+    }
+}
+// triangles are picked proportionally to their area, front faces follow (v1 - v0) x (v2 - v0)
+#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct TriangleMeshLight {
+    pub triangles: Vec<[DVec3; 3]>,
+    pub radiance: DVec3,
+    pub two_sided: bool,
+    cdf: Vec<f64>,
+    area: f64,
+}
+#[allow(dead_code)]
+impl TriangleMeshLight {
+    pub fn new(triangles: Vec<[DVec3; 3]>, radiance: DVec3, two_sided: bool) -> Self {
+        let mut area = 0.;
+        let cdf = triangles.iter().map(|[v0, v1, v2]| {
+            area += 0.5 * (*v1 - *v0).cross(*v2 - *v0).length();
+            area
+        }).collect();
+        Self { triangles, radiance, two_sided, cdf, area }
+    }
+    pub fn area(&self) -> f64 {
+        self.area
+    }
+}
+impl LightSource for TriangleMeshLight {
+    fn sample_li(&self, p: DVec3, u: DVec2) -> Option<LightSample> {
+        if self.area <= 0. {
+            return None;
+        }
+        // pick a triangle with u.x, then reuse what is left of it
+        let target = u.x * self.area;
+        let i = self.cdf.partition_point(|&c| c < target).min(self.triangles.len() - 1);
+        let lo = if i == 0 { 0. } else { self.cdf[i - 1] };
+        let ux = ((target - lo) / (self.cdf[i] - lo)).clamp(0., 1.);
+        let [v0, v1, v2] = self.triangles[i];
+        let b = sample_triangle(DVec2::new(ux, u.y));
+        let q = v0 * b.x + v1 * b.y + v2 * (1. - b.x - b.y);
+        let n = (v1 - v0).cross(v2 - v0).normalize();
+        area_sample(p, q, n, self.radiance, self.area, self.two_sided)
+    }
+    fn is_delta(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+    use glam::{DVec2, DVec3};
+
+    use crate::lib::{LightSource, SphereLight, RectLight, DiskLight, TriangleMeshLight, sample_disk};
+
+    // irradiance at the origin on a surface facing +y, averaged over a grid of samples
+    fn irradiance(li: &dyn LightSource) -> f64 {
+        let n = 64;
+        let mut sum = 0.;
+        for i in 0..n {
+            for j in 0..n {
+                let u = DVec2::new((i as f64 + 0.5) / n as f64, (j as f64 + 0.5) / n as f64);
+                if let Some(ls) = li.sample_li(DVec3::ZERO, u) {
+                    sum += ls.radiance.x * ls.dir.y.max(0.);
+                }
+            }
+        }
+        sum / (n * n) as f64
+    }
+    #[test]
+    fn test_sphere_light() {
+        // a sphere subtending half angle a gives E = pi L sin^2 a
+        let li = SphereLight { center: DVec3::new(0., 4., 0.), radius: 1., radiance: DVec3::ONE };
+        let e = irradiance(&li);
+        assert!((e - PI / 16.).abs() < 1e-2, "{}", e);
+        let ls = li.sample_li(DVec3::ZERO, DVec2::new(0., 0.)).unwrap();
+        assert!((ls.distance - 3.).abs() < 1e-9);
+    }
+    #[test]
+    fn test_disk_light() {
+        // disk of radius r at height h facing down: E = pi L r^2 / (h^2 + r^2)
+        let li = DiskLight { center: DVec3::new(0., 2., 0.), normal: DVec3::new(0., -1., 0.), radius: 1., radiance: DVec3::ONE, two_sided: false };
+        let e = irradiance(&li);
+        assert!((e - PI / 5.).abs() < 1e-2, "{}", e);
+        let up = DiskLight { normal: DVec3::Y, ..li };
+        assert!(up.sample_li(DVec3::ZERO, DVec2::splat(0.5)).is_none());
+        assert!(sample_disk(DVec2::new(1., 0.5)).length() <= 1. + 1e-12);
+    }
+    #[test]
+    fn test_rect_and_mesh_light() {
+        let rect = RectLight { corner: DVec3::new(-1., 2., -1.), edge_u: DVec3::new(2., 0., 0.), edge_v: DVec3::new(0., 0., 2.), radiance: DVec3::ONE, two_sided: false };
+        let [a, b, c, d] = [DVec3::new(-1., 2., -1.), DVec3::new(-1., 2., 1.), DVec3::new(1., 2., 1.), DVec3::new(1., 2., -1.)];
+        let mesh = TriangleMeshLight::new(vec![[a, d, c], [a, c, b]], DVec3::ONE, false);
+        assert_eq!(mesh.area(), 4.);
+        let (er, em) = (irradiance(&rect), irradiance(&mesh));
+        assert!((er - em).abs() < 2e-2, "{} {}", er, em);
+    }
+}
@@ -0,0 +1,359 @@
+use std::{borrow::Borrow, f64::consts::PI, fmt, ops::Deref, sync::Arc};
+use glam::{DVec2, DVec3};
+
+use super::{Texture, Material, SpecularProperties, Interior, Medium, Dispersion, reflect, refract, fresnel, coordinate_system, sample_disk};
+
+// where a bsdf is evaluated: hit position, shading normal, texture coordinates,
+// the surface tangent that anisotropic lobes are aligned to, the refractive
+// index of whatever is on the side the normal points to and, in spectral
+// mode, the hero wavelength in nm
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShadingPoint {
+    pub p: DVec3,
+    pub n: DVec3,
+    pub st: DVec2,
+    pub dpdu: DVec3,
+    pub outside_ior: f64,
+    pub wavelength: Option<f64>,
+}
+// f is the bsdf value for (wo, wi) and pdf the solid angle density wi was drawn with.
+// delta lobes store their discrete weight instead, f * |cos| / pdf is what counts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BsdfSample {
+    pub wi: DVec3,
+    pub f: DVec3,
+    pub pdf: f64,
+    pub delta: bool,
+}
+// scattering at a surface point. directions are world space unit vectors leaving
+// the surface: wo towards the viewer, wi towards the light
+pub trait Bsdf: fmt::Debug + Send + Sync {
+    fn eval(&self, sp: &ShadingPoint, wo: DVec3, wi: DVec3) -> DVec3;
+    // uc picks a lobe, u samples a direction within it
+    fn sample(&self, sp: &ShadingPoint, wo: DVec3, uc: f64, u: DVec2) -> Option<BsdfSample>;
+    fn pdf(&self, sp: &ShadingPoint, wo: DVec3, wi: DVec3) -> f64;
+    // only delta lobes: eval and pdf are zero everywhere, lights cannot be sampled
+    fn is_delta(&self) -> bool {
+        false
+    }
+    // every delta lobe as (wi, weight), so a whitted tracer can follow all of
+    // them instead of picking one at random
+    fn delta_lobes(&self, _sp: &ShadingPoint, _wo: DVec3) -> Vec<(DVec3, DVec3)> {
+        vec![]
+    }
+    // rough lobes a whitted tracer follows with one sampled ray on top of the
+    // direct light, diffuse ones get direct light only
+    fn is_glossy(&self) -> bool {
+        false
+    }
+    // transmissive bsdfs enclose an interior the ray travels through
+    fn interior(&self) -> Option<Interior> {
+        None
+    }
+    // scatters different wavelengths into different directions, so a spectral
+    // path can only keep following its hero wavelength
+    fn is_dispersive(&self) -> bool {
+        false
+    }
+    // not really a surface, light goes through unchanged (see MediumBoundary)
+    fn is_passthrough(&self) -> bool {
+        false
+    }
+}
+// opaque surfaces are two sided, shade with the normal on the viewer's side
+fn face_forward(n: DVec3, wo: DVec3) -> DVec3 {
+    match n.dot(wo) < 0. {
+        true => -n,
+        false => n,
+    }
+}
+// cosine weighted direction around n, pdf = cos / pi
+pub fn sample_cosine_hemisphere(n: DVec3, u: DVec2) -> DVec3 {
+    let d = sample_disk(u);
+    let (t, b) = coordinate_system(n);
+    t * d.x + b * d.y + n * (1. - d.length_squared()).max(0.).sqrt()
+}
+#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lambertian {
+    pub albedo: Texture,
+}
+impl Bsdf for Lambertian {
+    fn eval(&self, sp: &ShadingPoint, wo: DVec3, wi: DVec3) -> DVec3 {
+        match face_forward(sp.n, wo).dot(wi) > 0. {
+            true => self.albedo.eval(sp.st, sp.p) / PI,
+            false => DVec3::ZERO,
+        }
+    }
+    fn sample(&self, sp: &ShadingPoint, wo: DVec3, _uc: f64, u: DVec2) -> Option<BsdfSample> {
+        let wi = sample_cosine_hemisphere(face_forward(sp.n, wo), u);
+        let pdf = self.pdf(sp, wo, wi);
+        match pdf > 0. {
+            true => Some(BsdfSample { wi, f: self.eval(sp, wo, wi), pdf, delta: false }),
+            false => None,
+        }
+    }
+    fn pdf(&self, sp: &ShadingPoint, wo: DVec3, wi: DVec3) -> f64 {
+        face_forward(sp.n, wo).dot(wi).max(0.) / PI
+    }
+}
+// kd * lambert + ks * normalized phong lobe of the given exponent around the mirror direction.
+// the lambert term is kd * diffuse / pi, so kd = 1 reflects all light that arrives.
+// shapes lend it their texture, see ShapeBsdf
+#[derive(Debug, Clone, PartialEq)]
+pub struct Phong<T = Texture> {
+    pub diffuse: T,
+    pub kd: f64,
+    pub ks: f64,
+    pub exponent: f64,
+}
+impl<T> Phong<T> {
+    fn specular_probability(&self) -> f64 {
+        match self.kd + self.ks > 0. {
+            true => self.ks / (self.kd + self.ks),
+            false => 0.,
+        }
+    }
+}
+impl<T: Borrow<Texture> + fmt::Debug + Send + Sync> Bsdf for Phong<T> {
+    fn eval(&self, sp: &ShadingPoint, wo: DVec3, wi: DVec3) -> DVec3 {
+        let n = face_forward(sp.n, wo);
+        if n.dot(wi) <= 0. {
+            return DVec3::ZERO;
+        }
+        let cos_a = reflect(-wo, n).dot(wi).max(0.);
+        self.diffuse.borrow().eval(sp.st, sp.p) * self.kd / PI + DVec3::splat(self.ks * (self.exponent + 2.) / (2. * PI) * cos_a.powf(self.exponent))
+    }
+    fn sample(&self, sp: &ShadingPoint, wo: DVec3, uc: f64, u: DVec2) -> Option<BsdfSample> {
+        let n = face_forward(sp.n, wo);
+        let wi = match uc < self.specular_probability() {
+            true => {
+                let cos_a = u.x.powf(1. / (self.exponent + 1.));
+                let sin_a = (1. - cos_a * cos_a).max(0.).sqrt();
+                let phi = 2. * PI * u.y;
+                let r = reflect(-wo, n);
+                let (t, b) = coordinate_system(r);
+                t * sin_a * phi.cos() + b * sin_a * phi.sin() + r * cos_a
+            },
+            false => sample_cosine_hemisphere(n, u),
+        };
+        let pdf = self.pdf(sp, wo, wi);
+        match pdf > 0. {
+            true => Some(BsdfSample { wi, f: self.eval(sp, wo, wi), pdf, delta: false }),
+            false => None,
+        }
+    }
+    fn pdf(&self, sp: &ShadingPoint, wo: DVec3, wi: DVec3) -> f64 {
+        let n = face_forward(sp.n, wo);
+        let cos_i = n.dot(wi);
+        if cos_i <= 0. {
+            return 0.;
+        }
+        let ps = self.specular_probability();
+        let cos_a = reflect(-wo, n).dot(wi).max(0.);
+        (1. - ps) * cos_i / PI + ps * (self.exponent + 1.) / (2. * PI) * cos_a.powf(self.exponent)
+    }
+}
+// perfect specular reflection. with an ior the reflectance is further scaled by
+// the dielectric fresnel term, a glass surface that shows only its reflection
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mirror {
+    pub reflectance: DVec3,
+    pub ior: Option<f64>,
+}
+impl Mirror {
+    fn weight(&self, sp: &ShadingPoint, wo: DVec3) -> DVec3 {
+        match self.ior {
+            Some(ior) => self.reflectance * fresnel(-wo, sp.n, ior),
+            None => self.reflectance,
+        }
+    }
+}
+impl Bsdf for Mirror {
+    fn eval(&self, _sp: &ShadingPoint, _wo: DVec3, _wi: DVec3) -> DVec3 {
+        DVec3::ZERO
+    }
+    fn sample(&self, sp: &ShadingPoint, wo: DVec3, _uc: f64, _u: DVec2) -> Option<BsdfSample> {
+        let n = face_forward(sp.n, wo);
+        let wi = reflect(-wo, n);
+        let cos = wi.dot(n).abs();
+        match cos > 0. {
+            true => Some(BsdfSample { wi, f: self.weight(sp, wo) / cos, pdf: 1., delta: true }),
+            false => None,
+        }
+    }
+    fn pdf(&self, _sp: &ShadingPoint, _wo: DVec3, _wi: DVec3) -> f64 {
+        0.
+    }
+    fn is_delta(&self) -> bool {
+        true
+    }
+    fn delta_lobes(&self, sp: &ShadingPoint, wo: DVec3) -> Vec<(DVec3, DVec3)> {
+        vec![(reflect(-wo, sp.n), self.weight(sp, wo))]
+    }
+}
+// smooth glass around an interior, see Interior for absorption, priority and medium.
+// with a dispersion, spectral renders take the index from it instead of `ior`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dielectric {
+    pub ior: f64,
+    pub absorption: DVec3,
+    pub priority: i32,
+    pub dispersion: Option<Dispersion>,
+    pub medium: Option<Arc<Medium>>,
+}
+#[allow(dead_code)]
+impl Dielectric {
+    // clear glass, water and the like
+    pub fn clear(ior: f64) -> Self {
+        Self { ior, absorption: DVec3::ZERO, priority: 0, dispersion: None, medium: None }
+    }
+    pub fn ior_at(&self, sp: &ShadingPoint) -> f64 {
+        match (self.dispersion, sp.wavelength) {
+            (Some(d), Some(lambda)) => d.ior(lambda),
+            _ => self.ior,
+        }
+    }
+}
+impl Bsdf for Dielectric {
+    fn eval(&self, _sp: &ShadingPoint, _wo: DVec3, _wi: DVec3) -> DVec3 {
+        DVec3::ZERO
+    }
+    fn sample(&self, sp: &ShadingPoint, wo: DVec3, uc: f64, _u: DVec2) -> Option<BsdfSample> {
+        // choose a lobe proportionally to its weight, which then cancels out
+        let lobes = self.delta_lobes(sp, wo);
+        let (wi, w) = match lobes.len() > 1 && uc >= lobes[0].1.x {
+            true => lobes[1],
+            false => lobes[0],
+        };
+        let cos = wi.dot(sp.n).abs();
+        match cos > 0. && w.x > 0. {
+            true => Some(BsdfSample { wi, f: w / cos, pdf: w.x, delta: true }),
+            false => None,
+        }
+    }
+    fn pdf(&self, _sp: &ShadingPoint, _wo: DVec3, _wi: DVec3) -> f64 {
+        0.
+    }
+    fn is_delta(&self) -> bool {
+        true
+    }
+    fn delta_lobes(&self, sp: &ShadingPoint, wo: DVec3) -> Vec<(DVec3, DVec3)> {
+        let eta = self.ior_at(sp) / sp.outside_ior;
+        let kr = fresnel(-wo, sp.n, eta);
+        let reflected = (reflect(-wo, sp.n), DVec3::splat(kr));
+        match kr < 1. {
+            true => vec![reflected, (refract(-wo, sp.n, eta).normalize(), DVec3::splat(1. - kr))],
+            // total internal reflection
+            false => vec![reflected],
+        }
+    }
+    fn interior(&self) -> Option<Interior> {
+        Some(Interior { ior: self.ior, absorption: self.absorption, priority: self.priority, medium: self.medium.clone() })
+    }
+    fn is_dispersive(&self) -> bool {
+        self.dispersion.is_some()
+    }
+}
+// the bsdf of a shape, built on the stack from the shape's own fields on every hit
+#[derive(Debug)]
+pub enum ShapeBsdf<'a> {
+    Phong(Phong<&'a Texture>),
+    Mirror(Mirror),
+    Dielectric(Dielectric),
+    Custom(&'a dyn Bsdf),
+}
+impl<'a> Deref for ShapeBsdf<'a> {
+    type Target = dyn Bsdf + 'a;
+    fn deref(&self) -> &Self::Target {
+        match self {
+            ShapeBsdf::Phong(b) => b,
+            ShapeBsdf::Mirror(b) => b,
+            ShapeBsdf::Dielectric(b) => b,
+            ShapeBsdf::Custom(b) => *b,
+        }
+    }
+}
+impl Material {
+    // the closed set of built in materials in terms of the shape parameters
+    pub fn bsdf<'a>(&'a self, diffuse: &'a Texture, ior: f64, specular: SpecularProperties) -> ShapeBsdf<'a> {
+        match self {
+            Material::DiffuseAndGlossy => ShapeBsdf::Phong(Phong { diffuse, kd: specular.1, ks: specular.2, exponent: specular.0 }),
+            Material::ReflectionAndRefraction => ShapeBsdf::Dielectric(Dielectric::clear(ior)),
+            Material::Reflection => ShapeBsdf::Mirror(Mirror { reflectance: DVec3::ONE, ior: Some(ior) }),
+            Material::Custom(bsdf) => ShapeBsdf::Custom(bsdf.as_ref()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{f64::consts::PI, sync::Arc};
+    use glam::{DVec2, DVec3};
+
+    use crate::lib::{Bsdf, ShadingPoint, Lambertian, Phong, Mirror, Dielectric, Material, SpecularProperties, Texture};
+
+    const SP: ShadingPoint = ShadingPoint { p: DVec3::ZERO, n: DVec3::Y, st: DVec2::ZERO, dpdu: DVec3::X, outside_ior: 1., wavelength: None };
+
+    // monte carlo estimate of the directional albedo, sum of f cos / pdf
+    fn albedo(bsdf: &dyn Bsdf, wo: DVec3) -> DVec3 {
+        let n = 64;
+        let mut sum = DVec3::ZERO;
+        for i in 0..n {
+            for j in 0..n {
+                let u = DVec2::new((i as f64 + 0.5) / n as f64, (j as f64 + 0.5) / n as f64);
+                // reuse the grid for the lobe choice, decorrelated from u
+                let uc = ((i * 7 + j * 13) % n) as f64 / n as f64;
+                if let Some(s) = bsdf.sample(&SP, wo, uc, u) {
+                    sum += s.f * s.wi.dot(SP.n).abs() / s.pdf;
+                }
+            }
+        }
+        sum / (n * n) as f64
+    }
+    #[test]
+    fn test_lambertian() {
+        let lambert = Lambertian { albedo: Texture::Constant(DVec3::splat(0.5)) };
+        let wo = DVec3::new(0.3, 1., 0.).normalize();
+        assert!((albedo(&lambert, wo) - DVec3::splat(0.5)).length() < 1e-3);
+        assert_eq!(lambert.eval(&SP, wo, DVec3::Y), DVec3::splat(0.5 / PI));
+        assert_eq!(lambert.eval(&SP, wo, -DVec3::Y), DVec3::ZERO);
+        // seen from below, the surface is lit from below
+        assert_eq!(lambert.pdf(&SP, -wo, -DVec3::Y), 1. / PI);
+    }
+    #[test]
+    fn test_phong() {
+        let phong = Phong { diffuse: Texture::Constant(DVec3::ONE), kd: 0.6, ks: 0.3, exponent: 20. };
+        let wo = DVec3::new(0.5, 1., 0.).normalize();
+        let s = phong.sample(&SP, wo, 0.1, DVec2::new(0.1, 0.3)).unwrap();
+        assert!((s.pdf - phong.pdf(&SP, wo, s.wi)).abs() < 1e-12);
+        assert_eq!(s.f, phong.eval(&SP, wo, s.wi));
+        // energy conserving, some of the lobe is lost below the horizon
+        let a = albedo(&phong, wo);
+        assert!(a.x < 0.9 && a.x > 0.8, "{}", a);
+    }
+    #[test]
+    fn test_delta_lobes() {
+        let wo = DVec3::new(1., 1., 0.).normalize();
+        let mirror = Mirror { reflectance: DVec3::splat(0.9), ior: None };
+        assert!((mirror.delta_lobes(&SP, wo)[0].0 - DVec3::new(-1., 1., 0.).normalize()).length() < 1e-12);
+        assert!((albedo(&mirror, wo) - DVec3::splat(0.9)).length() < 1e-12);
+        let glass = Dielectric::clear(1.5);
+        let lobes = glass.delta_lobes(&SP, wo);
+        assert_eq!(lobes.len(), 2);
+        assert!((lobes[0].1 + lobes[1].1 - DVec3::ONE).length() < 1e-12);
+        assert!(lobes[1].0.y < 0.);
+        // leaving glass at a grazing angle reflects everything
+        assert_eq!(glass.delta_lobes(&SP, DVec3::new(1., -0.2, 0.).normalize()).len(), 1);
+        assert!((albedo(&glass, wo) - DVec3::ONE).length() < 1e-12);
+    }
+    #[test]
+    fn test_custom_material() {
+        let bsdf: Arc<dyn Bsdf> = Arc::new(Mirror { reflectance: DVec3::ONE, ior: None });
+        let m = Material::Custom(bsdf.clone());
+        assert_eq!(m, Material::Custom(bsdf));
+        let built = m.bsdf(&Texture::Constant(DVec3::ZERO), 1., SpecularProperties(1., 1., 1.));
+        assert!(built.is_delta());
+        assert!(!Material::DiffuseAndGlossy.bsdf(&Texture::checker(), 1.3, SpecularProperties(25., 0.8, 0.2)).is_delta());
+    }
+}
@@ -0,0 +1,143 @@
+use glam::DVec3;
+
+// per pixel features of the first surface each camera ray meets, averaged like
+// the colour: diffuse albedo (one for specular surfaces and misses), normal
+// and distance (infinite on a miss). also how many samples each pixel took
+#[derive(Debug, Clone, PartialEq)]
+pub struct GBuffer {
+    pub width: usize,
+    pub height: usize,
+    pub albedo: Vec<DVec3>,
+    pub normal: Vec<DVec3>,
+    pub depth: Vec<f64>,
+    pub samples: Vec<u32>,
+}
+impl GBuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        let n = width * height;
+        Self { width, height, albedo: vec![DVec3::ZERO; n], normal: vec![DVec3::ZERO; n], depth: vec![0.; n], samples: vec![0; n] }
+    }
+}
+// edge avoiding a-trous wavelet filter (dammertz et al. 2010). each pass is a
+// 5x5 b3 spline kernel with holes 2^i apart, weighted down across differences
+// in colour, normal, depth and albedo. lighting is filtered with the albedo
+// divided out so textures stay sharp. smaller sigmas keep more detail
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Denoiser {
+    pub iterations: usize,
+    pub sigma_color: f64,
+    pub sigma_normal: f64,
+    // relative to the distance
+    pub sigma_depth: f64,
+    pub sigma_albedo: f64,
+}
+impl Default for Denoiser {
+    fn default() -> Self {
+        Self { iterations: 5, sigma_color: 0.6, sigma_normal: 0.1, sigma_depth: 0.05, sigma_albedo: 0.1 }
+    }
+}
+const KERNEL: [f64; 5] = [1. / 16., 1. / 4., 3. / 8., 1. / 4., 1. / 16.];
+
+#[allow(dead_code)]
+impl Denoiser {
+    pub fn denoise(&self, color: &[DVec3], aux: &GBuffer) -> Vec<DVec3> {
+        let (w, h) = (aux.width as i64, aux.height as i64);
+        assert_eq!(color.len(), aux.albedo.len());
+        let demod = |i: usize| aux.albedo[i].max(DVec3::splat(1e-3));
+        let mut light: Vec<DVec3> = color.iter().enumerate().map(|(i, c)| *c / demod(i)).collect();
+        (0..self.iterations).for_each(|it| {
+            let step = 1i64 << it;
+            // later passes see smoother input, so colour edges count for more
+            let sigma_c = self.sigma_color * 0.5f64.powi(it as i32);
+            let filtered = (0..h).flat_map(|y| (0..w).map(move |x| (x, y))).map(|(x, y)| {
+                let p = (y * w + x) as usize;
+                let (cp, np, dp, ap) = (light[p], aux.normal[p], aux.depth[p], aux.albedo[p]);
+                let mut sum = DVec3::ZERO;
+                let mut total = 0.;
+                (0..5).for_each(|j| (0..5).for_each(|i| {
+                    let (qx, qy) = (x + (i as i64 - 2) * step, y + (j as i64 - 2) * step);
+                    if qx < 0 || qy < 0 || qx >= w || qy >= h {
+                        return;
+                    }
+                    let q = (qy * w + qx) as usize;
+                    let dd = match (dp.is_finite(), aux.depth[q].is_finite()) {
+                        (true, true) => (dp - aux.depth[q]) / dp.abs().max(1e-6),
+                        (false, false) => 0.,
+                        _ => f64::INFINITY,
+                    };
+                    let weight = KERNEL[i] * KERNEL[j]
+                        * (-(cp - light[q]).length_squared() / (sigma_c * sigma_c)).exp()
+                        * (-(np - aux.normal[q]).length_squared() / (self.sigma_normal * self.sigma_normal)).exp()
+                        * (-dd * dd / (self.sigma_depth * self.sigma_depth)).exp()
+                        * (-(ap - aux.albedo[q]).length_squared() / (self.sigma_albedo * self.sigma_albedo)).exp();
+                    sum += light[q] * weight;
+                    total += weight;
+                }));
+                // the centre always has weight, total is never zero
+                sum / total
+            }).collect();
+            light = filtered;
+        });
+        light.iter().enumerate().map(|(i, l)| *l * demod(i)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{DVec2, DVec3};
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use crate::lib::{Scene, Sphere, MeshTriangle, Triangle, Material, SpecularProperties, Texture, SphereLight, ObjectAppend, LightAppend, render_frame, diffuse_ball};
+
+    use super::{Denoiser, GBuffer};
+
+    fn mse(a: &[DVec3], b: &[DVec3]) -> f64 {
+        a.iter().zip(b).map(|(x, y)| (*x - *y).length_squared()).sum::<f64>() / a.len() as f64
+    }
+    #[test]
+    fn test_edges_preserved() {
+        // two flat noisy halves with different normals and albedos
+        let (w, h) = (32, 16);
+        let mut aux = GBuffer::new(w, h);
+        let mut rng = StdRng::seed_from_u64(3);
+        let clean: Vec<DVec3> = (0..w * h).map(|i| if i % w < w / 2 { DVec3::splat(0.2) } else { DVec3::new(0.9, 0.5, 0.1) }).collect();
+        (0..w * h).for_each(|i| {
+            let left = i % w < w / 2;
+            aux.albedo[i] = if left { DVec3::splat(0.4) } else { DVec3::new(0.9, 0.5, 0.1) };
+            aux.normal[i] = if left { DVec3::Y } else { DVec3::X };
+            aux.depth[i] = 5.;
+        });
+        let noisy: Vec<DVec3> = clean.iter().map(|c| *c * (1. + 0.5 * (rng.gen::<f64>() - 0.5))).collect();
+        let denoised = Denoiser::default().denoise(&noisy, &aux);
+        assert!(mse(&denoised, &clean) < 0.1 * mse(&noisy, &clean));
+        // nothing bleeds over the edge
+        (0..h).for_each(|y| {
+            assert!((denoised[y * w + w / 2 - 1] - clean[y * w + w / 2 - 1]).length() < 0.03);
+            assert!((denoised[y * w + w / 2] - clean[y * w + w / 2]).length() < 0.1);
+        });
+    }
+    #[test]
+    fn test_reference_scene() {
+        // a ball on a floor under a large area light: soft shadows with one
+        // shadow ray per pixel are noisy, 256 make the reference
+        let scene = |shadow_samples: u32| {
+            let mut sc = Scene::window(48, 36);
+            sc.shadow_samples = shadow_samples;
+            let ball = Sphere { specular: SpecularProperties(25.0, 0.8, 0.), ..diffuse_ball(DVec3::new(0., -1., -6.), 1., DVec3::new(0.8, 0.3, 0.2)) };
+            let (a, b, c, d) = (DVec3::new(-6., -2., -2.), DVec3::new(6., -2., -2.), DVec3::new(6., -2., -12.), DVec3::new(-6., -2., -12.));
+            let floor = MeshTriangle { specular: SpecularProperties(25.0, 0.8, 0.), diffuse: Texture::Constant(DVec3::splat(0.7)), ..MeshTriangle::new(vec![
+                Triangle { v0: a, v1: b, v2: d, s0: DVec2::ZERO, s1: DVec2::X, s2: DVec2::Y },
+                Triangle { v0: b, v1: c, v2: d, s0: DVec2::X, s1: DVec2::ONE, s2: DVec2::Y },
+            ], Material::DiffuseAndGlossy) };
+            ObjectAppend::append(&mut sc, Box::new(ball));
+            ObjectAppend::append(&mut sc, Box::new(floor));
+            LightAppend::append(&mut sc, SphereLight { center: DVec3::new(0., 3.5, -6.), radius: 2., radiance: DVec3::splat(3.) });
+            sc
+        };
+        let (reference, _) = render_frame(&mut scene(256)).unwrap();
+        let (noisy, aux) = render_frame(&mut scene(1)).unwrap();
+        let denoised = Denoiser::default().denoise(&noisy, &aux);
+        let (before, after) = (mse(&noisy, &reference), mse(&denoised, &reference));
+        assert!(after < 0.5 * before, "{} {}", before, after);
+    }
+}
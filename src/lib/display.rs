@@ -0,0 +1,201 @@
+use glam::{DMat3, DVec3};
+
+// srgb oetf, the inverse of srgb_to_linear
+pub fn linear_to_srgb(c: f64) -> f64 {
+    match c <= 0.0031308 {
+        true => c * 12.92,
+        false => 1.055 * c.powf(1. / 2.4) - 0.055,
+    }
+}
+// matrices below are column major, the way glam takes them
+const SRGB_TO_XYZ: [f64; 9] = [0.4124, 0.2126, 0.0193, 0.3576, 0.7152, 0.1192, 0.1805, 0.0722, 0.9505];
+const BRADFORD: [f64; 9] = [0.8951, -0.7502, 0.0389, 0.2664, 1.7135, -0.0685, -0.1614, 0.0367, 1.0296];
+const D65: DVec3 = DVec3::new(0.95047, 1., 1.08883);
+// stephen hill's fit of the aces rrt + odt, in and out of its working space
+const ACES_IN: [f64; 9] = [0.59719, 0.07600, 0.02840, 0.35458, 0.90834, 0.13383, 0.04823, 0.01566, 0.83777];
+const ACES_OUT: [f64; 9] = [1.60475, -0.10208, -0.00327, -0.53108, 1.10813, -0.07276, -0.07367, -0.00605, 1.07602];
+// minimal agx of benjamin wrensch, inset and outset matrices for linear srgb
+const AGX_IN: [f64; 9] = [0.842479062253094, 0.0423282422610123, 0.0423756549057051, 0.0784335999999992, 0.878468636469772, 0.0784336, 0.0792237451477643, 0.0791661274605434, 0.879142973793104];
+const AGX_OUT: [f64; 9] = [1.19687900512017, -0.0528968517574562, -0.0529716355144438, -0.0980208811401368, 1.15190312990417, -0.0980434501171241, -0.0990297440797205, -0.0989611768448433, 1.15107367264116];
+const AGX_MIN_EV: f64 = -12.47393;
+const AGX_MAX_EV: f64 = 4.026069;
+
+// xyz (y = 1) of a planckian radiator, kim et al. 2002 fit, 1667 K to 25000 K
+pub fn blackbody_white(kelvin: f64) -> DVec3 {
+    let t = kelvin.clamp(1667., 25000.);
+    let x = match t <= 4000. {
+        true => -0.2661239e9 / t.powi(3) - 0.2343589e6 / t.powi(2) + 0.8776956e3 / t + 0.179910,
+        false => -3.0258469e9 / t.powi(3) + 2.1070379e6 / t.powi(2) + 0.2226347e3 / t + 0.240390,
+    };
+    let y = if t <= 2222. {
+        -1.1063814 * x.powi(3) - 1.34811020 * x.powi(2) + 2.18555832 * x - 0.20219683
+    } else if t <= 4000. {
+        -0.9549476 * x.powi(3) - 1.37418593 * x.powi(2) + 2.09137015 * x - 0.16748867
+    } else {
+        3.0817580 * x.powi(3) - 5.87338670 * x.powi(2) + 3.75112997 * x - 0.37001483
+    };
+    DVec3::new(x / y, 1., (1. - x - y) / y)
+}
+// bradford adaptation in linear srgb that turns light of the given colour
+// temperature into d65 white
+pub fn white_balance(kelvin: f64) -> DMat3 {
+    let to_xyz = DMat3::from_cols_array(&SRGB_TO_XYZ);
+    let bradford = DMat3::from_cols_array(&BRADFORD);
+    let gains = (bradford * D65) / (bradford * blackbody_white(kelvin));
+    to_xyz.inverse() * bradford.inverse() * DMat3::from_diagonal(gains) * bradford * to_xyz
+}
+// maps exposed linear radiance into [0, 1], still linear
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ToneMap {
+    Clamp,
+    Reinhard,
+    // reinhard reaching 1 at `white` instead of infinity
+    ExtendedReinhard { white: f64 },
+    // uncharted 2 filmic curve by john hable
+    Hable,
+    AcesFitted,
+    AgX,
+}
+impl ToneMap {
+    pub fn map(&self, c: DVec3) -> DVec3 {
+        let c = c.max(DVec3::ZERO);
+        match self {
+            ToneMap::Clamp => c,
+            ToneMap::Reinhard => c / (DVec3::ONE + c),
+            ToneMap::ExtendedReinhard { white } => c * (DVec3::ONE + c / (white * white)) / (DVec3::ONE + c),
+            ToneMap::Hable => {
+                let (a, b, cc, d, e, f) = (0.15, 0.5, 0.1, 0.2, 0.02, 0.3);
+                let curve = |x: f64| (x * (a * x + cc * b) + d * e) / (x * (a * x + b) + d * f) - e / f;
+                let white = curve(11.2);
+                DVec3::new(curve(2. * c.x), curve(2. * c.y), curve(2. * c.z)) / white
+            }
+            ToneMap::AcesFitted => {
+                let v = DMat3::from_cols_array(&ACES_IN) * c;
+                let fit = |v: f64| (v * (v + 0.0245786) - 0.000090537) / (v * (0.983729 * v + 0.4329510) + 0.238081);
+                DMat3::from_cols_array(&ACES_OUT) * DVec3::new(fit(v.x), fit(v.y), fit(v.z))
+            }
+            ToneMap::AgX => {
+                let v = DMat3::from_cols_array(&AGX_IN) * c;
+                let encode = |x: f64| (x.max(1e-10).log2().clamp(AGX_MIN_EV, AGX_MAX_EV) - AGX_MIN_EV) / (AGX_MAX_EV - AGX_MIN_EV);
+                // polynomial approximation of the agx base contrast curve
+                let contrast = |x: f64| {
+                    let (x2, x4) = (x * x, x * x * x * x);
+                    15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232
+                };
+                let v = DMat3::from_cols_array(&AGX_OUT) * DVec3::new(contrast(encode(v.x)), contrast(encode(v.y)), contrast(encode(v.z)));
+                // the curve produces display values, back to linear for the oetf
+                v.max(DVec3::ZERO).powf(2.2)
+            }
+        }.clamp(DVec3::ZERO, DVec3::ONE)
+    }
+}
+// 8x8 bayer matrix, thresholds in [0, 64)
+const BAYER: [[u8; 8]; 8] = [
+    [0, 32, 8, 40, 2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44, 4, 36, 14, 46, 6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [3, 35, 11, 43, 1, 33, 9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47, 7, 39, 13, 45, 5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+// from linear scene radiance to 8 bit srgb: exposure in stops, white balance
+// for light of the given colour temperature, tone mapping, the srgb oetf and
+// ordered dithering to break up banding in smooth gradients
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DisplayTransform {
+    pub exposure: f64,
+    pub white_balance: Option<f64>,
+    pub tone_map: ToneMap,
+    pub dither: bool,
+}
+impl Default for DisplayTransform {
+    fn default() -> Self {
+        Self { exposure: 0., white_balance: None, tone_map: ToneMap::Clamp, dither: false }
+    }
+}
+#[allow(dead_code)]
+impl DisplayTransform {
+    // srgb encoded display colour in [0, 1]
+    pub fn apply(&self, c: DVec3) -> DVec3 {
+        self.apply_balanced(c, self.white_balance.map(white_balance))
+    }
+    fn apply_balanced(&self, c: DVec3, wb: Option<DMat3>) -> DVec3 {
+        let c = c * self.exposure.exp2();
+        let c = wb.map_or(c, |m| m * c);
+        let c = self.tone_map.map(c);
+        DVec3::new(linear_to_srgb(c.x), linear_to_srgb(c.y), linear_to_srgb(c.z))
+    }
+    // rgb bytes of a frame stored row by row
+    pub fn encode(&self, frame: &[DVec3], width: usize) -> Vec<u8> {
+        let wb = self.white_balance.map(white_balance);
+        frame.iter().enumerate().flat_map(|(i, c)| {
+            let v = self.apply_balanced(*c, wb);
+            let offset = match self.dither {
+                true => (BAYER[(i / width) % 8][(i % width) % 8] as f64 + 0.5) / 64.,
+                false => 0.5,
+            };
+            let q = |x: f64| (x * 255. + offset).floor().clamp(0., 255.) as u8;
+            [q(v.x), q(v.y), q(v.z)]
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::DVec3;
+
+    use crate::lib::srgb_to_linear;
+
+    use super::{linear_to_srgb, white_balance, blackbody_white, ToneMap, DisplayTransform};
+
+    #[test]
+    fn test_srgb_oetf() {
+        [0., 0.001, 0.18, 0.5, 1.].into_iter().for_each(|c| assert!((srgb_to_linear(linear_to_srgb(c)) - c).abs() < 1e-9));
+        // linear mid grey is well above the middle of the encoded range
+        assert!((linear_to_srgb(0.18) - 0.4613).abs() < 1e-3);
+        let bytes = DisplayTransform::default().encode(&[DVec3::new(0., 0.5, 2.)], 1);
+        assert_eq!(bytes, vec![0, 188, 255]);
+    }
+    #[test]
+    fn test_tone_maps() {
+        let all = [ToneMap::Clamp, ToneMap::Reinhard, ToneMap::ExtendedReinhard { white: 4. }, ToneMap::Hable, ToneMap::AcesFitted, ToneMap::AgX];
+        all.iter().for_each(|op| {
+            let curve: Vec<f64> = (0..200).map(|i| op.map(DVec3::splat(i as f64 * 0.05)).y).collect();
+            assert!(curve.windows(2).all(|w| w[1] >= w[0] - 1e-12), "{:?}", op);
+            assert!(curve[0] < 0.01 && curve.iter().all(|v| (0. ..=1.).contains(v)), "{:?}", op);
+            // greys stay grey
+            let g = op.map(DVec3::splat(0.3));
+            assert!((g.x - g.y).abs() < 1e-3 && (g.z - g.y).abs() < 1e-3, "{:?} {}", op, g);
+        });
+        assert_eq!(ToneMap::Reinhard.map(DVec3::ONE), DVec3::splat(0.5));
+        assert!((ToneMap::ExtendedReinhard { white: 4. }.map(DVec3::splat(4.)).x - 1.).abs() < 1e-12);
+        assert!((ToneMap::Hable.map(DVec3::splat(5.6)).x - 1.).abs() < 1e-12);
+        // highlights roll off instead of clipping
+        assert!(ToneMap::AcesFitted.map(DVec3::splat(2.)).x < ToneMap::AcesFitted.map(DVec3::splat(8.)).x);
+        assert!(ToneMap::AgX.map(DVec3::splat(2.)).x < ToneMap::AgX.map(DVec3::splat(8.)).x);
+    }
+    #[test]
+    fn test_exposure_and_white_balance() {
+        let stop_up = DisplayTransform { exposure: 1., ..DisplayTransform::default() };
+        assert_eq!(stop_up.apply(DVec3::splat(0.25)), DisplayTransform::default().apply(DVec3::splat(0.5)));
+        // tungsten light comes out neutral
+        let tungsten = crate::lib::xyz_to_srgb(blackbody_white(3000.));
+        assert!(tungsten.x > 1.5 * tungsten.z);
+        let balanced = white_balance(3000.) * tungsten;
+        assert!((balanced.x - balanced.y).abs() < 1e-3 && (balanced.z - balanced.y).abs() < 1e-3, "{}", balanced);
+    }
+    #[test]
+    fn test_dither() {
+        // a flat value between two codes is spread over both, averaging out right
+        let frame = vec![DVec3::splat(srgb_to_linear(100.25 / 255.)); 64];
+        let plain = DisplayTransform::default().encode(&frame, 8);
+        assert!(plain.iter().all(|b| *b == 100));
+        let dithered = DisplayTransform { dither: true, ..DisplayTransform::default() }.encode(&frame, 8);
+        assert!(dithered.iter().all(|b| *b == 100 || *b == 101));
+        let mean = dithered.iter().map(|b| *b as f64).sum::<f64>() / dithered.len() as f64;
+        assert!((mean - 100.25).abs() < 0.02, "{}", mean);
+    }
+}
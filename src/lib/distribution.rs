@@ -0,0 +1,103 @@
+use glam::DVec2;
+
+// piecewise constant 1d density over [0, 1) built from non-negative weights
+#[derive(Debug, Clone, PartialEq)]
+pub struct Distribution1D {
+    pub func: Vec<f64>,
+    pub cdf: Vec<f64>,
+    pub integral: f64,
+}
+impl Distribution1D {
+    pub fn new(func: Vec<f64>) -> Self {
+        let n = func.len();
+        let mut cdf = vec![0.; n + 1];
+        for i in 0..n {
+            cdf[i + 1] = cdf[i] + func[i].max(0.) / n as f64;
+        }
+        let integral = cdf[n];
+        match integral > 0. {
+            true => cdf.iter_mut().for_each(|c| *c /= integral),
+            // all zero: sample uniformly
+            false => cdf.iter_mut().enumerate().for_each(|(i, c)| *c = i as f64 / n as f64),
+        }
+        Self { func, cdf, integral }
+    }
+    pub fn count(&self) -> usize {
+        self.func.len()
+    }
+    // (x, pdf(x), bucket)
+    pub fn sample_continuous(&self, u: f64) -> (f64, f64, usize) {
+        let n = self.count();
+        let i = (self.cdf.partition_point(|&c| c <= u).max(1) - 1).min(n - 1);
+        let width = self.cdf[i + 1] - self.cdf[i];
+        let du = match width > 0. {
+            true => (u - self.cdf[i]) / width,
+            false => 0.,
+        };
+        let pdf = match self.integral > 0. {
+            true => self.func[i].max(0.) / self.integral,
+            false => 1.,
+        };
+        ((i as f64 + du) / n as f64, pdf, i)
+    }
+    pub fn pdf(&self, x: f64) -> f64 {
+        let i = ((x * self.count() as f64) as usize).min(self.count() - 1);
+        match self.integral > 0. {
+            true => self.func[i].max(0.) / self.integral,
+            false => 1.,
+        }
+    }
+}
+// 2d density on [0, 1)^2: a marginal over rows and one conditional per row.
+// func is row major with nu columns and nv rows
+#[derive(Debug, Clone, PartialEq)]
+pub struct Distribution2D {
+    pub conditional: Vec<Distribution1D>,
+    pub marginal: Distribution1D,
+}
+impl Distribution2D {
+    pub fn new(func: &[f64], nu: usize, nv: usize) -> Self {
+        let conditional: Vec<Distribution1D> = (0..nv).map(|v| Distribution1D::new(func[v * nu..(v + 1) * nu].to_vec())).collect();
+        let marginal = Distribution1D::new(conditional.iter().map(|c| c.integral).collect());
+        Self { conditional, marginal }
+    }
+    pub fn sample(&self, u: DVec2) -> (DVec2, f64) {
+        let (y, pdf_v, v) = self.marginal.sample_continuous(u.y);
+        let (x, pdf_u, _) = self.conditional[v].sample_continuous(u.x);
+        (DVec2::new(x, y), pdf_u * pdf_v)
+    }
+    pub fn pdf(&self, p: DVec2) -> f64 {
+        let v = ((p.y * self.marginal.count() as f64) as usize).min(self.marginal.count() - 1);
+        self.conditional[v].pdf(p.x) * self.marginal.pdf(p.y)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::DVec2;
+
+    use super::{Distribution1D, Distribution2D};
+
+    #[test]
+    fn test_distribution_1d() {
+        let d = Distribution1D::new(vec![1., 3.]);
+        assert_eq!(d.integral, 2.);
+        let (x, pdf, i) = d.sample_continuous(0.5);
+        assert_eq!(i, 1);
+        assert!((x - (0.5 + 1. / 6.)).abs() < 1e-12);
+        assert_eq!(pdf, 1.5);
+        assert_eq!(d.pdf(0.2), 0.5);
+        let (x, _, i) = Distribution1D::new(vec![0., 0.]).sample_continuous(0.75);
+        assert_eq!((x, i), (0.75, 1));
+    }
+    #[test]
+    fn test_distribution_2d() {
+        // all the weight in the bottom right cell
+        let d = Distribution2D::new(&[0., 0., 0., 4.], 2, 2);
+        let (p, pdf) = d.sample(DVec2::new(0.3, 0.9));
+        assert!(p.x >= 0.5 && p.y >= 0.5);
+        assert_eq!(pdf, 4.);
+        assert_eq!(d.pdf(p), pdf);
+        assert_eq!(d.pdf(DVec2::new(0.1, 0.1)), 0.);
+    }
+}
@@ -0,0 +1,209 @@
+use std::{f64::consts::PI, sync::Arc};
+use glam::{DVec2, DVec3};
+
+use super::{Image, WrapMode, Distribution2D, LightSource, LightSample, Sky, deg2rad};
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum EnvironmentKind {
+    // latitude / longitude layout, +y at the top row, -z in the middle column
+    Equirect(Arc<Image>),
+    // faces in +x, -x, +y, -y, +z, -z order, laid out as opengl cube maps
+    CubeMap([Arc<Image>; 6]),
+    // analytic daylight, oriented by its own sun azimuth so `rotation` is ignored
+    Sky(Box<Sky>),
+}
+// infinitely far away radiance surrounding the scene; seen by rays that miss
+// every object and sampled as a light proportionally to its luminance
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnvironmentMap {
+    pub kind: EnvironmentKind,
+    // degrees around +y
+    pub rotation: f64,
+    pub intensity: f64,
+    distribution: Distribution2D,
+}
+#[allow(dead_code)]
+impl EnvironmentMap {
+    pub fn new(kind: EnvironmentKind, rotation: f64, intensity: f64) -> Self {
+        // the map's own resolution over the lat-long square, and the table's
+        let (tu, tv) = match &kind {
+            EnvironmentKind::Equirect(img) => (img.width.max(1), img.height.max(1)),
+            EnvironmentKind::CubeMap(faces) => (faces[0].width.max(1) * 4, faces[0].width.max(1) * 2),
+            EnvironmentKind::Sky(_) => (256, 128),
+        };
+        let (nu, nv) = (tu.min(1024), tv.min(512));
+        // a table cell covering several texels averages all of them, a lookup at its
+        // centre alone would miss a bright feature smaller than the cell (the sun in an hdr)
+        let (su, sv) = (tu.div_ceil(nu), tv.div_ceil(nv));
+        let mut env = Self { kind, rotation, intensity, distribution: Distribution2D::new(&[1.], 1, 1) };
+        // tabulate luminance over the lat-long square, weighted by the solid angle of each cell
+        let func: Vec<f64> = (0..nv).flat_map(|v| (0..nu).map(move |u| (u, v))).map(|(u, v)| {
+            let sin_theta = (PI * (v as f64 + 0.5) / nv as f64).sin();
+            let covered = (0..sv).flat_map(|j| (0..su).map(move |i| (i, j))).map(|(i, j)| {
+                let uv = DVec2::new((u * su + i) as f64 + 0.5, (v * sv + j) as f64 + 0.5) / DVec2::new((nu * su) as f64, (nv * sv) as f64);
+                luminance(env.eval(env.uv_to_dir(uv)))
+            }).sum::<f64>();
+            covered / (su * sv) as f64 * sin_theta
+        }).collect();
+        env.distribution = Distribution2D::new(&func, nu, nv);
+        env
+    }
+    // rotation is applied here, so the maps themselves stay in local space
+    pub fn uv_to_dir(&self, uv: DVec2) -> DVec3 {
+        let theta = PI * uv.y;
+        let phi = 2. * PI * (uv.x - 0.5) + deg2rad(self.rotation);
+        DVec3::new(theta.sin() * phi.sin(), theta.cos(), -theta.sin() * phi.cos())
+    }
+    pub fn dir_to_uv(&self, dir: DVec3) -> DVec2 {
+        let d = dir.normalize();
+        let phi = d.x.atan2(-d.z) - deg2rad(self.rotation);
+        DVec2::new((phi / (2. * PI) + 0.5).rem_euclid(1.), d.y.clamp(-1., 1.).acos() / PI)
+    }
+    pub fn eval(&self, dir: DVec3) -> DVec3 {
+        let color = match &self.kind {
+            EnvironmentKind::Equirect(img) => {
+                let uv = self.dir_to_uv(dir);
+                img.bilinear(uv.x * img.width as f64, uv.y * img.height as f64, WrapMode::Repeat, WrapMode::Clamp)
+            },
+            EnvironmentKind::CubeMap(faces) => {
+                let (s, c) = deg2rad(self.rotation).sin_cos();
+                // undo the rotation around +y
+                let d = DVec3::new(c * dir.x + s * dir.z, dir.y, c * dir.z - s * dir.x);
+                let (face, sc, tc, ma) = cube_face(d);
+                let img = &faces[face];
+                let (u, v) = ((sc / ma + 1.) * 0.5, (tc / ma + 1.) * 0.5);
+                img.bilinear(u * img.width as f64, v * img.height as f64, WrapMode::Clamp, WrapMode::Clamp)
+            },
+            EnvironmentKind::Sky(sky) => sky.eval(dir),
+        };
+        color * self.intensity
+    }
+    // eval plus whatever is too small to be importance sampled from the table (the sun disc)
+    pub fn background(&self, dir: DVec3) -> DVec3 {
+        match &self.kind {
+            EnvironmentKind::Sky(sky) => sky.eval_with_sun(dir) * self.intensity,
+            _ => self.eval(dir),
+        }
+    }
+    pub fn pdf(&self, dir: DVec3) -> f64 {
+        let uv = self.dir_to_uv(dir);
+        let sin_theta = (PI * uv.y).sin();
+        match sin_theta > 0. {
+            true => self.distribution.pdf(uv) / (2. * PI * PI * sin_theta),
+            false => 0.,
+        }
+    }
+}
+impl LightSource for EnvironmentMap {
+    fn sample_li(&self, _p: DVec3, u: DVec2) -> Option<LightSample> {
+        let (uv, pdf_uv) = self.distribution.sample(u);
+        let sin_theta = (PI * uv.y).sin();
+        if pdf_uv <= 0. || sin_theta <= 0. {
+            return None;
+        }
+        let dir = self.uv_to_dir(uv);
+        let pdf = pdf_uv / (2. * PI * PI * sin_theta);
+        Some(LightSample { dir, distance: f64::INFINITY, radiance: self.eval(dir) / pdf })
+    }
+    fn is_delta(&self) -> bool {
+        false
+    }
+}
+impl<T: LightSource> LightSource for Arc<T> {
+    fn sample_li(&self, p: DVec3, u: DVec2) -> Option<LightSample> {
+        self.as_ref().sample_li(p, u)
+    }
+    fn is_delta(&self) -> bool {
+        self.as_ref().is_delta()
+    }
+}
+pub fn luminance(c: DVec3) -> f64 {
+    c.dot(DVec3::new(0.2126, 0.7152, 0.0722))
+}
+// (face, sc, tc, |major axis|)
+fn cube_face(d: DVec3) -> (usize, f64, f64, f64) {
+    let a = d.abs();
+    if a.x >= a.y && a.x >= a.z {
+        match d.x > 0. {
+            true => (0, -d.z, -d.y, a.x),
+            false => (1, d.z, -d.y, a.x),
+        }
+    } else if a.y >= a.z {
+        match d.y > 0. {
+            true => (2, d.x, d.z, a.y),
+            false => (3, d.x, -d.z, a.y),
+        }
+    } else {
+        match d.z > 0. {
+            true => (4, d.x, -d.y, a.z),
+            false => (5, -d.x, -d.y, a.z),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use glam::{DVec2, DVec3};
+
+    use crate::lib::{Image, LightSource, EnvironmentMap, EnvironmentKind};
+
+    fn hot_spot() -> Image {
+        // dim map with one bright texel just above the horizon, straight ahead (-z)
+        let (w, h) = (16, 8);
+        let mut data = vec![DVec3::splat(0.01); w * h];
+        data[3 * w + 8] = DVec3::splat(100.);
+        Image::new(w, h, data)
+    }
+    #[test]
+    fn test_uv_dir_roundtrip() {
+        let env = EnvironmentMap::new(EnvironmentKind::Equirect(Arc::new(hot_spot())), 30., 1.);
+        let uv = DVec2::new(0.3, 0.7);
+        assert!((env.dir_to_uv(env.uv_to_dir(uv)) - uv).length() < 1e-12);
+        assert!((env.uv_to_dir(DVec2::new(0.5, 0.5)) - DVec3::new(0.5, 0., -(3f64.sqrt()) / 2.)).length() < 1e-12);
+    }
+    #[test]
+    fn test_importance_sampling() {
+        let env = EnvironmentMap::new(EnvironmentKind::Equirect(Arc::new(hot_spot())), 0., 2.);
+        let hot = env.uv_to_dir(DVec2::new(8.5 / 16., 3.5 / 8.));
+        assert!((env.eval(hot) - DVec3::splat(200.)).length() < 1e-6);
+        // most samples land on the hot spot, and the estimator stays unbiased on a constant map
+        let ls = env.sample_li(DVec3::ZERO, DVec2::new(0.5, 0.5)).unwrap();
+        assert!(ls.dir.z < -0.9 && ls.dir.y > 0.);
+        let flat = EnvironmentMap::new(EnvironmentKind::Equirect(Arc::new(Image::new(4, 2, vec![DVec3::ONE; 8]))), 0., 1.);
+        let n = 32;
+        let mut sum = 0.;
+        for i in 0..n {
+            for j in 0..n {
+                let ls = flat.sample_li(DVec3::ZERO, DVec2::new((i as f64 + 0.5) / n as f64, (j as f64 + 0.5) / n as f64)).unwrap();
+                sum += ls.radiance.x * ls.dir.y.max(0.);
+            }
+        }
+        // irradiance from a uniform unit sky is pi
+        assert!((sum / (n * n) as f64 - std::f64::consts::PI).abs() < 0.05);
+    }
+    #[test]
+    fn test_importance_table_downsampling() {
+        // wider than the table, so each cell covers four texels and the hot one is off its centre
+        let (w, h) = (4096, 8);
+        let mut data = vec![DVec3::splat(0.01); w * h];
+        data[3 * w + 2048] = DVec3::splat(1e4);
+        let env = EnvironmentMap::new(EnvironmentKind::Equirect(Arc::new(Image::new(w, h, data))), 0., 1.);
+        let hot = env.uv_to_dir(DVec2::new(2048.5 / 4096., 3.5 / 8.));
+        let ls = env.sample_li(DVec3::ZERO, DVec2::new(0.5, 0.5)).unwrap();
+        assert!(ls.dir.dot(hot) > 0.999, "{}", ls.dir);
+        assert!(env.pdf(hot) > 100. * env.pdf(-hot));
+    }
+    #[test]
+    fn test_cube_map() {
+        let faces = [0, 1, 2, 3, 4, 5].map(|i| Arc::new(Image::new(1, 1, vec![DVec3::splat(i as f64)])));
+        let env = EnvironmentMap::new(EnvironmentKind::CubeMap(faces), 0., 1.);
+        assert_eq!(env.eval(DVec3::X), DVec3::splat(0.));
+        assert_eq!(env.eval(-DVec3::Y), DVec3::splat(3.));
+        assert_eq!(env.eval(-DVec3::Z), DVec3::splat(5.));
+        let turned = EnvironmentMap { rotation: 90., ..env };
+        // same convention as equirect maps: what was straight ahead turns to +x
+        assert_eq!(turned.eval(DVec3::X), DVec3::splat(5.));
+    }
+}
@@ -0,0 +1,245 @@
+use std::{env, fmt, fs, io, path::{Path, PathBuf}};
+use glam::DVec3;
+
+use super::{Image, ImageFormat, TextureError, write_image};
+
+// set to anything to have Golden::check write the goldens instead of
+// comparing against them, e.g. RS_RENDER_BLESS=1 cargo test golden
+pub const BLESS_ENV: &str = "RS_RENDER_BLESS";
+
+// how far a render may be from its golden image, measured on the display
+// encoded values in [0, 1]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Metric {
+    // largest difference of any channel of any pixel, at most
+    MaxAbs(f64),
+    // root mean square difference over all channels, at most
+    Rmse(f64),
+    // peak signal to noise ratio in dB, at least
+    Psnr(f64),
+    // structural similarity of the luminance, at least, 1 for identical images
+    Ssim(f64),
+}
+impl Metric {
+    pub fn measure(&self, a: &Image, b: &Image) -> f64 {
+        match self {
+            Metric::MaxAbs(_) => max_abs_error(a, b),
+            Metric::Rmse(_) => rmse(a, b),
+            Metric::Psnr(_) => psnr(a, b),
+            Metric::Ssim(_) => ssim(a, b),
+        }
+    }
+    pub fn passes(&self, value: f64) -> bool {
+        match *self {
+            Metric::MaxAbs(limit) | Metric::Rmse(limit) => value <= limit,
+            Metric::Psnr(limit) | Metric::Ssim(limit) => value >= limit,
+        }
+    }
+}
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Metric::MaxAbs(limit) => write!(f, "max abs error <= {}", limit),
+            Metric::Rmse(limit) => write!(f, "rmse <= {}", limit),
+            Metric::Psnr(limit) => write!(f, "psnr >= {} dB", limit),
+            Metric::Ssim(limit) => write!(f, "ssim >= {}", limit),
+        }
+    }
+}
+
+pub fn max_abs_error(a: &Image, b: &Image) -> f64 {
+    a.data.iter().zip(&b.data).map(|(x, y)| (*x - *y).abs().max_element()).fold(0., f64::max)
+}
+pub fn rmse(a: &Image, b: &Image) -> f64 {
+    let sum: f64 = a.data.iter().zip(&b.data).map(|(x, y)| (*x - *y).length_squared()).sum();
+    (sum / (3 * a.data.len().max(1)) as f64).sqrt()
+}
+// infinite for identical images
+pub fn psnr(a: &Image, b: &Image) -> f64 {
+    -20. * rmse(a, b).log10()
+}
+// mean ssim over 8x8 windows a half window apart, uniformly weighted. an
+// image smaller than a window is a single window
+pub fn ssim(a: &Image, b: &Image) -> f64 {
+    const WINDOW: usize = 8;
+    const C1: f64 = 0.01 * 0.01;
+    const C2: f64 = 0.03 * 0.03;
+    let luma = |img: &Image| -> Vec<f64> { img.data.iter().map(|c| c.dot(DVec3::new(0.2126, 0.7152, 0.0722))).collect() };
+    let (la, lb) = (luma(a), luma(b));
+    let starts = |len: usize| -> Vec<usize> { (0..=len.saturating_sub(WINDOW)).step_by(WINDOW / 2).collect() };
+    let (xs, ys) = (starts(a.width), starts(a.height));
+    let window = |x0: usize, y0: usize| {
+        let pixels: Vec<usize> = (y0..(y0 + WINDOW).min(a.height)).flat_map(|y| (x0..(x0 + WINDOW).min(a.width)).map(move |x| y * a.width + x)).collect();
+        let n = pixels.len() as f64;
+        let (ma, mb) = (pixels.iter().map(|&i| la[i]).sum::<f64>() / n, pixels.iter().map(|&i| lb[i]).sum::<f64>() / n);
+        let (mut va, mut vb, mut cov) = (0., 0., 0.);
+        pixels.iter().for_each(|&i| {
+            let (da, db) = (la[i] - ma, lb[i] - mb);
+            (va, vb, cov) = (va + da * da, vb + db * db, cov + da * db);
+        });
+        let (va, vb, cov) = (va / n, vb / n, cov / n);
+        (2. * ma * mb + C1) * (2. * cov + C2) / ((ma * ma + mb * mb + C1) * (va + vb + C2))
+    };
+    let total: f64 = ys.iter().flat_map(|&y| xs.iter().map(move |&x| (x, y))).map(|(x, y)| window(x, y)).sum();
+    total / (xs.len() * ys.len()) as f64
+}
+// the per channel differences, scaled so the largest is white
+pub fn diff_image(a: &Image, b: &Image) -> Image {
+    let scale = match max_abs_error(a, b) {
+        m if m > 0. => 1. / m,
+        _ => 1.,
+    };
+    Image::new(a.width, a.height, a.data.iter().zip(&b.data).map(|(x, y)| (*x - *y).abs() * scale).collect())
+}
+// rgb bytes, as render_to_memory returns them, to values in [0, 1]
+pub fn encoded_image(width: usize, height: usize, bytes: &[u8]) -> Image {
+    Image::new(width, height, bytes.chunks(3).map(|c| DVec3::new(c[0] as f64, c[1] as f64, c[2] as f64) / 255.).collect())
+}
+fn write_png(path: &Path, img: &Image) -> io::Result<()> {
+    let bytes: Vec<u8> = img.data.iter().flat_map(|c| c.to_array().map(|v| (v * 255.).round().clamp(0., 255.) as u8)).collect();
+    write_image(path, ImageFormat::Png, img.width as i32, img.height as i32, &bytes)
+}
+
+#[derive(Debug)]
+pub enum GoldenError {
+    Io(io::Error),
+    // there is no golden image yet, it has to be blessed
+    Missing(PathBuf),
+    Format(String),
+    // the golden's width and height and the render's
+    Size((usize, usize), (usize, usize)),
+    // the metrics that failed with what they measured, and where the
+    // render and its difference to the golden were written
+    Mismatch { failed: Vec<(Metric, f64)>, actual: PathBuf, diff: PathBuf },
+}
+impl fmt::Display for GoldenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GoldenError::Io(e) => write!(f, "io error: {}", e),
+            GoldenError::Missing(path) => write!(f, "no golden image {}, render it with {} set", path.display(), BLESS_ENV),
+            GoldenError::Format(s) => write!(f, "malformed golden image: {}", s),
+            GoldenError::Size(golden, actual) => write!(f, "golden image is {}x{}, the render {}x{}", golden.0, golden.1, actual.0, actual.1),
+            GoldenError::Mismatch { failed, actual, diff } => {
+                failed.iter().try_for_each(|(metric, value)| write!(f, "failed {}, measured {}; ", metric, value))?;
+                write!(f, "render written to {}, difference to {}", actual.display(), diff.display())
+            }
+        }
+    }
+}
+impl From<io::Error> for GoldenError {
+    fn from(e: io::Error) -> Self {
+        GoldenError::Io(e)
+    }
+}
+
+// compares renders against the png images in a directory
+#[derive(Debug, Clone, PartialEq)]
+pub struct Golden {
+    // holds <name>.png for every golden
+    pub dir: PathBuf,
+    // where a failed check writes <name>_actual.png and <name>_diff.png
+    pub failures: PathBuf,
+    // write the renders as the new goldens rather than comparing
+    pub bless: bool,
+    // all of them have to pass
+    pub metrics: Vec<Metric>,
+}
+impl Default for Golden {
+    // golden/ next to the manifest, blessing when BLESS_ENV is set. loose
+    // enough for floating point differences between platforms, tight enough
+    // to catch any visible change
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from("golden"),
+            failures: PathBuf::from("target/golden"),
+            bless: env::var_os(BLESS_ENV).is_some(),
+            metrics: vec![Metric::MaxAbs(0.25), Metric::Psnr(40.), Metric::Ssim(0.99)],
+        }
+    }
+}
+impl Golden {
+    pub fn check(&self, name: &str, actual: &Image) -> Result<(), GoldenError> {
+        let path = self.dir.join(name).with_extension("png");
+        if self.bless {
+            fs::create_dir_all(&self.dir)?;
+            return Ok(write_png(&path, actual)?);
+        }
+        let golden = match Image::load_linear(&path) {
+            Ok(golden) => golden,
+            Err(TextureError::Io(e)) if e.kind() == io::ErrorKind::NotFound => return Err(GoldenError::Missing(path)),
+            Err(TextureError::Io(e)) => return Err(GoldenError::Io(e)),
+            Err(e) => return Err(GoldenError::Format(e.to_string())),
+        };
+        if (golden.width, golden.height) != (actual.width, actual.height) {
+            return Err(GoldenError::Size((golden.width, golden.height), (actual.width, actual.height)));
+        }
+        let failed: Vec<(Metric, f64)> = self.metrics.iter()
+            .map(|m| (*m, m.measure(&golden, actual)))
+            .filter(|(m, value)| !m.passes(*value))
+            .collect();
+        if failed.is_empty() {
+            return Ok(());
+        }
+        fs::create_dir_all(&self.failures)?;
+        let (actual_path, diff) = (self.failures.join(format!("{}_actual.png", name)), self.failures.join(format!("{}_diff.png", name)));
+        write_png(&actual_path, actual)?;
+        write_png(&diff, &diff_image(&golden, actual))?;
+        Err(GoldenError::Mismatch { failed, actual: actual_path, diff })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::DVec3;
+
+    use crate::lib::{Image, BUILTIN_SCENES, builtin_scene, render_to_memory, TempPath};
+
+    use super::{Golden, GoldenError, Metric, max_abs_error, rmse, psnr, ssim, encoded_image};
+
+    fn gradient(offset: f64) -> Image {
+        Image::new(16, 12, (0..16 * 12).map(|i| DVec3::new((i % 16) as f64 / 16., (i / 16) as f64 / 12., 0.5) + offset).collect())
+    }
+    #[test]
+    fn test_metrics() {
+        let (a, b) = (gradient(0.), gradient(0.1));
+        assert_eq!((max_abs_error(&a, &a), rmse(&a, &a), psnr(&a, &a), ssim(&a, &a)), (0., 0., f64::INFINITY, 1.));
+        assert!((max_abs_error(&a, &b) - 0.1).abs() < 1e-12);
+        assert!((rmse(&a, &b) - 0.1).abs() < 1e-12);
+        assert!((psnr(&a, &b) - 20.).abs() < 1e-9);
+        // a brightness shift keeps the structure, noise does not
+        let noisy = Image::new(16, 12, a.data.iter().enumerate().map(|(i, c)| *c + (i % 2) as f64 * 0.2).collect());
+        assert!(ssim(&a, &b) > ssim(&a, &noisy));
+        assert!(ssim(&a, &noisy) < 0.9);
+        assert!(Metric::Psnr(30.).passes(40.) && !Metric::Psnr(30.).passes(20.));
+        assert!(Metric::MaxAbs(0.1).passes(0.1) && !Metric::Rmse(0.1).passes(0.2));
+    }
+    #[test]
+    fn test_check() {
+        let dir = TempPath::new("golden_check");
+        let golden = Golden { dir: dir.clone(), failures: dir.join("failures"), bless: false, metrics: vec![Metric::MaxAbs(0.05)] };
+        let img = encoded_image(16, 12, &gradient(0.).data.iter().flat_map(|c| c.to_array().map(|v| (v * 255.).round() as u8)).collect::<Vec<_>>());
+        assert!(matches!(golden.check("gradient", &img), Err(GoldenError::Missing(_))));
+        Golden { bless: true, ..golden.clone() }.check("gradient", &img).unwrap();
+        // the golden is stored losslessly
+        golden.check("gradient", &img).unwrap();
+        let shifted = Image::new(16, 12, img.data.iter().map(|c| *c * 0.8).collect());
+        let result = golden.check("gradient", &shifted);
+        let written = ["gradient_actual.png", "gradient_diff.png"].map(|f| dir.join("failures").join(f).exists());
+        let small = golden.check("gradient", &Image::new(1, 1, vec![DVec3::ZERO]));
+        assert!(matches!(result, Err(GoldenError::Mismatch { ref failed, .. }) if failed.len() == 1), "{:?}", result);
+        assert_eq!(written, [true, true]);
+        assert!(matches!(small, Err(GoldenError::Size((16, 12), (1, 1)))));
+    }
+    #[test]
+    fn test_golden_scenes() {
+        let golden = Golden::default();
+        BUILTIN_SCENES.iter().for_each(|(name, _, _)| {
+            let mut sc = builtin_scene(name).unwrap();
+            (sc.width, sc.height) = (80, 60);
+            let img = encoded_image(80, 60, &render_to_memory(&mut sc).unwrap());
+            if let Err(e) = golden.check(name, &img) {
+                panic!("{}: {}", name, e);
+            }
+        });
+    }
+}
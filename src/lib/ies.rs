@@ -0,0 +1,190 @@
+use std::{fmt, fs, io, path::Path};
+use glam::DVec3;
+
+use super::coordinate_system;
+
+#[derive(Debug)]
+pub enum IesError {
+    Io(io::Error),
+    Format(String),
+    Unsupported(String),
+}
+impl fmt::Display for IesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IesError::Io(e) => write!(f, "io error: {}", e),
+            IesError::Format(s) => write!(f, "malformed ies file: {}", s),
+            IesError::Unsupported(s) => write!(f, "unsupported ies file: {}", s),
+        }
+    }
+}
+impl From<io::Error> for IesError {
+    fn from(e: io::Error) -> Self {
+        IesError::Io(e)
+    }
+}
+
+// type c luminous intensity distribution (candela) from an lm-63 file.
+// vertical angles are measured from the nadir, horizontal angles around it
+#[derive(Debug, Clone, PartialEq)]
+pub struct IesProfile {
+    pub vertical: Vec<f64>,
+    pub horizontal: Vec<f64>,
+    // one row of vertical samples per horizontal angle, multiplier applied
+    pub candela: Vec<Vec<f64>>,
+}
+#[allow(dead_code)]
+impl IesProfile {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, IesError> {
+        // lm-63 files are nominally ascii, be lenient about stray bytes in keywords
+        IesProfile::parse(&String::from_utf8_lossy(&fs::read(path)?))
+    }
+    pub fn parse(text: &str) -> Result<Self, IesError> {
+        let mut lines = text.lines();
+        // keyword block up to and including the TILT= line
+        let tilt = loop {
+            match lines.next() {
+                Some(l) if l.trim_start().starts_with("TILT=") => break l.trim_start()[5..].trim().to_string(),
+                Some(_) => continue,
+                None => return Err(IesError::Format("missing TILT line".to_string())),
+            }
+        };
+        let rest: Vec<&str> = lines.collect();
+        let mut nums = rest.iter().flat_map(|l| l.split(|c: char| c.is_whitespace() || c == ',')).filter(|t| !t.is_empty()).map(|t| {
+            t.parse::<f64>().map_err(|_| IesError::Format(format!("bad number {}", t)))
+        });
+        let mut next = || nums.next().unwrap_or_else(|| Err(IesError::Format("unexpected end of data".to_string())));
+        match tilt.as_str() {
+            "NONE" => {},
+            "INCLUDE" => {
+                // lamp to luminaire geometry, then angle / factor pairs we do not use
+                let _geometry = next()?;
+                let n = next()? as usize;
+                for _ in 0..2 * n {
+                    next()?;
+                }
+            },
+            t => return Err(IesError::Unsupported(format!("external tilt file {}", t))),
+        }
+        let _lamps = next()?;
+        let _lumens = next()?;
+        let multiplier = next()?;
+        let nv = next()? as usize;
+        let nh = next()? as usize;
+        let photometric_type = next()? as i32;
+        if photometric_type != 1 {
+            return Err(IesError::Unsupported(format!("photometric type {}", photometric_type)));
+        }
+        // units, width, length, height, ballast factor, future use, input watts
+        for _ in 0..7 {
+            next()?;
+        }
+        if nv == 0 || nh == 0 {
+            return Err(IesError::Format("empty angle table".to_string()));
+        }
+        let vertical = (0..nv).map(|_| next()).collect::<Result<Vec<f64>, IesError>>()?;
+        let horizontal = (0..nh).map(|_| next()).collect::<Result<Vec<f64>, IesError>>()?;
+        let candela = (0..nh).map(|_| (0..nv).map(|_| next().map(|c| c * multiplier)).collect()).collect::<Result<Vec<Vec<f64>>, IesError>>()?;
+        Ok(IesProfile { vertical, horizontal, candela })
+    }
+    // intensity towards the unit direction w leaving a fixture aimed with its
+    // nadir along `down`, the c = 0 plane is the tangent coordinate_system picks
+    pub fn towards(&self, w: DVec3, down: DVec3) -> f64 {
+        let (c0, c90) = coordinate_system(down);
+        let vertical = w.dot(down).clamp(-1., 1.).acos().to_degrees();
+        let horizontal = w.dot(c90).atan2(w.dot(c0)).to_degrees();
+        self.eval(vertical, horizontal)
+    }
+    pub fn max_candela(&self) -> f64 {
+        self.candela.iter().flatten().fold(0., |m, &c| f64::max(m, c))
+    }
+    // intensity towards (vertical, horizontal) degrees, folding by the symmetry the table implies
+    pub fn eval(&self, vertical: f64, horizontal: f64) -> f64 {
+        let h = horizontal.rem_euclid(360.);
+        let last = *self.horizontal.last().unwrap();
+        let h = match last {
+            l if l <= 0. => 0.,
+            l if l <= 90. => {
+                let q = h % 180.;
+                let q = if q > 90. { 180. - q } else { q };
+                q.min(l)
+            },
+            l if l <= 180. => (if h > 180. { 360. - h } else { h }).min(l),
+            _ => h,
+        };
+        let (i, t) = bracket(&self.horizontal, h);
+        let row = |k: usize| {
+            let (j, s) = bracket(&self.vertical, vertical);
+            let r = &self.candela[k];
+            r[j] + (r[(j + 1).min(r.len() - 1)] - r[j]) * s
+        };
+        match self.horizontal.len() {
+            1 => row(0),
+            _ => row(i) + (row((i + 1).min(self.horizontal.len() - 1)) - row(i)) * t,
+        }
+    }
+}
+// index of the interval holding x and the fraction into it, clamped at the ends
+fn bracket(xs: &[f64], x: f64) -> (usize, f64) {
+    if xs.len() < 2 || x <= xs[0] {
+        return (0, 0.);
+    }
+    if x >= xs[xs.len() - 1] {
+        return (xs.len() - 1, 0.);
+    }
+    let i = xs.partition_point(|&v| v <= x) - 1;
+    (i, (x - xs[i]) / (xs[i + 1] - xs[i]))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use glam::{DVec2, DVec3};
+
+    use crate::lib::{IesProfile, Light, SpotLight, LightSource};
+
+    // downlight, rotationally symmetric, 1000 cd at nadir falling to nothing at 90 degrees
+    const DOWNLIGHT: &str = "IESNA:LM-63-2002
+[TEST] unit test
+[MANUFAC] none
+TILT=NONE
+1 1000 2 3 1 1 2 0.1 0.1 0
+1.0 1.0 20
+0 45 90
+0
+500 250 0
+";
+    #[test]
+    fn test_parse() {
+        let p = IesProfile::parse(DOWNLIGHT).unwrap();
+        assert_eq!(p.vertical, vec![0., 45., 90.]);
+        assert_eq!(p.candela, vec![vec![1000., 500., 0.]]);
+        assert_eq!(p.max_candela(), 1000.);
+        assert_eq!(p.eval(22.5, 123.), 750.);
+        assert_eq!(p.eval(120., 0.), 0.);
+        assert!(IesProfile::parse("TILT=NONE\n1 1000 1 3 1 2 2").is_err());
+    }
+    #[test]
+    fn test_quadrant_symmetry() {
+        let text = "IESNA91\nTILT=INCLUDE\n1\n2\n0 90\n1 1\n1 -1 1 2 2 1 1 0 0 0\n1 1 0\n0 90\n0 90\n100 100\n200 200\n";
+        let p = IesProfile::parse(text).unwrap();
+        assert_eq!(p.eval(10., 45.), 150.);
+        // 0-90 tables mirror into the other three quadrants
+        assert_eq!(p.eval(10., 135.), 150.);
+        assert_eq!(p.eval(10., 270.), 200.);
+        assert_eq!(p.eval(10., 180.), 100.);
+    }
+    #[test]
+    fn test_profiled_lights() {
+        let profile = Some(Arc::new(IesProfile::parse(DOWNLIGHT).unwrap()));
+        let li = Light { org: DVec3::new(0., 2., 0.), inten: DVec3::splat(0.01), ies: profile.clone() };
+        let below = li.sample_li(DVec3::ZERO, DVec2::ZERO).unwrap();
+        assert!((below.radiance - DVec3::splat(10. / 4.)).length() < 1e-12);
+        // level with the fixture the profile is dark
+        assert!(li.sample_li(DVec3::new(3., 2., 0.), DVec2::ZERO).is_none());
+        // a spot aims its profile, 45 degrees off its axis gets half
+        let spot = SpotLight { org: DVec3::new(0., 0., 2.), dir: -DVec3::Z, inten: DVec3::splat(0.01), inner: 60., outer: 80., falloff: 1., ies: profile };
+        let off_axis = spot.sample_li(DVec3::new(2., 0., 0.), DVec2::ZERO).unwrap();
+        assert!((off_axis.radiance - DVec3::splat(5. / 8.)).length() < 1e-12, "{}", off_axis.radiance);
+    }
+}
@@ -0,0 +1,114 @@
+use std::sync::Arc;
+use glam::DVec3;
+
+use super::Medium;
+
+// what fills a closed dielectric: its refractive index, the beer-lambert absorption
+// coefficient per unit length, and a priority deciding which interior owns the
+// space where two objects overlap (ice in water in a glass). a medium makes it
+// scatter as well, murky water or the smoke inside a MediumBoundary
+#[derive(Debug, Clone, PartialEq)]
+pub struct Interior {
+    pub ior: f64,
+    pub absorption: DVec3,
+    pub priority: i32,
+    pub medium: Option<Arc<Medium>>,
+}
+impl Interior {
+    pub fn transmittance(&self, distance: f64) -> DVec3 {
+        // written out so that no absorption over an infinite distance stays 1
+        let tr = |sigma: f64| match sigma > 0. {
+            true => (-sigma * distance).exp(),
+            false => 1.,
+        };
+        DVec3::new(tr(self.absorption.x), tr(self.absorption.y), tr(self.absorption.z))
+    }
+}
+// the interiors a ray is currently inside, tagged with the object they belong to.
+// the one with the highest priority, latest entered on ties, is where the ray travels
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MediumStack {
+    entries: Vec<(usize, Interior)>,
+}
+impl MediumStack {
+    pub fn current(&self) -> Option<(usize, Interior)> {
+        self.entries.iter().cloned().max_by_key(|(_, i)| i.priority)
+    }
+    // vacuum outside of everything
+    pub fn ior(&self) -> f64 {
+        self.current().map_or(1., |(_, i)| i.ior)
+    }
+    pub fn transmittance(&self, distance: f64) -> DVec3 {
+        self.current().map_or(DVec3::ONE, |(_, i)| i.transmittance(distance))
+    }
+    pub fn entered(&self, id: usize, interior: Interior) -> Self {
+        let mut entries = self.entries.clone();
+        entries.push((id, interior));
+        Self { entries }
+    }
+    pub fn left(&self, id: usize) -> Self {
+        let mut entries = self.entries.clone();
+        if let Some(k) = entries.iter().rposition(|(j, _)| *j == id) {
+            entries.remove(k);
+        }
+        Self { entries }
+    }
+    // surfaces of an interior that does not own the space on either side are not
+    // interfaces at all: entering a lower priority object, or leaving one while a
+    // higher priority interior is still around
+    pub fn is_false_hit(&self, id: usize, interior: &Interior, entering: bool) -> bool {
+        match entering {
+            true => self.current().is_some_and(|(_, c)| c.priority > interior.priority),
+            false => self.entries.iter().any(|(j, _)| *j == id) && self.current().is_some_and(|(j, _)| j != id),
+        }
+    }
+    // index on the far side of the interface with object `id`
+    pub fn outside_ior(&self, id: usize, entering: bool) -> f64 {
+        match entering {
+            true => self.ior(),
+            false => self.left(id).ior(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::DVec3;
+
+    use super::{Interior, MediumStack};
+
+    #[test]
+    fn test_transmittance() {
+        let tinted = Interior { ior: 1.5, absorption: DVec3::new(0., 0.5, 1.), priority: 0, medium: None };
+        let t = tinted.transmittance(2.);
+        assert_eq!(t.x, 1.);
+        assert!((t.y - (-1f64).exp()).abs() < 1e-12);
+        assert!(t.z < t.y);
+        assert_eq!(tinted.transmittance(f64::INFINITY), DVec3::new(1., 0., 0.));
+        assert_eq!(MediumStack::default().transmittance(10.), DVec3::ONE);
+    }
+    #[test]
+    fn test_nested() {
+        let glass = Interior { ior: 1.5, absorption: DVec3::ZERO, priority: 2, medium: None };
+        let water = Interior { ior: 1.33, absorption: DVec3::ZERO, priority: 1, medium: None };
+        let ice = Interior { ior: 1.31, absorption: DVec3::ZERO, priority: 3, medium: None };
+        let air = MediumStack::default();
+        assert_eq!(air.outside_ior(0, true), 1.);
+        let in_glass = air.entered(0, glass.clone());
+        // the water surface overlapping the glass wall is not there
+        assert!(in_glass.is_false_hit(1, &water, true));
+        let in_both = in_glass.entered(1, water.clone());
+        assert_eq!(in_both.ior(), 1.5);
+        // the inner glass wall borders on water
+        assert!(!in_both.is_false_hit(0, &glass, false));
+        assert_eq!(in_both.outside_ior(0, false), 1.33);
+        let in_water = in_both.left(0);
+        assert_eq!(in_water.ior(), 1.33);
+        // ice floating in the water
+        assert!(!in_water.is_false_hit(2, &ice, true));
+        assert_eq!(in_water.outside_ior(2, true), 1.33);
+        assert_eq!(in_water.entered(2, ice).outside_ior(2, false), 1.33);
+        // leaving water that is still inside the glass wall
+        assert!(in_both.is_false_hit(1, &water, false));
+    }
+}
@@ -1,65 +1,188 @@
-use std::f64::INFINITY;
+use std::sync::Arc;
+use glam::{DVec2, DVec3};
 
-use glam::DVec3;
+use super::{IesProfile, coordinate_system, deg2rad};
 
-#[allow(unused)]
+// a light as seen from a shading point: unit direction towards it, distance
+// for the shadow ray and the incident radiance already divided by the pdf
 #[derive(Debug, Clone, PartialEq, Copy)]
+pub struct LightSample {
+    pub dir: DVec3,
+    pub distance: f64,
+    pub radiance: DVec3,
+}
+pub trait LightSource: Send + Sync {
+    // u is a uniform 2d sample, ignored by lights that have a single direction
+    fn sample_li(&self, p: DVec3, u: DVec2) -> Option<LightSample>;
+    // delta lights are sampled with a single shadow ray, the rest with Scene::shadow_samples
+    fn is_delta(&self) -> bool {
+        true
+    }
+}
+// also used as the ray type; as a light it is a point source with radiant
+// intensity `inten` (W/sr) and inverse square falloff, isotropic unless a
+// measured profile hangs it pointing down -y, candela then scale `inten`
+#[allow(unused)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Light {
     pub org: DVec3,
     pub inten: DVec3,
+    pub ies: Option<Arc<IesProfile>>,
 }
 #[allow(unused)]
 impl Light {
     pub fn new(org: DVec3, inten: DVec3) -> Light {
-        Light { org, inten }
+        Light { org, inten, ies: None }
     }
     pub fn create() -> Light {
-        Light { org: DVec3::new(0., 0., 0.), inten: DVec3::new(0., 0., 0.) }
+        Light { org: DVec3::new(0., 0., 0.), inten: DVec3::new(0., 0., 0.), ies: None }
     }
     pub fn intersection(&self, v0: DVec3, v1: DVec3, v2: DVec3, dir: DVec3) -> (bool, f64, f64, f64) {
-        // o + t*d = (1-u-v)*v0+u*v1+v*v2
+        // o + t*d = (1-u-v)*v0+u*v1+v*v2, moller-trumbore; both faces are hit
         let od1 = v1 - v0;let od2 = v2 - v0;
-        match dir.dot(od2) {
-            det if det < 0. => (false, INFINITY, 0., det),
+        let s1 = dir.cross(od2);
+        match s1.dot(od1) {
+            det if det.abs() < 1e-12 => (false, f64::INFINITY, 0., 0.),
             det => {
+                let base = 1. / det;
                 let s = self.org - v0;
-                let s1 = dir.cross(od2);
-                match s1.dot(s) {
-                    b1d if b1d < 0. => (false, INFINITY, b1d, det),
-                    b1d => {
-                        let s2 = s.cross(od1);
-                        match s2.dot(od2) {
-                            td if td < 0. => (false, INFINITY, b1d, det),
-                            td => {
-                                let base = 1./s1.dot(od1);
-                                let t = td * base;
-                                let b1 = b1d * base;
-                                let b2 = det * base;
-                                match 1. - b1 - b2 {
-                                    el if el < 0. => (false, t, b1, b2),
-                                    _ => (true, t, b1, b2),
-                                }
-                            }
-                        }
-                    }
-                }
+                let s2 = s.cross(od1);
+                let t = s2.dot(od2) * base;
+                let b1 = s1.dot(s) * base;
+                let b2 = s2.dot(dir) * base;
+                (t > 0. && b1 >= 0. && b2 >= 0. && b1 + b2 <= 1., t, b1, b2)
             }
         }
     }
 }
+impl LightSource for Light {
+    fn sample_li(&self, p: DVec3, _u: DVec2) -> Option<LightSample> {
+        let d = self.org - p;
+        let d2 = d.length_squared();
+        if d2 <= 0. {
+            return None;
+        }
+        let dir = d / d2.sqrt();
+        let k = self.ies.as_ref().map_or(1., |ies| ies.towards(-dir, -DVec3::Y));
+        match k > 0. {
+            true => Some(LightSample { dir, distance: d2.sqrt(), radiance: self.inten * k / d2 }),
+            false => None,
+        }
+    }
+}
+// sun-like light from infinitely far away, `dir` is the direction the light travels;
+// a non-zero angular diameter (degrees) spreads it over a cone for soft shadows
+#[allow(unused)]
+#[derive(Debug, Clone, PartialEq, Copy)]
+pub struct DirectionalLight {
+    pub dir: DVec3,
+    pub irradiance: DVec3,
+    pub angular_diameter: f64,
+}
+impl LightSource for DirectionalLight {
+    fn sample_li(&self, _p: DVec3, u: DVec2) -> Option<LightSample> {
+        let w = -self.dir.normalize();
+        let dir = match self.angular_diameter > 0. {
+            true => sample_cone(w, deg2rad(self.angular_diameter * 0.5).cos(), u),
+            false => w,
+        };
+        Some(LightSample { dir, distance: f64::INFINITY, radiance: self.irradiance })
+    }
+    fn is_delta(&self) -> bool {
+        self.angular_diameter <= 0.
+    }
+}
+// point light restricted to a cone around `dir`; full intensity inside `inner`,
+// fading to zero at `outer` (degrees, half angles), `falloff` shapes the fade.
+// a profile is aimed with its nadir along `dir`, within the cone
+#[allow(unused)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpotLight {
+    pub org: DVec3,
+    pub dir: DVec3,
+    pub inten: DVec3,
+    pub inner: f64,
+    pub outer: f64,
+    pub falloff: f64,
+    pub ies: Option<Arc<IesProfile>>,
+}
+impl SpotLight {
+    pub fn cone_factor(&self, cos_theta: f64) -> f64 {
+        let (cos_inner, cos_outer) = (deg2rad(self.inner).cos(), deg2rad(self.outer).cos());
+        if cos_theta >= cos_inner {
+            return 1.;
+        }
+        if cos_theta <= cos_outer {
+            return 0.;
+        }
+        let t = (cos_theta - cos_outer) / (cos_inner - cos_outer);
+        (t * t * (3. - 2. * t)).powf(self.falloff)
+    }
+}
+impl LightSource for SpotLight {
+    fn sample_li(&self, p: DVec3, u: DVec2) -> Option<LightSample> {
+        let ls = Light { org: self.org, inten: self.inten, ies: None }.sample_li(p, u)?;
+        let k = self.cone_factor((-ls.dir).dot(self.dir.normalize()));
+        let k = k * self.ies.as_ref().map_or(1., |ies| ies.towards(-ls.dir, self.dir.normalize()));
+        match k > 0. {
+            true => Some(LightSample { radiance: ls.radiance * k, ..ls }),
+            false => None,
+        }
+    }
+}
+// uniform direction inside the cone around w with cos half angle cos_max
+pub fn sample_cone(w: DVec3, cos_max: f64, u: DVec2) -> DVec3 {
+    let cos_theta = 1. - u.x * (1. - cos_max);
+    let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+    let phi = 2. * std::f64::consts::PI * u.y;
+    let (t, b) = coordinate_system(w);
+    (t * phi.cos() * sin_theta + b * phi.sin() * sin_theta + w * cos_theta).normalize()
+}
 #[cfg(test)]
 mod tests {
-    use glam::DVec3;
+    use glam::{DVec2, DVec3};
 
-    use super::Light;
+    use super::{Light, LightSource, DirectionalLight, SpotLight};
 
     #[test]
     fn test_intersection() {
-        let li = Light { org:DVec3::new(0.25, 0.14, 1.1), inten: DVec3::new(0.44, 0.44, -0.02)};
+        let li = Light { org:DVec3::new(0.25, 0.14, 1.1), inten: DVec3::new(0.44, 0.44, -0.02), ies: None };
         let (res, t, b1, b2) = li.intersection(DVec3::new(-1.6, -1.5, 6.2), DVec3::new(2.1, 6.4, -4.4), DVec3::new(14.4, 13.2, -2.4), DVec3::new(0.44, 0.44, -0.02));
-        assert_eq!(res, true);
+        assert!(res);
         assert_eq!(t, 14.811501379424634);
         assert_eq!(b1, 0.10439076224178441);
-        assert_eq!(b2, 0.666803146842921);
+        assert_eq!(b2, 0.4988009241657648);
+        // the plane of the triangle behind the ray is not a hit
+        let (res, ..) = li.intersection(DVec3::new(-1.6, -1.5, 6.2), DVec3::new(2.1, 6.4, -4.4), DVec3::new(14.4, 13.2, -2.4), DVec3::new(-0.44, -0.44, 0.02));
+        assert!(!res);
+    }
+    #[test]
+    fn test_inverse_square() {
+        let li = Light { org: DVec3::new(0., 4., 0.), inten: DVec3::splat(16.), ies: None };
+        let near = li.sample_li(DVec3::new(0., 2., 0.), DVec2::ZERO).unwrap();
+        let far = li.sample_li(DVec3::ZERO, DVec2::ZERO).unwrap();
+        assert_eq!(near.radiance, DVec3::splat(4.));
+        assert_eq!(far.radiance, DVec3::ONE);
+        assert_eq!(far.dir, DVec3::Y);
+        assert_eq!(far.distance, 4.);
+    }
+    #[test]
+    fn test_directional() {
+        let sun = DirectionalLight { dir: DVec3::new(0., -1., 0.), irradiance: DVec3::ONE, angular_diameter: 0. };
+        let ls = sun.sample_li(DVec3::new(5., 0., 3.), DVec2::new(0.3, 0.7)).unwrap();
+        assert_eq!(ls.dir, DVec3::Y);
+        assert!(ls.distance.is_infinite());
+        let disc = DirectionalLight { angular_diameter: 10., ..sun };
+        let ls = disc.sample_li(DVec3::ZERO, DVec2::new(0.9, 0.2)).unwrap();
+        assert!(ls.dir.dot(DVec3::Y) >= 5f64.to_radians().cos() - 1e-12);
+        assert!(!disc.is_delta());
+    }
+    #[test]
+    fn test_spot_cone() {
+        let spot = SpotLight { org: DVec3::new(0., 2., 0.), dir: DVec3::new(0., -1., 0.), inten: DVec3::splat(4.), inner: 20., outer: 30., falloff: 1., ies: None };
+        assert_eq!(spot.sample_li(DVec3::ZERO, DVec2::ZERO).unwrap().radiance, DVec3::ONE);
+        assert!(spot.sample_li(DVec3::new(2., 0., 0.), DVec2::ZERO).is_none());
+        let k = spot.cone_factor(25f64.to_radians().cos());
+        assert!(k > 0. && k < 1.);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,349 @@
+use std::{f64::consts::PI, fmt, fs, io, path::Path, sync::Arc};
+use glam::{DVec2, DVec3};
+
+use super::{Bsdf, BsdfSample, ShadingPoint, Interior, coordinate_system};
+
+#[derive(Debug)]
+pub enum VolumeError {
+    Io(io::Error),
+    Format(String),
+}
+impl fmt::Display for VolumeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VolumeError::Io(e) => write!(f, "io error: {}", e),
+            VolumeError::Format(s) => write!(f, "malformed voxel file: {}", s),
+        }
+    }
+}
+impl From<io::Error> for VolumeError {
+    fn from(e: io::Error) -> Self {
+        VolumeError::Io(e)
+    }
+}
+
+// phase function of a medium, g > 0 scatters forward, g < 0 backward, 0 is isotropic.
+// like bsdfs, wo points back towards the viewer and wi towards the light
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HenyeyGreenstein {
+    pub g: f64,
+}
+#[allow(dead_code)]
+impl HenyeyGreenstein {
+    fn density(&self, cos_theta: f64) -> f64 {
+        let g = self.g;
+        let denom = 1. + g * g - 2. * g * cos_theta;
+        (1. - g * g) / (4. * PI * denom * denom.sqrt())
+    }
+    pub fn eval(&self, wo: DVec3, wi: DVec3) -> f64 {
+        // angle between the direction light travels in and the one it leaves in
+        self.density(-wo.dot(wi))
+    }
+    // wi distributed exactly like eval, returned with its density
+    pub fn sample(&self, wo: DVec3, u: DVec2) -> (DVec3, f64) {
+        let g = self.g;
+        let cos_theta = match g.abs() < 1e-3 {
+            true => 1. - 2. * u.x,
+            false => {
+                let s = (1. - g * g) / (1. - g + 2. * g * u.x);
+                ((1. + g * g - s * s) / (2. * g)).clamp(-1., 1.)
+            }
+        };
+        let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+        let phi = 2. * PI * u.y;
+        // sampled around the direction the light leaves in
+        let axis = wo;
+        let (t, b) = coordinate_system(axis);
+        let wi = -(axis * cos_theta + (t * phi.cos() + b * phi.sin()) * sin_theta);
+        (wi, self.density(cos_theta))
+    }
+}
+// densities on a regular nx * ny * nz lattice, voxel centres at (i + 0.5) / nx etc.
+// of the unit cube, x varying fastest
+#[derive(Debug, Clone, PartialEq)]
+pub struct VoxelGrid {
+    pub nx: usize,
+    pub ny: usize,
+    pub nz: usize,
+    pub data: Vec<f64>,
+    // largest density, the majorant the trackers step with
+    max: f64,
+}
+#[allow(dead_code)]
+impl VoxelGrid {
+    pub fn new(nx: usize, ny: usize, nz: usize, data: Vec<f64>) -> Self {
+        assert_eq!(nx * ny * nz, data.len());
+        let max = data.iter().copied().fold(0., f64::max);
+        Self { nx, ny, nz, data, max }
+    }
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, VolumeError> {
+        VoxelGrid::parse(&fs::read_to_string(path)?)
+    }
+    // plain text: the resolution `nx ny nz`, then nx * ny * nz non negative densities,
+    // separated by any whitespace. everything after a # on a line is a comment
+    pub fn parse(text: &str) -> Result<Self, VolumeError> {
+        let mut tokens = text.lines().flat_map(|l| l.split('#').next().unwrap_or("").split_whitespace());
+        let mut size = || -> Result<usize, VolumeError> {
+            let t = tokens.next().ok_or_else(|| VolumeError::Format("missing resolution".to_string()))?;
+            match t.parse::<usize>() {
+                Ok(n) if n > 0 => Ok(n),
+                _ => Err(VolumeError::Format(format!("bad resolution {}", t))),
+            }
+        };
+        let (nx, ny, nz) = (size()?, size()?, size()?);
+        let data = tokens.map(|t| match t.parse::<f64>() {
+            Ok(d) if d >= 0. && d.is_finite() => Ok(d),
+            _ => Err(VolumeError::Format(format!("bad density {}", t))),
+        }).collect::<Result<Vec<f64>, VolumeError>>()?;
+        if data.len() != nx * ny * nz {
+            return Err(VolumeError::Format(format!("expected {} densities, found {}", nx * ny * nz, data.len())));
+        }
+        Ok(VoxelGrid::new(nx, ny, nz, data))
+    }
+    pub fn max(&self) -> f64 {
+        self.max
+    }
+    fn voxel(&self, x: i64, y: i64, z: i64) -> f64 {
+        // clamp to the border voxels, the box itself cuts the volume off
+        let x = x.clamp(0, self.nx as i64 - 1) as usize;
+        let y = y.clamp(0, self.ny as i64 - 1) as usize;
+        let z = z.clamp(0, self.nz as i64 - 1) as usize;
+        self.data[(z * self.ny + y) * self.nx + x]
+    }
+    // trilinear lookup at a point of the unit cube, zero outside of it
+    pub fn density(&self, p: DVec3) -> f64 {
+        if p.min_element() < 0. || p.max_element() > 1. {
+            return 0.;
+        }
+        let g = p * DVec3::new(self.nx as f64, self.ny as f64, self.nz as f64) - 0.5;
+        let g0 = g.floor();
+        let f = g - g0;
+        let (x, y, z) = (g0.x as i64, g0.y as i64, g0.z as i64);
+        let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+        let plane = |z: i64| lerp(
+            lerp(self.voxel(x, y, z), self.voxel(x + 1, y, z), f.x),
+            lerp(self.voxel(x, y + 1, z), self.voxel(x + 1, y + 1, z), f.x),
+            f.y,
+        );
+        lerp(plane(z), plane(z + 1), f.z)
+    }
+}
+#[derive(Debug, Clone, PartialEq)]
+pub enum Density {
+    Homogeneous,
+    // a voxel grid stretched over the world space box min..max
+    Grid { grid: Arc<VoxelGrid>, min: DVec3, max: DVec3 },
+}
+// absorbing and scattering matter per unit length, scaled by the density where
+// it is not homogeneous
+#[derive(Debug, Clone, PartialEq)]
+pub struct Medium {
+    pub sigma_a: DVec3,
+    pub sigma_s: DVec3,
+    pub phase: HenyeyGreenstein,
+    pub density: Density,
+}
+// what happened along a ray segment: scattered at distance t, or passed it entirely.
+// weight is the throughput of the tracking estimator up to there
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MediumEvent {
+    Scattered { t: f64, weight: DVec3 },
+    Passed { weight: DVec3 },
+}
+#[allow(dead_code)]
+impl Medium {
+    pub fn homogeneous(sigma_a: DVec3, sigma_s: DVec3, g: f64) -> Self {
+        Self { sigma_a, sigma_s, phase: HenyeyGreenstein { g }, density: Density::Homogeneous }
+    }
+    pub fn heterogeneous(sigma_a: DVec3, sigma_s: DVec3, g: f64, grid: VoxelGrid, min: DVec3, max: DVec3) -> Self {
+        Self { sigma_a, sigma_s, phase: HenyeyGreenstein { g }, density: Density::Grid { grid: Arc::new(grid), min, max } }
+    }
+    pub fn sigma_t(&self) -> DVec3 {
+        self.sigma_a + self.sigma_s
+    }
+    pub fn density_at(&self, p: DVec3) -> f64 {
+        match &self.density {
+            Density::Homogeneous => 1.,
+            Density::Grid { grid, min, max } => grid.density((p - *min) / (*max - *min)),
+        }
+    }
+    // bound on the extinction of every channel anywhere in the medium
+    fn majorant(&self) -> f64 {
+        let scale = match &self.density {
+            Density::Homogeneous => 1.,
+            Density::Grid { grid, .. } => grid.max(),
+        };
+        self.sigma_t().max_element() * scale
+    }
+    // part of [0, t_max] along the ray where there can be anything at all
+    fn extent(&self, org: DVec3, dir: DVec3, t_max: f64) -> Option<(f64, f64)> {
+        match &self.density {
+            Density::Homogeneous => Some((0., t_max)),
+            Density::Grid { min, max, .. } => {
+                // slabs
+                let inv = dir.recip();
+                let (t0, t1) = ((*min - org) * inv, (*max - org) * inv);
+                let near = t0.min(t1).max_element().max(0.);
+                let far = t0.max(t1).min_element().min(t_max);
+                match near < far {
+                    true => Some((near, far)),
+                    false => None,
+                }
+            }
+        }
+    }
+    // fraction of light making it from org to org + dir * t_max. exact when
+    // homogeneous, a ratio tracking estimate through a grid
+    pub fn transmittance(&self, org: DVec3, dir: DVec3, t_max: f64, mut rand: impl FnMut() -> f64) -> DVec3 {
+        let Some((t0, t1)) = self.extent(org, dir, t_max) else {
+            return DVec3::ONE;
+        };
+        let sigma_t = self.sigma_t();
+        if let Density::Homogeneous = self.density {
+            // written out so that nothing over an infinite distance stays 1
+            let tr = |sigma: f64| match sigma > 0. {
+                true => (-sigma * (t1 - t0)).exp(),
+                false => 1.,
+            };
+            return DVec3::new(tr(sigma_t.x), tr(sigma_t.y), tr(sigma_t.z));
+        }
+        let mu = self.majorant();
+        let mut tr = DVec3::ONE;
+        if mu <= 0. {
+            return tr;
+        }
+        let mut t = t0;
+        loop {
+            t -= (1. - rand()).ln() / mu;
+            if t >= t1 {
+                return tr;
+            }
+            tr *= DVec3::ONE - sigma_t * self.density_at(org + dir * t) / mu;
+        }
+    }
+    // free flight sampling up to t_max by delta tracking against the majorant.
+    // collisions are accepted with the channel averaged extinction and the
+    // weights make up for the channels that differ from it (spectral tracking)
+    pub fn sample(&self, org: DVec3, dir: DVec3, t_max: f64, mut rand: impl FnMut() -> f64) -> MediumEvent {
+        let mu = self.majorant();
+        let passed = MediumEvent::Passed { weight: DVec3::ONE };
+        let Some((t0, t1)) = self.extent(org, dir, t_max) else {
+            return passed;
+        };
+        if mu <= 0. {
+            return passed;
+        }
+        let mut weight = DVec3::ONE;
+        let mut t = t0;
+        loop {
+            t -= (1. - rand()).ln() / mu;
+            if t >= t1 {
+                return MediumEvent::Passed { weight };
+            }
+            let d = self.density_at(org + dir * t);
+            let sigma_t = self.sigma_t() * d;
+            let p_collide = sigma_t.dot(DVec3::ONE) / 3. / mu;
+            if rand() < p_collide {
+                return MediumEvent::Scattered { t, weight: weight * self.sigma_s * d / (mu * p_collide) };
+            }
+            weight *= (DVec3::splat(mu) - sigma_t) / (mu * (1. - p_collide));
+        }
+    }
+}
+// the surface of a volume: light passes it unchanged, it only marks where the
+// medium starts and ends. smoke and clouds are closed shapes with this material
+#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediumBoundary {
+    pub medium: Arc<Medium>,
+    pub priority: i32,
+}
+impl Bsdf for MediumBoundary {
+    fn eval(&self, _sp: &ShadingPoint, _wo: DVec3, _wi: DVec3) -> DVec3 {
+        DVec3::ZERO
+    }
+    fn sample(&self, _sp: &ShadingPoint, wo: DVec3, _uc: f64, _u: DVec2) -> Option<BsdfSample> {
+        Some(BsdfSample { wi: -wo, f: DVec3::ONE, pdf: 1., delta: true })
+    }
+    fn pdf(&self, _sp: &ShadingPoint, _wo: DVec3, _wi: DVec3) -> f64 {
+        0.
+    }
+    fn is_delta(&self) -> bool {
+        true
+    }
+    fn delta_lobes(&self, _sp: &ShadingPoint, wo: DVec3) -> Vec<(DVec3, DVec3)> {
+        vec![(-wo, DVec3::ONE)]
+    }
+    // index matched with the air around it
+    fn interior(&self) -> Option<Interior> {
+        Some(Interior { ior: 1., absorption: DVec3::ZERO, priority: self.priority, medium: Some(self.medium.clone()) })
+    }
+    fn is_passthrough(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+    use glam::{DVec2, DVec3};
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::{HenyeyGreenstein, Medium, MediumEvent, VoxelGrid};
+
+    #[test]
+    fn test_phase() {
+        let wo = DVec3::new(0.3, -0.4, 0.866).normalize();
+        for g in [0., 0.7, -0.4] {
+            let hg = HenyeyGreenstein { g };
+            // integrates to one over the sphere
+            let n = 400;
+            let total: f64 = (0..n).flat_map(|i| (0..n).map(move |j| (i, j))).map(|(i, j)| {
+                let (wi, pdf) = hg.sample(wo, DVec2::new((i as f64 + 0.5) / n as f64, (j as f64 + 0.5) / n as f64));
+                assert!((hg.eval(wo, wi) - pdf).abs() < 1e-9 * pdf.max(1.));
+                1. / (4. * PI * pdf)
+            }).sum::<f64>() / (n * n) as f64;
+            assert!((total - 1.).abs() < 1e-2, "{} {}", g, total);
+        }
+        // forward scattering keeps light going the way it went
+        let hg = HenyeyGreenstein { g: 0.8 };
+        assert!(hg.eval(wo, -wo) > 10. * hg.eval(wo, wo));
+    }
+    #[test]
+    fn test_voxel_file() {
+        let grid = VoxelGrid::parse("# two by one by one\n2 1 1\n0.5 1.5 # densities\n").unwrap();
+        assert_eq!((grid.nx, grid.ny, grid.nz, grid.max()), (2, 1, 1, 1.5));
+        assert_eq!(grid.density(DVec3::new(0.25, 0.5, 0.5)), 0.5);
+        assert_eq!(grid.density(DVec3::new(0.5, 0.2, 0.9)), 1.);
+        assert_eq!(grid.density(DVec3::new(1.5, 0.5, 0.5)), 0.);
+        assert!(VoxelGrid::parse("2 2 1\n1 2 3").is_err());
+        assert!(VoxelGrid::parse("1 1 1\n-1").is_err());
+        assert!(VoxelGrid::parse("1 0 1\n").is_err());
+    }
+    #[test]
+    fn test_tracking() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let fog = Medium::homogeneous(DVec3::new(0.1, 0.2, 0.3), DVec3::splat(0.2), 0.);
+        let tr = fog.transmittance(DVec3::ZERO, DVec3::X, 2., || rng.gen());
+        assert!((tr - DVec3::new(-0.6f64, -0.8, -1.).exp()).length() < 1e-12);
+        // a constant grid in a box behaves like the homogeneous medium clipped to it
+        let grid = VoxelGrid::new(1, 1, 1, vec![1.]);
+        let cloud = Medium::heterogeneous(DVec3::new(0.1, 0.2, 0.3), DVec3::splat(0.2), 0., grid, DVec3::splat(-1.), DVec3::splat(1.));
+        let n = 20000;
+        let mean = (0..n).map(|_| cloud.transmittance(DVec3::new(-5., 0., 0.), DVec3::X, 10., || rng.gen())).fold(DVec3::ZERO, |a, b| a + b) / n as f64;
+        assert!((mean - tr).length() < 1e-2, "{} {}", mean, tr);
+        // transmitted plus scattered weights account for everything but absorption
+        let (mut passed, mut scattered) = (DVec3::ZERO, DVec3::ZERO);
+        (0..n).for_each(|_| match fog.sample(DVec3::ZERO, DVec3::X, 2., || rng.gen()) {
+            MediumEvent::Passed { weight } => passed += weight,
+            MediumEvent::Scattered { t, weight } => {
+                assert!(t < 2.);
+                scattered += weight;
+            }
+        });
+        assert!((passed / n as f64 - tr).length() < 2e-2, "{}", passed / n as f64);
+        // the scattered weight estimates the integral of sigma_s * tr over the segment
+        let expected = DVec3::splat(0.2) * (DVec3::ONE - tr) / fog.sigma_t();
+        assert!((scattered / n as f64 - expected).length() < 2e-2, "{} {}", scattered / n as f64, expected);
+    }
+}
@@ -0,0 +1,415 @@
+use std::{f64::consts::PI, sync::Arc};
+use glam::{DVec2, DVec3};
+
+use super::{Bsdf, BsdfSample, ShadingPoint, Dielectric, Interior, Medium, Dispersion, reflect, refract, fresnel, coordinate_system};
+
+// trowbridge-reitz (ggx) normal distribution in the local shading frame, +z is
+// the normal and x the tangent. alpha_x != alpha_y stretches highlights along x or y
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrowbridgeReitz {
+    pub alpha_x: f64,
+    pub alpha_y: f64,
+}
+#[allow(dead_code)]
+impl TrowbridgeReitz {
+    // perceptual roughness in [0, 1], alpha = roughness^2
+    pub fn new(roughness: f64) -> Self {
+        TrowbridgeReitz::anisotropic(roughness, roughness)
+    }
+    pub fn anisotropic(roughness_u: f64, roughness_v: f64) -> Self {
+        let a = |r: f64| (r * r).max(1e-4);
+        Self { alpha_x: a(roughness_u), alpha_y: a(roughness_v) }
+    }
+    // below this the lobe is sharper than anything we can sample, treat it as a mirror
+    pub fn is_smooth(&self) -> bool {
+        self.alpha_x.max(self.alpha_y) < 1e-3
+    }
+    pub fn d(&self, wm: DVec3) -> f64 {
+        let e = (wm.x / self.alpha_x).powi(2) + (wm.y / self.alpha_y).powi(2) + wm.z * wm.z;
+        1. / (PI * self.alpha_x * self.alpha_y * e * e)
+    }
+    fn lambda(&self, w: DVec3) -> f64 {
+        if w.z == 0. {
+            return f64::INFINITY;
+        }
+        let a2tan2 = ((w.x * self.alpha_x).powi(2) + (w.y * self.alpha_y).powi(2)) / (w.z * w.z);
+        ((1. + a2tan2).sqrt() - 1.) / 2.
+    }
+    pub fn g1(&self, w: DVec3) -> f64 {
+        1. / (1. + self.lambda(w))
+    }
+    pub fn g(&self, wo: DVec3, wi: DVec3) -> f64 {
+        1. / (1. + self.lambda(wo) + self.lambda(wi))
+    }
+    // density of normals visible from w
+    pub fn d_visible(&self, w: DVec3, wm: DVec3) -> f64 {
+        match w.z != 0. {
+            true => self.g1(w) / w.z.abs() * self.d(wm) * w.dot(wm).abs(),
+            false => 0.,
+        }
+    }
+    // heitz 2018: sample the visible normals by projecting a disk onto the
+    // hemisphere of the stretched configuration
+    pub fn sample_wm(&self, w: DVec3, u: DVec2) -> DVec3 {
+        let mut wh = DVec3::new(self.alpha_x * w.x, self.alpha_y * w.y, w.z).normalize();
+        if wh.z < 0. {
+            wh = -wh;
+        }
+        let t1 = match wh.z < 0.99999 {
+            true => DVec3::Z.cross(wh).normalize(),
+            false => DVec3::X,
+        };
+        let t2 = wh.cross(t1);
+        let (r, phi) = (u.x.sqrt(), 2. * PI * u.y);
+        let (px, py) = (r * phi.cos(), r * phi.sin());
+        let h = (1. - px * px).sqrt();
+        let s = (1. + wh.z) / 2.;
+        let py = (1. - s) * h + s * py;
+        let pz = (1. - px * px - py * py).max(0.).sqrt();
+        let nh = t1 * px + t2 * py + wh * pz;
+        DVec3::new(self.alpha_x * nh.x, self.alpha_y * nh.y, nh.z.max(1e-6)).normalize()
+    }
+}
+// (t, b, n) with t following dpdu where it is usable
+fn local_frame(sp: &ShadingPoint) -> (DVec3, DVec3, DVec3) {
+    let n = sp.n;
+    let t = sp.dpdu - n * n.dot(sp.dpdu);
+    match t.length_squared() > 1e-20 {
+        true => {
+            let t = t.normalize();
+            (t, n.cross(t), n)
+        },
+        false => {
+            let (t, b) = coordinate_system(n);
+            (t, b, n)
+        },
+    }
+}
+fn to_local(f: (DVec3, DVec3, DVec3), v: DVec3) -> DVec3 {
+    DVec3::new(v.dot(f.0), v.dot(f.1), v.dot(f.2))
+}
+fn to_world(f: (DVec3, DVec3, DVec3), v: DVec3) -> DVec3 {
+    f.0 * v.x + f.1 * v.y + f.2 * v.z
+}
+// unpolarized reflectance of a conductor with complex index eta + ik
+pub fn fresnel_conductor(cos_i: f64, eta: f64, k: f64) -> f64 {
+    let c2 = cos_i.abs().min(1.).powi(2);
+    let s2 = 1. - c2;
+    let (eta2, k2) = (eta * eta, k * k);
+    let t0 = eta2 - k2 - s2;
+    let a2b2 = (t0 * t0 + 4. * eta2 * k2).sqrt();
+    let t1 = a2b2 + c2;
+    let a = (0.5 * (a2b2 + t0)).max(0.).sqrt();
+    let t2 = 2. * c2.sqrt() * a;
+    let rs = (t1 - t2) / (t1 + t2);
+    let t3 = c2 * a2b2 + s2 * s2;
+    let t4 = t2 * s2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+    0.5 * (rp + rs)
+}
+// rough metal, cook-torrance with a ggx distribution. eta and k are given at
+// representative red, green and blue wavelengths
+#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conductor {
+    pub eta: DVec3,
+    pub k: DVec3,
+    pub distribution: TrowbridgeReitz,
+}
+#[allow(dead_code)]
+impl Conductor {
+    pub fn gold(roughness: f64) -> Self {
+        Self { eta: DVec3::new(0.143, 0.374, 1.442), k: DVec3::new(3.983, 2.385, 1.603), distribution: TrowbridgeReitz::new(roughness) }
+    }
+    pub fn silver(roughness: f64) -> Self {
+        Self { eta: DVec3::new(0.155, 0.117, 0.138), k: DVec3::new(4.828, 3.122, 2.147), distribution: TrowbridgeReitz::new(roughness) }
+    }
+    pub fn copper(roughness: f64) -> Self {
+        Self { eta: DVec3::new(0.200, 0.924, 1.102), k: DVec3::new(3.912, 2.452, 2.142), distribution: TrowbridgeReitz::new(roughness) }
+    }
+    pub fn aluminium(roughness: f64) -> Self {
+        Self { eta: DVec3::new(1.657, 0.880, 0.521), k: DVec3::new(9.224, 6.270, 4.837), distribution: TrowbridgeReitz::new(roughness) }
+    }
+    pub fn fresnel(&self, cos_i: f64) -> DVec3 {
+        DVec3::new(
+            fresnel_conductor(cos_i, self.eta.x, self.k.x),
+            fresnel_conductor(cos_i, self.eta.y, self.k.y),
+            fresnel_conductor(cos_i, self.eta.z, self.k.z),
+        )
+    }
+}
+impl Bsdf for Conductor {
+    fn eval(&self, sp: &ShadingPoint, wo: DVec3, wi: DVec3) -> DVec3 {
+        if self.distribution.is_smooth() {
+            return DVec3::ZERO;
+        }
+        let f = local_frame(sp);
+        let (mut wo, mut wi) = (to_local(f, wo), to_local(f, wi));
+        // metals are opaque and two sided
+        if wo.z < 0. {
+            wo = -wo;
+            wi = -wi;
+        }
+        if wi.z <= 0. || wo.z <= 0. {
+            return DVec3::ZERO;
+        }
+        let wm = (wo + wi).normalize();
+        self.fresnel(wo.dot(wm)) * self.distribution.d(wm) * self.distribution.g(wo, wi) / (4. * wo.z * wi.z)
+    }
+    fn sample(&self, sp: &ShadingPoint, wo: DVec3, _uc: f64, u: DVec2) -> Option<BsdfSample> {
+        if self.distribution.is_smooth() {
+            let (wi, w) = self.delta_lobes(sp, wo)[0];
+            let cos = wi.dot(sp.n).abs();
+            return match cos > 0. {
+                true => Some(BsdfSample { wi, f: w / cos, pdf: 1., delta: true }),
+                false => None,
+            };
+        }
+        let f = local_frame(sp);
+        let sign = if to_local(f, wo).z < 0. { -1. } else { 1. };
+        let wo_l = to_local(f, wo) * sign;
+        let wm = self.distribution.sample_wm(wo_l, u);
+        let wi = to_world(f, reflect(-wo_l, wm) * sign);
+        let pdf = self.pdf(sp, wo, wi);
+        match pdf > 0. {
+            true => Some(BsdfSample { wi, f: self.eval(sp, wo, wi), pdf, delta: false }),
+            false => None,
+        }
+    }
+    fn pdf(&self, sp: &ShadingPoint, wo: DVec3, wi: DVec3) -> f64 {
+        if self.distribution.is_smooth() {
+            return 0.;
+        }
+        let f = local_frame(sp);
+        let (mut wo, mut wi) = (to_local(f, wo), to_local(f, wi));
+        if wo.z < 0. {
+            wo = -wo;
+            wi = -wi;
+        }
+        if wi.z <= 0. || wo.z <= 0. {
+            return 0.;
+        }
+        let wm = (wo + wi).normalize();
+        self.distribution.d_visible(wo, wm) / (4. * wo.dot(wm).abs())
+    }
+    fn is_delta(&self) -> bool {
+        self.distribution.is_smooth()
+    }
+    fn is_glossy(&self) -> bool {
+        !self.distribution.is_smooth()
+    }
+    fn delta_lobes(&self, sp: &ShadingPoint, wo: DVec3) -> Vec<(DVec3, DVec3)> {
+        match self.distribution.is_smooth() {
+            true => vec![(reflect(-wo, sp.n), self.fresnel(wo.dot(sp.n)))],
+            false => vec![],
+        }
+    }
+}
+// frosted glass: reflection and transmission through a ggx distribution of
+// microfacets, a smooth Dielectric when the roughness goes to zero
+#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoughDielectric {
+    pub ior: f64,
+    pub absorption: DVec3,
+    pub priority: i32,
+    pub dispersion: Option<Dispersion>,
+    pub medium: Option<Arc<Medium>>,
+    pub distribution: TrowbridgeReitz,
+}
+impl RoughDielectric {
+    // frosted clear glass, see Dielectric::clear
+    pub fn clear(ior: f64, roughness: f64) -> Self {
+        Self { ior, absorption: DVec3::ZERO, priority: 0, dispersion: None, medium: None, distribution: TrowbridgeReitz::new(roughness) }
+    }
+    fn smooth(&self) -> Dielectric {
+        Dielectric { ior: self.ior, absorption: self.absorption, priority: self.priority, dispersion: self.dispersion, medium: self.medium.clone() }
+    }
+    // local frame microfacet normal between wo and wi facing +z, with the relative
+    // ior seen from wo; None for configurations no microfacet can produce
+    fn half_vector(&self, eta: f64, wo: DVec3, wi: DVec3) -> Option<(DVec3, f64)> {
+        let reflect = wo.z * wi.z > 0.;
+        let etap = match (reflect, wo.z > 0.) {
+            (true, _) => 1.,
+            (false, true) => eta,
+            (false, false) => 1. / eta,
+        };
+        let wm = wi * etap + wo;
+        if wi.z == 0. || wo.z == 0. || wm.length_squared() == 0. {
+            return None;
+        }
+        let wm = wm.normalize();
+        let wm = if wm.z < 0. { -wm } else { wm };
+        // microfacets seen from behind
+        match wm.dot(wi) * wi.z < 0. || wm.dot(wo) * wo.z < 0. {
+            true => None,
+            false => Some((wm, etap)),
+        }
+    }
+}
+impl Bsdf for RoughDielectric {
+    fn eval(&self, sp: &ShadingPoint, wo: DVec3, wi: DVec3) -> DVec3 {
+        if self.distribution.is_smooth() {
+            return DVec3::ZERO;
+        }
+        let f = local_frame(sp);
+        let (wo, wi) = (to_local(f, wo), to_local(f, wi));
+        let eta = self.smooth().ior_at(sp) / sp.outside_ior;
+        let (wm, etap) = match self.half_vector(eta, wo, wi) {
+            Some(h) => h,
+            None => return DVec3::ZERO,
+        };
+        let kr = fresnel(-wo, wm, eta);
+        let d = self.distribution.d(wm) * self.distribution.g(wo, wi);
+        match wo.z * wi.z > 0. {
+            true => DVec3::splat(d * kr / (4. * wo.z * wi.z).abs()),
+            false => {
+                let denom = (wi.dot(wm) + wo.dot(wm) / etap).powi(2);
+                DVec3::splat(d * (1. - kr) * (wi.dot(wm) * wo.dot(wm) / (wi.z * wo.z * denom)).abs())
+            },
+        }
+    }
+    fn sample(&self, sp: &ShadingPoint, wo: DVec3, uc: f64, u: DVec2) -> Option<BsdfSample> {
+        if self.distribution.is_smooth() {
+            return self.smooth().sample(sp, wo, uc, u);
+        }
+        let f = local_frame(sp);
+        let wo_l = to_local(f, wo);
+        let wm = self.distribution.sample_wm(wo_l, u);
+        let eta = self.smooth().ior_at(sp) / sp.outside_ior;
+        let kr = fresnel(-wo_l, wm, eta);
+        let wi = match uc < kr {
+            true => reflect(-wo_l, wm),
+            false => refract(-wo_l, wm, eta),
+        };
+        if wi == DVec3::ZERO {
+            return None;
+        }
+        let wi = to_world(f, wi.normalize());
+        let pdf = self.pdf(sp, wo, wi);
+        match pdf > 0. {
+            true => Some(BsdfSample { wi, f: self.eval(sp, wo, wi), pdf, delta: false }),
+            false => None,
+        }
+    }
+    fn pdf(&self, sp: &ShadingPoint, wo: DVec3, wi: DVec3) -> f64 {
+        if self.distribution.is_smooth() {
+            return 0.;
+        }
+        let f = local_frame(sp);
+        let (wo, wi) = (to_local(f, wo), to_local(f, wi));
+        let eta = self.smooth().ior_at(sp) / sp.outside_ior;
+        let (wm, etap) = match self.half_vector(eta, wo, wi) {
+            Some(h) => h,
+            None => return 0.,
+        };
+        let kr = fresnel(-wo, wm, eta);
+        match wo.z * wi.z > 0. {
+            true => self.distribution.d_visible(wo, wm) / (4. * wo.dot(wm).abs()) * kr,
+            false => {
+                let denom = (wi.dot(wm) + wo.dot(wm) / etap).powi(2);
+                self.distribution.d_visible(wo, wm) * wi.dot(wm).abs() / denom * (1. - kr)
+            },
+        }
+    }
+    fn is_delta(&self) -> bool {
+        self.distribution.is_smooth()
+    }
+    fn is_glossy(&self) -> bool {
+        !self.distribution.is_smooth()
+    }
+    fn delta_lobes(&self, sp: &ShadingPoint, wo: DVec3) -> Vec<(DVec3, DVec3)> {
+        match self.distribution.is_smooth() {
+            true => self.smooth().delta_lobes(sp, wo),
+            false => vec![],
+        }
+    }
+    fn interior(&self) -> Option<Interior> {
+        self.smooth().interior()
+    }
+    fn is_dispersive(&self) -> bool {
+        self.dispersion.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+    use glam::{DVec2, DVec3};
+
+    use crate::lib::{Bsdf, ShadingPoint, TrowbridgeReitz, Conductor, RoughDielectric, fresnel_conductor};
+
+    const SP: ShadingPoint = ShadingPoint { p: DVec3::ZERO, n: DVec3::Z, st: DVec2::ZERO, dpdu: DVec3::X, outside_ior: 1., wavelength: None };
+
+    // (mean of f |cos| / pdf, largest mismatch between sampled and evaluated f and pdf)
+    fn estimate(bsdf: &dyn Bsdf, wo: DVec3) -> (DVec3, f64) {
+        let n = 96;
+        let (mut sum, mut err) = (DVec3::ZERO, 0f64);
+        for i in 0..n {
+            for j in 0..n {
+                let u = DVec2::new((i as f64 + 0.5) / n as f64, (j as f64 + 0.5) / n as f64);
+                let uc = ((i * 37 + j * 61) % n) as f64 / n as f64;
+                if let Some(s) = bsdf.sample(&SP, wo, uc, u) {
+                    sum += s.f * s.wi.z.abs() / s.pdf;
+                    err = err.max((s.pdf - bsdf.pdf(&SP, wo, s.wi)).abs() / s.pdf);
+                    err = err.max((s.f - bsdf.eval(&SP, wo, s.wi)).length() / s.f.length());
+                }
+            }
+        }
+        (sum / (n * n) as f64, err)
+    }
+    #[test]
+    fn test_distribution() {
+        // projected normals integrate to one, isotropic or not
+        for dist in [TrowbridgeReitz::new(0.5), TrowbridgeReitz::anisotropic(0.3, 0.8)] {
+            let (nt, np) = (400, 200);
+            let mut sum = 0.;
+            for i in 0..nt {
+                let theta = (i as f64 + 0.5) / nt as f64 * PI / 2.;
+                for j in 0..np {
+                    let phi = (j as f64 + 0.5) / np as f64 * 2. * PI;
+                    let wm = DVec3::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos());
+                    sum += dist.d(wm) * theta.cos() * theta.sin();
+                }
+            }
+            sum *= (PI / 2. / nt as f64) * (2. * PI / np as f64);
+            assert!((sum - 1.).abs() < 1e-2, "{}", sum);
+        }
+        let aniso = TrowbridgeReitz::anisotropic(0.2, 0.6);
+        // tilting towards the rougher axis keeps more density
+        assert!(aniso.d(DVec3::new(0., 0.3, 1.).normalize()) > aniso.d(DVec3::new(0.3, 0., 1.).normalize()));
+        assert!(TrowbridgeReitz::new(0.).is_smooth());
+    }
+    #[test]
+    fn test_conductor() {
+        // normal incidence reflectance of gold, ((n-1)^2 + k^2) / ((n+1)^2 + k^2)
+        let r = fresnel_conductor(1., 0.143, 3.983);
+        assert!((r - (0.857f64.powi(2) + 3.983f64.powi(2)) / (1.143f64.powi(2) + 3.983f64.powi(2))).abs() < 1e-12);
+        assert!((fresnel_conductor(0., 0.143, 3.983) - 1.).abs() < 1e-9);
+        let wo = DVec3::new(0.4, 0.2, 1.).normalize();
+        let gold = Conductor::gold(0.4);
+        let (albedo, err) = estimate(&gold, wo);
+        assert!(err < 1e-6, "{}", err);
+        // some energy is lost to shadowing and masking, and gold is yellow
+        let f0 = gold.fresnel(1.);
+        assert!(albedo.x < f0.x + 0.02 && albedo.x > 0.8 * f0.x, "{} {}", albedo, f0);
+        assert!(albedo.z < albedo.y && albedo.y < albedo.x);
+        let brushed = Conductor { distribution: TrowbridgeReitz::anisotropic(0.1, 0.5), ..Conductor::aluminium(0.) };
+        assert!(estimate(&brushed, wo).1 < 1e-6);
+        assert!(Conductor::silver(0.).is_delta());
+    }
+    #[test]
+    fn test_rough_dielectric() {
+        let glass = RoughDielectric { ior: 1.5, absorption: DVec3::ZERO, priority: 0, dispersion: None, medium: None, distribution: TrowbridgeReitz::new(0.3) };
+        for wo in [DVec3::new(0.3, 0., 1.).normalize(), DVec3::new(0.2, 0.1, -1.).normalize()] {
+            let (albedo, err) = estimate(&glass, wo);
+            assert!(err < 1e-6, "{}", err);
+            // nothing is absorbed, a little is lost to masking
+            assert!(albedo.x > 0.85 && albedo.x < 1.02, "{}", albedo);
+        }
+        // light comes through from below
+        let wo = DVec3::Z;
+        assert!(glass.eval(&SP, wo, -DVec3::new(0.1, 0., 1.).normalize()).x > 0.);
+        assert!(glass.pdf(&SP, wo, -DVec3::Z) > glass.pdf(&SP, wo, DVec3::Z));
+    }
+}
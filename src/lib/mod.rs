@@ -3,9 +3,61 @@ mod light;
 mod sphere;
 mod scene;
 mod render;
+mod texture;
+mod noise;
+mod area_light;
+mod distribution;
+mod environment;
+mod sky;
+mod ies;
+mod bsdf;
+mod microfacet;
+mod interior;
+mod spectrum;
+mod medium;
+mod motion;
+mod display;
+mod denoise;
+mod adaptive;
+mod progressive;
+mod progress;
+mod stats;
+mod scene_file;
+mod sampler;
+// regression tests against stored images, nothing else needs it
+#[cfg(test)]
+mod golden;
+// unique scratch files for tests
+#[cfg(test)]
+mod temp;
 
 pub use triangle::*;
 pub use light::*;
 pub use sphere::*;
 pub use scene::*;
-pub use render::*;
\ No newline at end of file
+pub use render::*;
+pub use texture::*;
+pub use noise::*;
+pub use area_light::*;
+pub use distribution::*;
+pub use environment::*;
+pub use sky::*;
+pub use ies::*;
+pub use bsdf::*;
+pub use microfacet::*;
+pub use interior::*;
+pub use spectrum::*;
+pub use medium::*;
+pub use motion::*;
+pub use display::*;
+pub use denoise::*;
+pub use adaptive::*;
+pub use progressive::*;
+pub use progress::*;
+pub use stats::*;
+pub use scene_file::*;
+pub use sampler::*;
+#[cfg(test)]
+pub use golden::*;
+#[cfg(test)]
+pub use temp::*;
\ No newline at end of file
@@ -0,0 +1,327 @@
+use glam::{DAffine3, DQuat, DVec2, DVec3};
+
+use super::{Object, Light, Material, SpecularProperties, ShapeBsdf, NormalMap, Emission};
+
+// axis aligned box, what trace tests before the exact intersection
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: DVec3,
+    pub max: DVec3,
+}
+#[allow(dead_code)]
+impl Aabb {
+    pub fn empty() -> Self {
+        Self { min: DVec3::splat(f64::INFINITY), max: DVec3::splat(f64::NEG_INFINITY) }
+    }
+    // for shapes that cannot tell, never culls anything
+    pub fn infinite() -> Self {
+        Self { min: DVec3::splat(f64::NEG_INFINITY), max: DVec3::splat(f64::INFINITY) }
+    }
+    pub fn around(p: DVec3, radius: f64) -> Self {
+        Self { min: p - radius, max: p + radius }
+    }
+    pub fn union(&self, other: &Aabb) -> Self {
+        Self { min: self.min.min(other.min), max: self.max.max(other.max) }
+    }
+    pub fn with(&self, p: DVec3) -> Self {
+        Self { min: self.min.min(p), max: self.max.max(p) }
+    }
+    pub fn corners(&self) -> [DVec3; 8] {
+        let (a, b) = (self.min, self.max);
+        [
+            DVec3::new(a.x, a.y, a.z), DVec3::new(b.x, a.y, a.z), DVec3::new(a.x, b.y, a.z), DVec3::new(b.x, b.y, a.z),
+            DVec3::new(a.x, a.y, b.z), DVec3::new(b.x, a.y, b.z), DVec3::new(a.x, b.y, b.z), DVec3::new(b.x, b.y, b.z),
+        ]
+    }
+    pub fn is_finite(&self) -> bool {
+        self.min.is_finite() && self.max.is_finite()
+    }
+    // whether the ray can touch the box closer than t_max
+    pub fn hit(&self, org: DVec3, dir: DVec3, t_max: f64) -> bool {
+        if !self.is_finite() {
+            return self.min.x <= self.max.x;
+        }
+        let inv = dir.recip();
+        let (t0, t1) = ((self.min - org) * inv, (self.max - org) * inv);
+        // nan from 0 * inf, a ray in the plane of a face, must not cull. far is
+        // pushed out a little so rounding never loses a hit on the box's edge
+        let near = t0.min(t1).max_element();
+        let far = t0.max(t1).min_element() * (1. + 1e-9);
+        near.is_nan() || far.is_nan() || (near <= far && far >= 0. && near <= t_max)
+    }
+}
+// values interpolated between keyframes
+pub trait Interpolate: Clone {
+    fn interpolate(&self, other: &Self, t: f64) -> Self;
+}
+impl Interpolate for DVec3 {
+    fn interpolate(&self, other: &Self, t: f64) -> Self {
+        self.lerp(*other, t)
+    }
+}
+// (time, value) pairs in increasing time, linear in between and held constant
+// before the first and after the last key. two keys move linearly over the
+// shutter, more make a multi segment path
+#[derive(Debug, Clone, PartialEq)]
+pub struct Keyframes<T> {
+    keys: Vec<(f64, T)>,
+}
+#[allow(dead_code)]
+impl<T: Interpolate> Keyframes<T> {
+    pub fn new(mut keys: Vec<(f64, T)>) -> Self {
+        assert!(!keys.is_empty());
+        keys.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Self { keys }
+    }
+    pub fn linear(t0: f64, v0: T, t1: f64, v1: T) -> Self {
+        Keyframes::new(vec![(t0, v0), (t1, v1)])
+    }
+    pub fn keys(&self) -> &[(f64, T)] {
+        &self.keys
+    }
+    pub fn at(&self, time: f64) -> T {
+        let k = self.keys.partition_point(|(t, _)| *t <= time);
+        match k {
+            0 => self.keys[0].1.clone(),
+            k if k == self.keys.len() => self.keys[k - 1].1.clone(),
+            k => {
+                let ((ta, a), (tb, b)) = (&self.keys[k - 1], &self.keys[k]);
+                a.interpolate(b, (time - ta) / (tb - ta))
+            }
+        }
+    }
+}
+// scale, then rotate, then translate. rotations are slerped between keys
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    pub translation: DVec3,
+    pub rotation: DQuat,
+    pub scale: DVec3,
+}
+#[allow(dead_code)]
+impl Transform {
+    pub fn identity() -> Self {
+        Self { translation: DVec3::ZERO, rotation: DQuat::IDENTITY, scale: DVec3::ONE }
+    }
+    pub fn translate(translation: DVec3) -> Self {
+        Self { translation, ..Transform::identity() }
+    }
+    pub fn to_affine(self) -> DAffine3 {
+        DAffine3::from_scale_rotation_translation(self.scale, self.rotation, self.translation)
+    }
+}
+impl Interpolate for Transform {
+    fn interpolate(&self, other: &Self, t: f64) -> Self {
+        Self {
+            translation: self.translation.lerp(other.translation, t),
+            rotation: self.rotation.slerp(other.rotation, t),
+            scale: self.scale.lerp(other.scale, t),
+        }
+    }
+}
+// world box of a local box under every transform on the keyframed path
+#[allow(dead_code)]
+fn swept_bounds(local: Aabb, motion: &Keyframes<Transform>) -> Aabb {
+    if !local.is_finite() {
+        return local;
+    }
+    let boxed = |xf: &Transform| {
+        let m = xf.to_affine();
+        local.corners().iter().fold(Aabb::empty(), |b, c| b.with(m.transform_point3(*c)))
+    };
+    let keys = motion.keys();
+    let mut bounds = boxed(&keys[0].1);
+    keys.windows(2).for_each(|w| {
+        let (a, b) = (&w[0].1, &w[1].1);
+        bounds = match a.rotation == b.rotation {
+            // linear in time, the path stays in the hull of both ends
+            true => bounds.union(&boxed(b)),
+            // rotating: every point stays within its largest distance from the
+            // origin of the object, around the linearly moving translation
+            false => {
+                let reach = local.corners().iter().map(|c| c.length()).fold(0., f64::max) * a.scale.max(b.scale).max_element();
+                bounds.union(&Aabb::around(a.translation, reach)).union(&Aabb::around(b.translation, reach))
+            }
+        };
+    });
+    bounds
+}
+// an object placed in the world by an affine transform. rays are taken into the
+// object's space unnormalized, so distances along them stay the same
+#[derive(Clone)]
+pub struct Transformed {
+    pub object: Box<dyn Object>,
+    transform: DAffine3,
+    inverse: DAffine3,
+}
+#[allow(dead_code)]
+impl Transformed {
+    pub fn new(object: Box<dyn Object>, transform: DAffine3) -> Self {
+        Self { object, transform, inverse: transform.inverse() }
+    }
+    fn local_ray(&self, light: Light, dir: DVec3) -> (Light, DVec3) {
+        (Light { org: self.inverse.transform_point3(light.org), inten: light.inten, ies: None }, self.inverse.transform_vector3(dir))
+    }
+}
+impl Object for Transformed {
+    // counted as what they wrap
+    fn kind(&self) -> &'static str {
+        self.object.kind()
+    }
+    fn intersection(&self, light: Light, dir: DVec3) -> (bool, f64, usize, DVec2) {
+        let (light, dir) = self.local_ray(light, dir);
+        self.object.intersection(light, dir)
+    }
+    fn eval_diffuse_color(&self, vx: DVec2, p: DVec3) -> DVec3 {
+        self.object.eval_diffuse_color(vx, self.inverse.transform_point3(p))
+    }
+    fn get_surface_properties(&self, p: DVec3, px: DVec3, idx: usize, uv: DVec2) -> (DVec3, DVec2) {
+        let (n, st) = self.object.get_surface_properties(self.inverse.transform_point3(p), self.inverse.transform_vector3(px), idx, uv);
+        // normals go with the inverse transpose
+        (self.inverse.matrix3.transpose().mul_vec3(n).normalize(), st)
+    }
+    fn get_tangents(&self, p: DVec3, idx: usize, uv: DVec2) -> (DVec3, DVec3) {
+        let (dpdu, dpdv) = self.object.get_tangents(self.inverse.transform_point3(p), idx, uv);
+        (self.transform.transform_vector3(dpdu), self.transform.transform_vector3(dpdv))
+    }
+    fn get_material_properties(&self) -> Material {
+        self.object.get_material_properties()
+    }
+    fn get_ior(&self) -> f64 {
+        self.object.get_ior()
+    }
+    fn get_specular_properties(&self) -> SpecularProperties {
+        self.object.get_specular_properties()
+    }
+    fn get_bsdf(&self) -> ShapeBsdf<'_> {
+        self.object.get_bsdf()
+    }
+    fn get_normal_map(&self) -> Option<&NormalMap> {
+        self.object.get_normal_map()
+    }
+    // seen by camera and specular rays only, the light would be in object space
+    fn get_emission(&self) -> Option<Emission> {
+        self.object.get_emission()
+    }
+    fn bounds(&self) -> Aabb {
+        let local = self.object.bounds();
+        match local.is_finite() {
+            true => local.corners().iter().fold(Aabb::empty(), |b, c| b.with(self.transform.transform_point3(*c))),
+            false => local,
+        }
+    }
+}
+// an object following keyframed transforms over the shutter interval
+#[allow(dead_code)]
+#[derive(Clone)]
+pub struct Moving {
+    pub object: Box<dyn Object>,
+    pub motion: Keyframes<Transform>,
+}
+#[allow(dead_code)]
+impl Moving {
+    fn posed(&self, time: f64) -> Transformed {
+        Transformed::new(self.object.clone(), self.motion.at(time).to_affine())
+    }
+}
+// without a time the object is where it is at time 0
+impl Object for Moving {
+    fn kind(&self) -> &'static str {
+        self.object.kind()
+    }
+    fn intersection(&self, light: Light, dir: DVec3) -> (bool, f64, usize, DVec2) {
+        self.intersection_at(light, dir, 0.)
+    }
+    fn intersection_at(&self, light: Light, dir: DVec3, time: f64) -> (bool, f64, usize, DVec2) {
+        let inverse = self.motion.at(time).to_affine().inverse();
+        self.object.intersection(Light { org: inverse.transform_point3(light.org), inten: light.inten, ies: None }, inverse.transform_vector3(dir))
+    }
+    fn at_time(&self, time: f64) -> Box<dyn Object> {
+        Box::new(self.posed(time))
+    }
+    fn eval_diffuse_color(&self, vx: DVec2, p: DVec3) -> DVec3 {
+        self.posed(0.).eval_diffuse_color(vx, p)
+    }
+    fn get_surface_properties(&self, p: DVec3, px: DVec3, idx: usize, uv: DVec2) -> (DVec3, DVec2) {
+        self.posed(0.).get_surface_properties(p, px, idx, uv)
+    }
+    fn get_tangents(&self, p: DVec3, idx: usize, uv: DVec2) -> (DVec3, DVec3) {
+        self.posed(0.).get_tangents(p, idx, uv)
+    }
+    fn get_material_properties(&self) -> Material {
+        self.object.get_material_properties()
+    }
+    fn get_ior(&self) -> f64 {
+        self.object.get_ior()
+    }
+    fn get_specular_properties(&self) -> SpecularProperties {
+        self.object.get_specular_properties()
+    }
+    fn get_bsdf(&self) -> ShapeBsdf<'_> {
+        self.object.get_bsdf()
+    }
+    fn get_normal_map(&self) -> Option<&NormalMap> {
+        self.object.get_normal_map()
+    }
+    fn get_emission(&self) -> Option<Emission> {
+        self.object.get_emission()
+    }
+    fn bounds(&self) -> Aabb {
+        swept_bounds(self.object.bounds(), &self.motion)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+    use glam::{DAffine3, DQuat, DVec3};
+
+    use crate::lib::{Object, Light, diffuse_ball};
+
+    use super::{Aabb, Keyframes, Transform, Transformed, Moving, Interpolate};
+
+    #[test]
+    fn test_keyframes() {
+        let path = Keyframes::new(vec![(1., DVec3::X), (0., DVec3::ZERO), (3., DVec3::new(1., 4., 0.))]);
+        assert_eq!(path.at(-1.), DVec3::ZERO);
+        assert_eq!(path.at(0.5), DVec3::new(0.5, 0., 0.));
+        assert_eq!(path.at(2.), DVec3::new(1., 2., 0.));
+        assert_eq!(path.at(5.), DVec3::new(1., 4., 0.));
+        let spin = Transform { rotation: DQuat::from_rotation_y(PI), ..Transform::identity() };
+        let half = Transform::identity().interpolate(&spin, 0.5);
+        assert!((half.to_affine().transform_vector3(DVec3::X) - DVec3::new(0., 0., -1.)).length() < 1e-12);
+    }
+    #[test]
+    fn test_transformed() {
+        let eye = Light { org: DVec3::ZERO, inten: DVec3::ONE, ies: None };
+        let moved = Transformed::new(Box::new(diffuse_ball(DVec3::ZERO, 1., DVec3::ONE)), DAffine3::from_scale_rotation_translation(DVec3::splat(2.), DQuat::IDENTITY, DVec3::new(0., 0., -10.)));
+        let (hit, t, _, _) = moved.intersection(eye.clone(), -DVec3::Z);
+        assert!(hit && (t - 8.).abs() < 1e-12);
+        let (n, _) = moved.get_surface_properties(DVec3::new(0., 0., -8.), -DVec3::Z, 0, Default::default());
+        assert!((n - DVec3::Z).length() < 1e-12);
+        assert!(moved.bounds().hit(eye.org, -DVec3::Z, f64::INFINITY));
+        assert!(!moved.bounds().hit(eye.org, DVec3::Z, f64::INFINITY));
+        assert!(!moved.bounds().hit(eye.org, -DVec3::Z, 7.));
+    }
+    #[test]
+    fn test_moving() {
+        let eye = Light { org: DVec3::ZERO, inten: DVec3::ONE, ies: None };
+        let slide = Moving { object: Box::new(diffuse_ball(DVec3::ZERO, 1., DVec3::ONE)), motion: Keyframes::linear(0., Transform::translate(DVec3::new(-3., 0., -10.)), 1., Transform::translate(DVec3::new(3., 0., -10.))) };
+        assert!(!slide.intersection_at(eye.clone(), -DVec3::Z, 0.).0);
+        assert!(slide.intersection_at(eye.clone(), -DVec3::Z, 0.5).0);
+        // the swept box covers the whole path, not just the ends
+        let bounds = slide.bounds();
+        assert_eq!(bounds, Aabb { min: DVec3::new(-4., -1., -11.), max: DVec3::new(4., 1., -9.) });
+        assert!(bounds.hit(eye.org, -DVec3::Z, f64::INFINITY));
+        let posed = slide.at_time(0.5);
+        let (n, _) = posed.get_surface_properties(DVec3::new(0., 0., -9.), -DVec3::Z, 0, Default::default());
+        assert!((n - DVec3::Z).length() < 1e-12);
+        // an orbit keeps every point within reach of the path
+        let orbit = Moving { object: Box::new(diffuse_ball(DVec3::new(2., 0., 0.), 1., DVec3::ONE)), motion: Keyframes::linear(0., Transform::identity(), 1., Transform { rotation: DQuat::from_rotation_y(PI / 2.), ..Transform::identity() }) };
+        let bounds = orbit.bounds();
+        (0..=10).for_each(|i| {
+            let c = orbit.motion.at(i as f64 / 10.).to_affine().transform_point3(DVec3::new(2., 0., 0.));
+            assert!(c.cmpge(bounds.min + 1.).all() && c.cmple(bounds.max - 1.).all(), "{}", c);
+        });
+    }
+}
@@ -0,0 +1,156 @@
+use glam::DVec3;
+
+// integer lattice hash (pcg style), deterministic across runs
+fn hash(x: i64, y: i64, z: i64) -> u32 {
+    let mut h = (x as u32).wrapping_mul(0x8da6b343) ^ (y as u32).wrapping_mul(0xd8163841) ^ (z as u32).wrapping_mul(0xcb1ab31f);
+    h = h.wrapping_mul(747796405).wrapping_add(2891336453);
+    h = ((h >> ((h >> 28) + 4)) ^ h).wrapping_mul(277803737);
+    (h >> 22) ^ h
+}
+fn hash_unit(x: i64, y: i64, z: i64, k: u32) -> f64 {
+    hash(x, y, z).wrapping_add(k.wrapping_mul(0x9e3779b9)).wrapping_mul(0x85ebca6b) as f64 / u32::MAX as f64
+}
+fn grad(h: u32, d: DVec3) -> f64 {
+    // the 12 cube edge directions, as in improved perlin noise
+    match h % 12 {
+        0 => d.x + d.y,
+        1 => -d.x + d.y,
+        2 => d.x - d.y,
+        3 => -d.x - d.y,
+        4 => d.x + d.z,
+        5 => -d.x + d.z,
+        6 => d.x - d.z,
+        7 => -d.x - d.z,
+        8 => d.y + d.z,
+        9 => -d.y + d.z,
+        10 => d.y - d.z,
+        _ => -d.y - d.z,
+    }
+}
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6. - 15.) + 10.)
+}
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t
+}
+// perlin gradient noise, roughly in [-1, 1]
+pub fn perlin(p: DVec3) -> f64 {
+    let c = p.floor();
+    let (x, y, z) = (c.x as i64, c.y as i64, c.z as i64);
+    let f = p - c;
+    let (u, v, w) = (fade(f.x), fade(f.y), fade(f.z));
+    let g = |dx: i64, dy: i64, dz: i64| grad(hash(x + dx, y + dy, z + dz), f - DVec3::new(dx as f64, dy as f64, dz as f64));
+    lerp(
+        lerp(lerp(g(0, 0, 0), g(1, 0, 0), u), lerp(g(0, 1, 0), g(1, 1, 0), u), v),
+        lerp(lerp(g(0, 0, 1), g(1, 0, 1), u), lerp(g(0, 1, 1), g(1, 1, 1), u), v),
+        w,
+    )
+}
+// simplex noise (gustavson's 3d version), roughly in [-1, 1]. four corners per
+// point instead of eight and no axis aligned streaks
+pub fn simplex(p: DVec3) -> f64 {
+    const F3: f64 = 1. / 3.;
+    const G3: f64 = 1. / 6.;
+    // skew into the grid of cubes that each hold six tetrahedra
+    let c = (p + DVec3::splat((p.x + p.y + p.z) * F3)).floor();
+    let d0 = p - c + DVec3::splat((c.x + c.y + c.z) * G3);
+    // the tetrahedron holding d0, by the order of its coordinates
+    let (o1, o2) = match (d0.x >= d0.y, d0.y >= d0.z, d0.x >= d0.z) {
+        (true, true, _) => (DVec3::X, DVec3::new(1., 1., 0.)),
+        (true, false, true) => (DVec3::X, DVec3::new(1., 0., 1.)),
+        (true, false, false) => (DVec3::Z, DVec3::new(1., 0., 1.)),
+        (false, false, _) => (DVec3::Z, DVec3::new(0., 1., 1.)),
+        (false, true, false) => (DVec3::Y, DVec3::new(0., 1., 1.)),
+        (false, true, true) => (DVec3::Y, DVec3::new(1., 1., 0.)),
+    };
+    [DVec3::ZERO, o1, o2, DVec3::ONE].iter().enumerate().map(|(k, o)| {
+        let d = d0 - *o + DVec3::splat(k as f64 * G3);
+        let r = 0.6 - d.length_squared();
+        match r > 0. {
+            true => r.powi(4) * grad(hash(c.x as i64 + o.x as i64, c.y as i64 + o.y as i64, c.z as i64 + o.z as i64), d),
+            false => 0.,
+        }
+    }).sum::<f64>() * 32.
+}
+// octaves of the basis, each at twice the frequency and half the amplitude
+fn octaves(p: DVec3, octaves: u32, basis: impl Fn(DVec3) -> f64) -> f64 {
+    let (mut sum, mut amp, mut freq) = (0., 0.5, 1.);
+    for _ in 0..octaves {
+        sum += amp * basis(p * freq);
+        amp *= 0.5;
+        freq *= 2.;
+    }
+    sum
+}
+pub fn fbm(p: DVec3, n: u32) -> f64 {
+    octaves(p, n, perlin)
+}
+pub fn simplex_fbm(p: DVec3, n: u32) -> f64 {
+    octaves(p, n, simplex)
+}
+pub fn turbulence(p: DVec3, n: u32) -> f64 {
+    octaves(p, n, |q| perlin(q).abs())
+}
+// worley cellular noise: distance to the nearest and second nearest feature point
+pub fn worley(p: DVec3) -> (f64, f64) {
+    let c = p.floor();
+    let (mut f1, mut f2) = (f64::MAX, f64::MAX);
+    for dz in -1..=1 {
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let (x, y, z) = (c.x as i64 + dx, c.y as i64 + dy, c.z as i64 + dz);
+                let feature = DVec3::new(x as f64, y as f64, z as f64) + DVec3::new(hash_unit(x, y, z, 0), hash_unit(x, y, z, 1), hash_unit(x, y, z, 2));
+                let d = feature.distance(p);
+                if d < f1 {
+                    f2 = f1;
+                    f1 = d;
+                } else if d < f2 {
+                    f2 = d;
+                }
+            }
+        }
+    }
+    (f1, f2)
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::DVec3;
+
+    use super::{perlin, simplex, fbm, simplex_fbm, worley};
+
+    #[test]
+    fn test_perlin_lattice() {
+        // gradient noise vanishes on integer lattice points
+        assert_eq!(perlin(DVec3::new(3., -2., 7.)), 0.);
+        let v = perlin(DVec3::new(0.3, 1.7, -4.2));
+        assert!(v.abs() <= 1.);
+        assert_eq!(v, perlin(DVec3::new(0.3, 1.7, -4.2)));
+    }
+    #[test]
+    fn test_simplex() {
+        // zero on the corners of the simplex grid, (3, -2, 7) unskewed, otherwise
+        // bounded, smooth and not flat
+        assert!(simplex(DVec3::new(3., -2., 7.) - DVec3::splat(8. / 6.)).abs() < 1e-12);
+        let values: Vec<f64> = (0..2000).map(|i| simplex(DVec3::new(i as f64 * 0.173, i as f64 * 0.071, -(i as f64) * 0.119))).collect();
+        assert!(values.iter().all(|v| v.abs() <= 1.));
+        assert!(values.iter().any(|v| *v > 0.3) && values.iter().any(|v| *v < -0.3));
+        let p = DVec3::new(0.3, 1.7, -4.2);
+        assert_eq!(simplex(p), simplex(p));
+        assert!((simplex(p + DVec3::splat(1e-4)) - simplex(p)).abs() < 1e-2);
+        (0..100).for_each(|i| assert!(simplex_fbm(DVec3::new(i as f64 * 0.37, 0.5, i as f64 * 0.29), 6).abs() < 1.));
+    }
+    #[test]
+    fn test_fbm_range() {
+        (0..100).for_each(|i| {
+            let p = DVec3::new(i as f64 * 0.37, i as f64 * 0.11, -(i as f64) * 0.23);
+            assert!(fbm(p, 6).abs() < 1.);
+        });
+    }
+    #[test]
+    fn test_worley() {
+        let (f1, f2) = worley(DVec3::new(0.5, 0.5, 0.5));
+        assert!(f1 <= f2);
+        assert!(f1 < 3f64.sqrt());
+    }
+}
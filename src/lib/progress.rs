@@ -0,0 +1,140 @@
+use std::{io::{self, Write}, sync::{Arc, atomic::{AtomicBool, Ordering}}, time::{Duration, Instant}};
+
+// how far a render is, handed to a ProgressObserver after every row
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Progress {
+    // camera samples taken and the most the render can take; adaptive
+    // renders usually finish well before done reaches total
+    pub done: u64,
+    pub total: u64,
+    pub elapsed: Duration,
+    // at the rate so far, none before anything was rendered
+    pub eta: Option<Duration>,
+    // rays traced per second of elapsed time, camera, bounce and shadow rays
+    pub rays_per_second: f64,
+}
+impl Progress {
+    pub fn fraction(&self) -> f64 {
+        match self.total {
+            0 => 1.,
+            total => self.done as f64 / total as f64,
+        }
+    }
+}
+// called from the render's threads, one at a time
+pub trait ProgressObserver: Send + Sync {
+    fn update(&mut self, progress: &Progress);
+    // once, when the render completes or is cancelled
+    fn finish(&mut self, _progress: &Progress) {}
+}
+// the progress bar on stdout
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TerminalBar {
+    pub width: usize,
+}
+impl Default for TerminalBar {
+    fn default() -> Self {
+        Self { width: 50 }
+    }
+}
+impl ProgressObserver for TerminalBar {
+    fn update(&mut self, progress: &Progress) {
+        let fraction = progress.fraction();
+        let pos = (self.width as f64 * fraction) as usize;
+        let bar: String = (0..self.width).map(|i| match i.cmp(&pos) {
+            std::cmp::Ordering::Less => '=',
+            std::cmp::Ordering::Equal => '>',
+            std::cmp::Ordering::Greater => ' ',
+        }).collect();
+        let eta = progress.eta.map_or("--".to_string(), |eta| format!("{}s", eta.as_secs()));
+        print!("[{}]{}% eta {} {:.0} rays/s\r", bar, (fraction * 100.) as i32, eta, progress.rays_per_second);
+        // a closed pipe is not worth failing the render over
+        let _ = io::stdout().flush();
+    }
+    fn finish(&mut self, _progress: &Progress) {
+        println!();
+    }
+}
+// reports nothing, what a scene starts with
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Silent;
+impl ProgressObserver for Silent {
+    fn update(&mut self, _progress: &Progress) {}
+}
+// stops a render from another thread. clones share the flag, the render
+// checks it between rows
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+#[allow(dead_code)]
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+// counts the work of one render call into Progress values
+#[derive(Debug, Clone, Copy)]
+pub struct ProgressTracker {
+    start: Instant,
+    // samples already there when the render started, e.g. from a checkpoint
+    resumed: u64,
+    done: u64,
+    total: u64,
+    rays: u64,
+}
+impl ProgressTracker {
+    pub fn new(done: u64, total: u64) -> Self {
+        Self { start: Instant::now(), resumed: done, done, total, rays: 0 }
+    }
+    pub fn add(&mut self, samples: u64, rays: u64) {
+        self.done += samples;
+        self.rays += rays;
+    }
+    pub fn progress(&self) -> Progress {
+        let elapsed = self.start.elapsed();
+        let this_run = self.done - self.resumed;
+        let eta = match this_run {
+            0 => None,
+            n => Some(elapsed.mul_f64(self.total.saturating_sub(self.done) as f64 / n as f64)),
+        };
+        let rays_per_second = match elapsed.as_secs_f64() {
+            s if s > 0. => self.rays as f64 / s,
+            _ => 0.,
+        };
+        Progress { done: self.done, total: self.total, elapsed, eta, rays_per_second }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use super::{ProgressTracker, CancelToken};
+
+    #[test]
+    fn test_tracker() {
+        let mut tracker = ProgressTracker::new(100, 400);
+        assert_eq!(tracker.progress().eta, None);
+        assert_eq!(tracker.progress().fraction(), 0.25);
+        thread::sleep(Duration::from_millis(20));
+        tracker.add(100, 5000);
+        let p = tracker.progress();
+        assert_eq!(p.done, 200);
+        // twice as long again for the two hundred samples left
+        let eta = p.eta.unwrap().as_secs_f64();
+        assert!((eta - 2. * p.elapsed.as_secs_f64()).abs() < 1e-6, "{:?}", p);
+        assert!(p.rays_per_second > 0. && p.rays_per_second <= 5000. / 0.02);
+        tracker.add(200, 0);
+        assert_eq!(tracker.progress().eta, Some(Duration::ZERO));
+    }
+    #[test]
+    fn test_cancel_token() {
+        let token = CancelToken::default();
+        let shared = token.clone();
+        assert!(!token.is_cancelled());
+        thread::spawn(move || shared.cancel()).join().unwrap();
+        assert!(token.is_cancelled());
+    }
+}
@@ -0,0 +1,137 @@
+use std::{fs::{self, File}, io::{self, Read, Write, BufReader, BufWriter}, path::{Path, PathBuf}, time::Duration};
+use glam::DVec3;
+
+use super::{PixelStats, GBuffer};
+
+// where and how often render_frame saves its progress. a render finding a
+// checkpoint for an image of its size picks up from it, the file is removed
+// once the render is complete
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checkpoint {
+    pub path: PathBuf,
+    // at most one save per interval, always after a whole pass
+    pub interval: Duration,
+}
+// everything a render has gathered after some number of passes over the image
+#[derive(Debug, Clone, PartialEq)]
+pub struct Accumulation {
+    pub width: usize,
+    pub height: usize,
+    pub passes: u32,
+    pub stats: Vec<PixelStats>,
+    // sums of the first hit features, see GBuffer
+    pub albedo: Vec<DVec3>,
+    pub normal: Vec<DVec3>,
+    pub depth: Vec<f64>,
+}
+const MAGIC: &[u8; 4] = b"RSCK";
+
+impl Accumulation {
+    pub fn new(width: usize, height: usize) -> Self {
+        let n = width * height;
+        Self { width, height, passes: 0, stats: vec![PixelStats::default(); n], albedo: vec![DVec3::ZERO; n], normal: vec![DVec3::ZERO; n], depth: vec![0.; n] }
+    }
+    // the frame buffer and feature buffers as averages so far
+    pub fn frame(&self) -> (Vec<DVec3>, GBuffer) {
+        let mut aux = GBuffer::new(self.width, self.height);
+        let frame = self.stats.iter().enumerate().map(|(m, s)| {
+            let n = s.n.max(1) as f64;
+            aux.albedo[m] = self.albedo[m] / n;
+            aux.normal[m] = self.normal[m] / n;
+            aux.depth[m] = self.depth[m] / n;
+            aux.samples[m] = s.n;
+            s.mean
+        }).collect();
+        (frame, aux)
+    }
+    // little endian, written to a temporary file first so a render killed
+    // while saving leaves the previous checkpoint intact
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let tmp = path.with_extension("tmp");
+        let mut w = BufWriter::new(File::create(&tmp)?);
+        w.write_all(MAGIC)?;
+        [self.width as u64, self.height as u64, self.passes as u64].iter().try_for_each(|v| w.write_all(&v.to_le_bytes()))?;
+        let vec3 = |w: &mut BufWriter<File>, v: DVec3| v.to_array().iter().try_for_each(|x| w.write_all(&x.to_le_bytes()));
+        (0..self.stats.len()).try_for_each(|m| {
+            w.write_all(&self.stats[m].n.to_le_bytes())?;
+            vec3(&mut w, self.stats[m].mean)?;
+            vec3(&mut w, self.stats[m].m2)?;
+            vec3(&mut w, self.albedo[m])?;
+            vec3(&mut w, self.normal[m])?;
+            w.write_all(&self.depth[m].to_le_bytes())
+        })?;
+        w.into_inner()?.sync_all()?;
+        fs::rename(tmp, path)
+    }
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut r = BufReader::new(File::open(path)?);
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a render checkpoint"));
+        }
+        let u64_ = |r: &mut BufReader<File>| -> io::Result<u64> {
+            let mut b = [0u8; 8];
+            r.read_exact(&mut b)?;
+            Ok(u64::from_le_bytes(b))
+        };
+        let (width, height, passes) = (u64_(&mut r)? as usize, u64_(&mut r)? as usize, u64_(&mut r)? as u32);
+        let f64_ = |r: &mut BufReader<File>| -> io::Result<f64> {
+            let mut b = [0u8; 8];
+            r.read_exact(&mut b)?;
+            Ok(f64::from_le_bytes(b))
+        };
+        let vec3 = |r: &mut BufReader<File>| -> io::Result<DVec3> { Ok(DVec3::new(f64_(r)?, f64_(r)?, f64_(r)?)) };
+        let mut acc = Accumulation::new(width, height);
+        acc.passes = passes;
+        (0..width * height).try_for_each(|m| -> io::Result<()> {
+            let mut n = [0u8; 4];
+            r.read_exact(&mut n)?;
+            acc.stats[m] = PixelStats { n: u32::from_le_bytes(n), mean: vec3(&mut r)?, m2: vec3(&mut r)? };
+            acc.albedo[m] = vec3(&mut r)?;
+            acc.normal[m] = vec3(&mut r)?;
+            acc.depth[m] = f64_(&mut r)?;
+            Ok(())
+        })?;
+        Ok(acc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use glam::DVec3;
+
+    use crate::lib::TempPath;
+
+    use super::Accumulation;
+
+    #[test]
+    fn test_checkpoint_round_trip() {
+        let mut acc = Accumulation::new(3, 2);
+        acc.passes = 7;
+        (0..6).for_each(|m| {
+            (0..=m).for_each(|k| acc.stats[m].add(DVec3::new(k as f64 * 0.1, 1. / (k + 1) as f64, -0.3)));
+            acc.albedo[m] = DVec3::splat(m as f64 / 3.);
+            acc.normal[m] = DVec3::Y;
+            acc.depth[m] = if m == 4 { f64::INFINITY } else { 2.5 * m as f64 };
+        });
+        let path = TempPath::new("round_trip.ck");
+        acc.save(&path).unwrap();
+        let loaded = Accumulation::load(&path).unwrap();
+        assert_eq!(loaded, acc);
+        // averages of the features over each pixel's samples
+        let (frame, aux) = loaded.frame();
+        assert_eq!(frame[5], acc.stats[5].mean);
+        assert_eq!(aux.samples[5], 6);
+        assert_eq!(aux.depth[5], 12.5 / 6.);
+    }
+    #[test]
+    fn test_not_a_checkpoint() {
+        let path = TempPath::new("not_a_checkpoint.ck");
+        fs::write(&*path, b"P6\n1 1\n255\n\0\0\0").unwrap();
+        assert!(Accumulation::load(&path).is_err());
+        assert!(Accumulation::load(&TempPath::new("missing.ck")).is_err());
+    }
+}
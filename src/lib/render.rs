@@ -1,18 +1,79 @@
-use std::{f64::consts::PI, mem::swap, io::{self, Write}, fs::File, usize};
+use std::{f64::consts::PI, mem::{self, swap}, io::{self, Write, BufWriter}, fs::{self, File}, cell::{Cell, RefCell}, time::Instant, path::Path, sync::{Arc, Mutex}, thread};
 use glam::{DVec3, DVec2};
-use rand::Rng;
 
-use super::{Object, Light, Scene, Material};
+use super::{Object, Light, Scene, ShadingPoint, MediumStack, Wavelengths, Medium, MediumEvent, GBuffer, PixelStats, DisplayTransform, Accumulation, ProgressTracker, RenderStats, RayKind, count_ray, count_intersection, count_depth, count_max_depth_reached, count_path, add_time, rays_traced, take_stats, merge_stats, ProgressObserver, Silent, Sampler, mix};
 
 pub struct HitPayload {
     pub tnear: f64,
     pub idx: usize,
     pub uv: DVec2,
     pub hit_obj: Box<dyn Object>,
+    // position in the scene's object list
+    pub obj_id: usize,
+}
+// what a path carries from one bounce to the next
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PathState {
+    pub media: MediumStack,
+    // spectral mode: the channels hold radiance at these wavelengths instead of rgb
+    pub wavelengths: Option<Wavelengths>,
+    // when in the shutter interval the path happens, see Scene::shutter
+    pub time: f64,
+    // the ray left a glossy surface that already took direct light from
+    // scene.get_light(), so emitters and a lit environment are not counted again
+    pub lit: bool,
+}
+impl PathState {
+    // an rgb quantity (reflectance, radiance) in the channels this path carries
+    pub fn channels(&self, rgb: DVec3) -> DVec3 {
+        match self.wavelengths {
+            Some(wl) => wl.spectrum_of(rgb),
+            None => rgb,
+        }
+    }
+}
+// what a camera ray brings back
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Integrator {
+    // cast_ray: direct light, specular reflection and refraction, media
+    #[default]
+    Whitted,
+    // the first hit's features (see GBuffer), for checking how a scene is set up
+    Albedo,
+    // mapped from [-1, 1] into [0, 1], black on a miss
+    Normal,
+    // 1 / (1 + distance), black on a miss
+    Depth,
+}
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ImageFormat {
+    // binary p6
+    #[default]
+    Ppm,
+    Png,
+}
+#[allow(dead_code)]
+impl ImageFormat {
+    // by the file extension
+    pub fn from_path(path: &Path) -> Option<ImageFormat> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "ppm" => Some(ImageFormat::Ppm),
+            "png" => Some(ImageFormat::Png),
+            _ => None,
+        }
+    }
 }
 pub fn deg2rad(deg: f64) -> f64 {
     deg * PI / 180.0
 }
+// any orthonormal basis (t, b, n) around a unit vector n
+pub fn coordinate_system(n: DVec3) -> (DVec3, DVec3) {
+    let helper = if n.x.abs() > 0.9 { DVec3::Y } else { DVec3::X };
+    let t = (helper - n * n.dot(helper)).normalize();
+    (t, n.cross(t))
+}
 pub fn reflect(iv: DVec3, n: DVec3) -> DVec3 {
     // iv + ov = 2k * n;
     // dot(iv, n) = |n| * k = k
@@ -55,167 +116,498 @@ pub fn fresnel(iv: DVec3, n: DVec3, ior: f64) -> f64 {
         }
     }
 }
-pub fn trace(light: Light, dir: DVec3, objects: &Vec<Box<dyn Object>>) -> Option<HitPayload> {
-    let tnear = f64::MAX;
-    let mut payload = None;
-    objects.into_iter().for_each(|obj| {
-        let (resk, tk, idxk, uvk) = obj.intersection(light, dir);
+// nearest hit at `time` within the shutter; objects whose (swept) bounds the
+// ray misses are not intersected at all
+pub fn trace(light: Light, dir: DVec3, objects: &Vec<Box<dyn Object>>, time: f64) -> Option<HitPayload> {
+    let mut tnear = f64::MAX;
+    let mut hit = None;
+    objects.iter().enumerate().for_each(|(id, obj)| {
+        if !obj.bounds().hit(light.org, dir, tnear) {
+            return;
+        }
+        let (resk, tk, idxk, uvk) = obj.intersection_at(light.clone(), dir, time);
+        count_intersection(obj.kind(), resk);
         if resk && tk < tnear {
-            let _res = payload.insert(HitPayload {
-                tnear: tk,
-                idx: idxk,
-                uv: uvk,
-                hit_obj: obj.clone(),
-            });
+            tnear = tk;
+            hit = Some((tk, idxk, uvk, id));
         }
     });
-    payload
+    // only the nearest object is posed and copied
+    hit.map(|(tk, idxk, uvk, id)| HitPayload {
+        tnear: tk,
+        idx: idxk,
+        uv: uvk,
+        hit_obj: objects[id].at_time(time),
+        obj_id: id,
+    })
 }
-#[allow(dead_code)]
+// the camera sample the numbers of this thread are drawn for
+struct SampleContext {
+    sampler: Arc<dyn Sampler>,
+    pixel: (u32, u32),
+    index: u32,
+    // numbers drawn so far
+    dimension: u32,
+}
+thread_local! {
+    static SAMPLE: RefCell<Option<SampleContext>> = const { RefCell::new(None) };
+    static RNG: Cell<u64> = const { Cell::new(0) };
+}
+// the next number of the current camera sample, see start_sample, or of this
+// thread's splitmix64 sequence outside of one
 pub fn get_random_float() -> f64 {
-    let mut rng = rand::thread_rng();
-    let res: f64 = rng.gen();
-    res
-    // todo!()
-}
-pub fn update_progress(progress: f64) {
-    let bar_width = 50;
-    print!("[");
-    let pos = (bar_width as f64 * progress + 1.) as i32;
-    (0..bar_width).for_each(|i| {
-        if i < pos {
-            print!("=");
-        } else if i == pos {
-            print!(">");
-        } else {
-            print!(" ");
+    let sampled = SAMPLE.with(|sample| sample.borrow_mut().as_mut().map(|s| {
+        s.dimension += 1;
+        s.sampler.get_1d(s.pixel, s.index, s.dimension - 1)
+    }));
+    sampled.unwrap_or_else(|| RNG.with(|state| {
+        let s = state.get().wrapping_add(0x9e3779b97f4a7c15);
+        state.set(s);
+        // top 53 bits, uniform in [0, 1)
+        (mix(s) >> 11) as f64 / (1u64 << 53) as f64
+    }))
+}
+// the next two numbers as a point, stratified together by samplers that can
+pub fn get_random_vec2() -> DVec2 {
+    let sampled = SAMPLE.with(|sample| sample.borrow_mut().as_mut().map(|s| {
+        s.dimension += 2;
+        s.sampler.get_2d(s.pixel, s.index, s.dimension - 2)
+    }));
+    sampled.unwrap_or_else(|| DVec2::new(get_random_float(), get_random_float()))
+}
+// get_random_float draws from sampler for camera sample index of pixel from
+// here on, starting at its first dimension
+pub fn start_sample(sampler: &Arc<dyn Sampler>, pixel: (u32, u32), index: u32) {
+    SAMPLE.with(|sample| *sample.borrow_mut() = Some(SampleContext { sampler: sampler.clone(), pixel, index, dimension: 0 }));
+}
+// restarts this thread's get_random_float sequence outside of a camera
+// sample, the same seed gives the same numbers
+#[allow(dead_code)]
+pub fn seed_random(seed: u64) {
+    SAMPLE.with(|sample| *sample.borrow_mut() = None);
+    RNG.with(|state| state.set(seed));
+}
+// the scattering medium a path is travelling through, in the path's channels:
+// the one of the interior it is in, the scene's fog outside of everything
+fn segment_medium(scene: &Scene, state: &PathState) -> Option<Medium> {
+    let medium = match state.media.current() {
+        Some((_, interior)) => interior.medium,
+        None => scene.fog.clone(),
+    }?;
+    Some(Medium { sigma_a: state.channels(medium.sigma_a), sigma_s: state.channels(medium.sigma_s), ..(*medium).clone() })
+}
+// what arrives at org + dir * max_t from org. passthrough surfaces and those the
+// medium stack ignores let the light through, anything else blocks it
+pub fn shadow_transmittance(scene: &Scene, org: DVec3, dir: DVec3, max_t: f64, state: &PathState) -> DVec3 {
+    count_ray(RayKind::Shadow);
+    let (mut org, mut max_t, mut state) = (org, max_t, state.clone());
+    let mut tr = DVec3::ONE;
+    loop {
+        let hit = trace(Light { org, inten: DVec3::ZERO, ies: None }, dir, scene.get_obj(), state.time);
+        let t = hit.as_ref().map_or(f64::INFINITY, |h| h.tnear).min(max_t);
+        tr *= state.channels(state.media.transmittance(t));
+        if let Some(medium) = segment_medium(scene, &state) {
+            tr *= medium.transmittance(org, dir, t, get_random_float);
         }
+        let Some(payload) = hit.filter(|h| h.tnear < max_t) else {
+            return tr;
+        };
+        let p = org + dir * payload.tnear;
+        let (n, _) = payload.hit_obj.get_surface_properties(p, dir, payload.idx, payload.uv);
+        let bsdf = payload.hit_obj.get_bsdf();
+        let entering = dir.dot(n) < 0.;
+        state = match bsdf.interior() {
+            Some(i) if bsdf.is_passthrough() || state.media.is_false_hit(payload.obj_id, &i, entering) => match entering {
+                true => PathState { media: state.media.entered(payload.obj_id, i), ..state },
+                false => PathState { media: state.media.left(payload.obj_id), ..state },
+            },
+            _ => return DVec3::ZERO,
+        };
+        org = match entering {
+            true => p - n * scene.epsilon,
+            false => p + n * scene.epsilon,
+        };
+        max_t -= payload.tnear;
+    }
+}
+// single scattering: light from scene.get_light() reaching p inside a medium
+fn in_scattered(scene: &Scene, medium: &Medium, p: DVec3, wo: DVec3, state: &PathState) -> DVec3 {
+    let mut l = DVec3::ZERO;
+    scene.get_light().iter().for_each(|li| {
+        let n_samples = if li.is_delta() { 1 } else { scene.shadow_samples.max(1) };
+        (0..n_samples).for_each(|_| {
+            if let Some(ls) = li.sample_li(p, get_random_vec2()) {
+                let phase = medium.phase.eval(wo, ls.dir);
+                let max_t = ls.distance * (1. - 1e-6) - scene.epsilon;
+                let tr = shadow_transmittance(scene, p, ls.dir, max_t, state);
+                l += phase * state.channels(ls.radiance) * tr / n_samples as f64;
+            }
+        });
     });
-    print!("]{}%\r", (progress * 100. + 1.) as i32);
-    io::stdout().flush().unwrap();
+    l
 }
-pub fn cast_ray(light: Light, dir: DVec3, scene: &mut Scene, depth: i32) -> DVec3 {
+pub fn cast_ray(light: Light, dir: DVec3, scene: &Scene, depth: i32, state: &PathState) -> DVec3 {
     if depth > scene.max_depth.into() {
+        count_max_depth_reached();
         return DVec3::new(0., 0., 0.);
     }
-    let mut hit_color = scene.background_color;
-    if let Some(payload) = trace(light, dir, scene.get_obj()) {
+    count_depth(depth as u64);
+    let hit = trace(light.clone(), dir, scene.get_obj(), state.time);
+    let distance = hit.as_ref().map_or(f64::INFINITY, |h| h.tnear);
+    // beer-lambert over the segment, through whatever interior the ray is in
+    let mut segment = state.channels(state.media.transmittance(distance));
+    // a scattering medium may stop the ray before it gets to the surface
+    if let Some(medium) = segment_medium(scene, state) {
+        match medium.sample(light.org, dir, distance, get_random_float) {
+            MediumEvent::Scattered { t, weight } => {
+                let p = light.org + dir * t;
+                let absorbed = state.channels(state.media.transmittance(t));
+                return weight * absorbed * in_scattered(scene, &medium, p, -dir, state);
+            }
+            MediumEvent::Passed { weight } => segment *= weight,
+        }
+    }
+    let mut hit_color = match state.lit && scene.get_environment().is_some() {
+        true => DVec3::ZERO,
+        false => state.channels(scene.background(dir)),
+    };
+    if let Some(payload) = hit {
         let hit_point = light.org + dir * payload.tnear;
         let (n, st) = payload.hit_obj.get_surface_properties(hit_point, dir, payload.idx, payload.uv);
-        match payload.hit_obj.get_material_properties() {
-            Material::ReflectionAndRefraction => {
-                let reflect_dir = reflect(dir, n).normalize();
-                let refract_dir = refract(dir, n, payload.hit_obj.get_ior()).normalize();
-                let reflect_ray_org = match reflect_dir.dot(n) < 0. {
-                    true => hit_point - n * scene.epsilon,
-                    false => hit_point + n * scene.epsilon,
-                };
-                let refract_ray_org = match reflect_ray_org.dot(n) < 0. {
-                    true => hit_point - n * scene.epsilon,
-                    false => hit_point + n * scene.epsilon,
-                };
-                let reflect_color = cast_ray(Light { org: reflect_ray_org, inten: light.inten }, reflect_dir, scene, depth + 1);
-                let refract_color = cast_ray(Light { org: refract_ray_org, inten: light.inten }, refract_dir, scene, depth + 1);
-                let kr = fresnel(dir, n, payload.hit_obj.get_ior());
-                hit_color = reflect_color * kr + refract_color * (1. - kr);
-            },
-            Material::Reflection => {
-                let kr = fresnel(dir, n, payload.hit_obj.get_ior());
-                let reflect_dir = reflect(dir, n);
-                let reflect_ray_org = match reflect_dir.dot(n) < 0. {
-                    true => hit_point + n * scene.epsilon,
-                    false => hit_point - n * scene.epsilon,
-                };
-                hit_color = cast_ray(Light { org: reflect_ray_org, inten: light.inten }, reflect_dir, scene, depth + 1) * kr;
-            },
-            _ => {
-                let mut light_amt = DVec3::ZERO;let mut specular_color = DVec3::ZERO;
-                let shadow_org = match dir.dot(n) < 0. {
-                    true => hit_point + n * scene.epsilon,
-                    false => hit_point - n * scene.epsilon,
+        // n offsets secondary rays off the surface, ns does the shading
+        let (dpdu, dpdv) = payload.hit_obj.get_tangents(hit_point, payload.idx, payload.uv);
+        let ns = match payload.hit_obj.get_normal_map() {
+            Some(map) => map.perturb(n, dpdu, dpdv, st, hit_point),
+            None => n,
+        };
+        // emitters are seen directly by camera and specular rays; diffuse and glossy surfaces pick
+        // them up through their light in scene.get_light(), so nothing is counted twice
+        let emitted = match payload.hit_obj.get_emission() {
+            _ if state.lit => DVec3::ZERO,
+            Some(e) if e.two_sided || dir.dot(n) < 0. => e.radiance,
+            _ => DVec3::ZERO,
+        };
+        let bsdf = payload.hit_obj.get_bsdf();
+        let wo = -dir;
+        // offset along the geometric normal to the side the new ray leaves from
+        let eps = scene.epsilon;
+        let offset = |wi: DVec3| match wi.dot(n) < 0. {
+            true => hit_point - n * eps,
+            false => hit_point + n * eps,
+        };
+        let id = payload.obj_id;
+        let entering = dir.dot(n) < 0.;
+        let interior = bsdf.interior();
+        // the state on the far side of this surface
+        let crossed = |base: &PathState| match (&interior, entering) {
+            (Some(i), true) => PathState { media: base.media.entered(id, i.clone()), ..base.clone() },
+            (Some(_), false) => PathState { media: base.media.left(id), ..base.clone() },
+            (None, _) => base.clone(),
+        };
+        if let Some(i) = &interior {
+            // volume boundaries and surfaces the stack ignores are not bounces
+            if bsdf.is_passthrough() || state.media.is_false_hit(id, i, entering) {
+                let through = cast_ray(Light { org: offset(dir), inten: light.inten, ies: None }, dir, scene, depth, &crossed(state));
+                return through * segment;
+            }
+        }
+        // a dispersive surface sends every wavelength its own way, only the hero
+        // is followed from here and stands in for the other two
+        let (here, mask) = match state.wavelengths {
+            Some(wl) if bsdf.is_dispersive() && !wl.terminated => (PathState { wavelengths: Some(wl.terminate()), lit: false, ..state.clone() }, DVec3::new(3., 0., 0.)),
+            _ => (PathState { lit: false, ..state.clone() }, DVec3::ONE),
+        };
+        let sp = ShadingPoint { p: hit_point, n: ns, st, dpdu, outside_ior: state.media.outside_ior(id, entering), wavelength: here.wavelengths.map(|wl| wl.hero()) };
+        hit_color = DVec3::ZERO;
+        bsdf.delta_lobes(&sp, wo).into_iter().for_each(|(wi, weight)| {
+            let (next, kind) = match wi.dot(n) * dir.dot(n) > 0. {
+                true => (crossed(&here), RayKind::Refraction),
+                false => (here.clone(), RayKind::Reflection),
+            };
+            count_ray(kind);
+            hit_color += here.channels(weight) * cast_ray(Light { org: offset(wi), inten: light.inten, ies: None }, wi, scene, depth + 1, &next);
+        });
+        if !bsdf.is_delta() {
+            scene.get_light().iter().for_each(|li| {
+                let n_samples = if li.is_delta() { 1 } else { scene.shadow_samples.max(1) };
+                (0..n_samples).for_each(|_| {
+                    if let Some(ls) = li.sample_li(hit_point, get_random_vec2()) {
+                        let f = bsdf.eval(&sp, wo, ls.dir);
+                        if f == DVec3::ZERO {
+                            return;
+                        }
+                        // transmissive bsdfs can see lights on the far side
+                        let through = match ls.dir.dot(n) * dir.dot(n) > 0. {
+                            true => crossed(&here),
+                            false => here.clone(),
+                        };
+                        // leave room for the emitter itself being hit at the sampled point
+                        let max_t = ls.distance * (1. - 1e-6) - 2. * scene.epsilon;
+                        let tr = shadow_transmittance(scene, offset(ls.dir), ls.dir, max_t, &through);
+                        hit_color += here.channels(f) * here.channels(ls.radiance) * tr * ls.dir.dot(ns).abs() / n_samples as f64;
+                    }
+                });
+            });
+        }
+        // a rough lobe is too narrow for direct light alone to show what it
+        // reflects, one sampled ray picks up the rest of the scene
+        if bsdf.is_glossy() {
+            if let Some(bs) = bsdf.sample(&sp, wo, get_random_float(), get_random_vec2()) {
+                let (next, kind) = match bs.wi.dot(n) * dir.dot(n) > 0. {
+                    true => (crossed(&here), RayKind::Refraction),
+                    false => (here.clone(), RayKind::Reflection),
                 };
-                scene.get_light().iter().for_each(|li| {
-                    let mut light_dir = li.org - hit_point;
-                    let light_distance = light_dir.dot(light_dir);
-                    light_dir = light_dir.normalize();
-                    let ldn = light_dir.dot(n).max(0.);
-                    let shadow_res = trace(Light { org: shadow_org, inten: light.inten }, light_dir, scene.get_obj());
-                    light_amt += match shadow_res.is_some() && (shadow_res.unwrap().tnear.powf(2.) < light_distance) {
-                        true => DVec3::ZERO,
-                        false => li.inten * ldn,
-                    };
-                    let reflect_dir = reflect(-light_dir, n);
-                    specular_color += f64::powf(-reflect_dir.dot(dir).max(0.), payload.hit_obj.get_specular_properties().0) * li.inten;
-                    hit_color = light_amt * payload.hit_obj.eval_diffuse_color(st) * payload.hit_obj.get_specular_properties().1 + specular_color * payload.hit_obj.get_specular_properties().2;
-                })
+                count_ray(kind);
+                let weight = bs.f * bs.wi.dot(ns).abs() / bs.pdf;
+                hit_color += here.channels(weight) * cast_ray(Light { org: offset(bs.wi), inten: light.inten, ies: None }, bs.wi, scene, depth + 1, &PathState { lit: true, ..next });
             }
         }
+        hit_color = hit_color * mask + state.channels(emitted);
+    }
+    hit_color * segment
+}
+// albedo, normal and distance of the first surface along a camera ray, see GBuffer
+fn first_hit(scene: &Scene, org: DVec3, dir: DVec3, time: f64) -> (DVec3, DVec3, f64) {
+    match trace(Light { org, inten: DVec3::ZERO, ies: None }, dir, scene.get_obj(), time) {
+        Some(payload) => {
+            let p = org + dir * payload.tnear;
+            let (n, st) = payload.hit_obj.get_surface_properties(p, dir, payload.idx, payload.uv);
+            let albedo = match payload.hit_obj.get_bsdf().is_delta() {
+                true => DVec3::ONE,
+                false => payload.hit_obj.eval_diffuse_color(st, p),
+            };
+            (albedo, n, payload.tnear)
+        }
+        None => (DVec3::ONE, DVec3::ZERO, f64::INFINITY),
     }
-    hit_color
 }
-pub fn render(scene: &mut Scene) {
-    let is: usize = (scene.width * scene.height) as usize;
-    let mut frame_buffer: Vec<DVec3> = vec![DVec3::ZERO; is];
+// one camera ray through pixel (i, j) at (jx, jy) inside it: what
+// scene.integrator makes of it in rgb and the first hit's albedo, normal and
+// distance
+fn camera_sample(scene: &Scene, i: usize, j: usize, jx: f64, jy: f64) -> (DVec3, (DVec3, DVec3, f64)) {
     let img_rto = scene.width as f64 / scene.height as f64;
     let scale = deg2rad(scene.fov * 0.5).tan();
     let eye_pos = DVec3::ZERO;
-    let mut m: usize = 0;
-    for j in 0..scene.height as usize {
-        for i in 0..scene.width as usize {
-            let x = ((i as f64 + 0.5) * 2. / scene.width as f64 - 1.) * scale * img_rto;
-            let y = ((j as f64 + 0.5) * 2. / scene.height as f64 - 1.) * -scale;
-            let dir = DVec3::new(x, y, -1.).normalize(); 
-            //camera org: 0,0,0   dir = (x,y,-1).normalize()
-            frame_buffer[m] = cast_ray(Light {org: eye_pos, inten: DVec3::ZERO}, dir, scene, 0);
-            m += 1;
-        }
-        update_progress(j as f64 / scene.height as f64);
-    }
-    println!("");
-    let mut fp = match File::create("binary.ppm") {
-        Ok(fp) => fp,
-        Err(e) => panic!("Failed to create file: {}", e),
+    let x = ((i as f64 + jx) * 2. / scene.width as f64 - 1.) * scale * img_rto;
+    let y = ((j as f64 + jy) * 2. / scene.height as f64 - 1.) * -scale;
+    let dir = DVec3::new(x, y, -1.).normalize(); 
+    //camera org: 0,0,0   dir = (x,y,-1).normalize()
+    let (open, close) = scene.shutter;
+    let time = open + (close - open) * get_random_float();
+    count_ray(RayKind::Primary);
+    let (albedo, n, d) = first_hit(scene, eye_pos, dir, time);
+    let color = match scene.integrator {
+        Integrator::Whitted => {
+            let state = PathState { wavelengths: scene.spectral.then(|| Wavelengths::sample(get_random_float())), time, ..PathState::default() };
+            let l = cast_ray(Light { org: eye_pos, inten: DVec3::ZERO, ies: None }, dir, scene, 0, &state);
+            match state.wavelengths {
+                Some(wl) => wl.to_rgb(l),
+                None => l,
+            }
+        }
+        Integrator::Albedo => albedo,
+        Integrator::Normal if n == DVec3::ZERO => DVec3::ZERO,
+        Integrator::Normal => n * 0.5 + 0.5,
+        Integrator::Depth => DVec3::splat(1. / (1. + d)),
     };
-    let s = format!("P6\n{} {}\n255\n", scene.width, scene.height);
-    fp.write_all(s.as_bytes()).unwrap();
-    frame_buffer.iter().for_each(|v| {
-        let ss: &[u8] = &[(v.x.clamp(0., 1.) * 255.) as u8, (v.y.clamp(0., 1.) * 255.) as u8, (v.z.clamp(0., 1.) * 255.) as u8];
-        match fp.write_all(ss) {
-            Ok(_) => {},
-            Err(_) => panic!("Failed to write frame_buffer"),
-        };
-    })
-    
+    count_path();
+    (color, (albedo, n, d))
+}
+fn is_converged(scene: &Scene, stats: &PixelStats) -> bool {
+    match scene.adaptive {
+        Some(adaptive) => adaptive.is_done(stats),
+        None => stats.n >= scene.spp.max(1),
+    }
+}
+// what the threads of a pass share: progress so far, the observer told
+// after every row, whether anything was sampled and the threads' statistics
+struct PassShared<'a> {
+    tracker: &'a mut ProgressTracker,
+    progress: Box<dyn ProgressObserver>,
+    sampled: bool,
+    stats: RenderStats,
+}
+// one more camera ray through every pixel that still needs samples, false
+// once there are none or scene.cancel stops it between rows. rows are shared
+// out over scene.threads threads; every sample has its own random sequence,
+// so the result doesn't depend on the thread count or on whether the passes
+// ran in one go
+pub fn render_pass(scene: &mut Scene, acc: &mut Accumulation, tracker: &mut ProgressTracker) -> bool {
+    let width = scene.width as usize;
+    let progress = mem::replace(&mut scene.progress, Box::new(Silent));
+    let shared = Mutex::new(PassShared { tracker, progress, sampled: false, stats: RenderStats::default() });
+    let scene_ref: &Scene = scene;
+    let rows = acc.stats.chunks_mut(width).zip(acc.albedo.chunks_mut(width)).zip(acc.normal.chunks_mut(width)).zip(acc.depth.chunks_mut(width)).enumerate();
+    let rows = Mutex::new(rows);
+    thread::scope(|s| (0..scene_ref.threads.max(1)).for_each(|_| {
+        let (rows, shared) = (&rows, &shared);
+        s.spawn(move || {
+            loop {
+                if scene_ref.cancel.is_cancelled() {
+                    break;
+                }
+                let next = rows.lock().unwrap().next();
+                let Some((row, (((stats, albedo), normal), depth))) = next else {
+                    break;
+                };
+                let (rays, mut samples) = (rays_traced(), 0);
+                for i in 0..width {
+                    if is_converged(scene_ref, &stats[i]) {
+                        continue;
+                    }
+                    start_sample(&scene_ref.sampler, (i as u32, row as u32), stats[i].n);
+                    // a single sample stays in the pixel center
+                    let (jx, jy) = match (scene_ref.spp, scene_ref.adaptive) {
+                        (0 | 1, None) => (0.5, 0.5),
+                        _ => get_random_vec2().into(),
+                    };
+                    let (color, (a, n, d)) = camera_sample(scene_ref, i, row, jx, jy);
+                    stats[i].add(color);
+                    albedo[i] += a;
+                    normal[i] += n;
+                    depth[i] += d;
+                    samples += 1;
+                }
+                if samples > 0 {
+                    let mut shared = shared.lock().unwrap();
+                    shared.tracker.add(samples, rays_traced() - rays);
+                    let progress = shared.tracker.progress();
+                    shared.progress.update(&progress);
+                    shared.sampled = true;
+                }
+            }
+            shared.lock().unwrap().stats.merge(&take_stats());
+        });
+    }));
+    let shared = shared.into_inner().unwrap();
+    merge_stats(&shared.stats);
+    scene.progress = shared.progress;
+    let sampled = shared.sampled && !scene.cancel.is_cancelled();
+    acc.passes += sampled as u32;
+    sampled
+}
+// the frame buffer with the denoiser's feature buffers, before any denoising.
+// resumes from and saves to scene.checkpoint when there is one. a cancelled
+// render returns what it has and leaves its checkpoint behind. counts the
+// render statistics from zero, take_stats hands them out
+pub fn render_frame(scene: &mut Scene) -> io::Result<(Vec<DVec3>, GBuffer)> {
+    take_stats();
+    let start = Instant::now();
+    let (width, height) = (scene.width as usize, scene.height as usize);
+    let checkpoint = scene.checkpoint.clone();
+    // only a missing checkpoint starts afresh, anything else there is not ours to overwrite
+    let mut acc = match checkpoint.as_ref().map(|c| (c, Accumulation::load(&c.path))) {
+        None => Accumulation::new(width, height),
+        Some((_, Err(e))) if e.kind() == io::ErrorKind::NotFound => Accumulation::new(width, height),
+        Some((c, Err(e))) => return Err(io::Error::new(e.kind(), format!("checkpoint {}: {}", c.path.display(), e))),
+        Some((_, Ok(acc))) if acc.width == width && acc.height == height => acc,
+        Some((c, Ok(acc))) => return Err(io::Error::new(io::ErrorKind::InvalidData, format!(
+            "checkpoint {} is for a {}x{} image, not {}x{}", c.path.display(), acc.width, acc.height, width, height
+        ))),
+    };
+    let spp = scene.adaptive.map_or(scene.spp, |a| a.max_spp).max(1) as u64;
+    let done = acc.stats.iter().map(|s| s.n as u64).sum();
+    let mut tracker = ProgressTracker::new(done, (width * height) as u64 * spp);
+    add_time(|t| &mut t.prep, start.elapsed());
+    let start = Instant::now();
+    let mut saved = Instant::now();
+    while render_pass(scene, &mut acc, &mut tracker) {
+        if let Some(c) = checkpoint.as_ref().filter(|c| saved.elapsed() >= c.interval) {
+            acc.save(&c.path)?;
+            saved = Instant::now();
+        }
+    }
+    add_time(|t| &mut t.rendering, start.elapsed());
+    scene.progress.finish(&tracker.progress());
+    if let Some(c) = &checkpoint {
+        match scene.cancel.is_cancelled() {
+            true => acc.save(&c.path)?,
+            false => { let _ = fs::remove_file(&c.path); }
+        }
+    }
+    Ok(acc.frame())
+}
+// the image as display encoded rgb bytes, denoised when the scene asks for it
+fn encoded(scene: &Scene, frame_buffer: Vec<DVec3>, aux: &GBuffer) -> Vec<u8> {
+    let frame_buffer = match scene.denoise {
+        Some(denoiser) => denoiser.denoise(&frame_buffer, aux),
+        None => frame_buffer,
+    };
+    scene.display.encode(&frame_buffer, scene.width as usize)
+}
+// render without writing anything, the rgb bytes render would write
+#[allow(dead_code)]
+pub fn render_to_memory(scene: &mut Scene) -> io::Result<Vec<u8>> {
+    let (frame_buffer, aux) = render_frame(scene)?;
+    Ok(encoded(scene, frame_buffer, &aux))
+}
+pub fn write_image(path: &Path, format: ImageFormat, width: i32, height: i32, bytes: &[u8]) -> io::Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+    match format {
+        ImageFormat::Ppm => {
+            write!(w, "P6\n{} {}\n255\n", width, height)?;
+            w.write_all(bytes)?;
+        }
+        ImageFormat::Png => {
+            let mut encoder = png::Encoder::new(&mut w, width as u32, height as u32);
+            encoder.set_color(png::ColorType::Rgb);
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder.write_header().and_then(|mut png| png.write_image_data(bytes)).map_err(io::Error::other)?;
+        }
+    }
+    w.flush()
+}
+// writes the image to scene.output, and the adaptive sample counts next to
+// it with _samples added to the name. the statistics' Display is a summary
+// table
+pub fn render(scene: &mut Scene) -> io::Result<RenderStats> {
+    let (frame_buffer, aux) = render_frame(scene)?;
+    // nothing is written for a cancelled render
+    if scene.cancel.is_cancelled() {
+        return Ok(take_stats());
+    }
+    let start = Instant::now();
+    let width = scene.width as usize;
+    write_image(&scene.output, scene.format, scene.width, scene.height, &encoded(scene, frame_buffer, &aux))?;
+    if let Some(adaptive) = scene.adaptive {
+        let heatmap = adaptive.heatmap(&aux.samples);
+        let mut name = scene.output.file_stem().unwrap_or_default().to_os_string();
+        name.push("_samples");
+        let path = scene.output.with_file_name(name).with_extension(scene.output.extension().unwrap_or_default());
+        write_image(&path, scene.format, scene.width, scene.height, &DisplayTransform::default().encode(&heatmap, width))?;
+    }
+    add_time(|t| &mut t.output, start.elapsed());
+    Ok(take_stats())
 }
 
 #[cfg(test)]
 mod tests {
 
-    use std::{fs::File, io::Write};
+    use std::{fs::{self, File}, io::{self, Write}, path::Path, sync::{Arc, Mutex}, time::Duration};
 
     use glam::{DVec3, DVec2};
 
-    use crate::lib::{Scene, MeshTriangle, Triangle, Sphere, ObjectAppend, Light, SpecularProperties, Material, LightAppend};
+    use crate::lib::{Scene, MeshTriangle, Triangle, Sphere, ObjectAppend, Light, SpecularProperties, Material, LightAppend, Texture, Emission, Dielectric, Conductor, Dispersion, Wavelengths, Medium, MediumBoundary, Keyframes, Adaptive, SphereLight, Accumulation, Checkpoint, TempPath, diffuse_ball, ProgressTracker, ProgressObserver, Progress, CancelToken, Golden, encoded_image, SAMPLERS, sampler_by_name, builtin_scene};
 
-    use super::{trace, render};
+    use super::{trace, render, render_to_memory, render_frame, render_pass, cast_ray, shadow_transmittance, PathState};
 
     #[test]
     fn test_trace() {
         let mut sc = Scene::create();
         let tri = MeshTriangle { vertices: vec![Triangle { 
             v0: DVec3::ZERO, v1: DVec3::ZERO, v2: DVec3::ZERO, s0: DVec2::ZERO, s1: DVec2::ZERO, s2: DVec2::ZERO 
-        }], material: crate::lib::Material::DiffuseAndGlossy, ior: 1.3, specular: SpecularProperties(25.0, 0.8, 0.2)};
-        let sp = Sphere { center: DVec3::new(2., 0., 0.), radius: 1.6, radius2: 2.56, material: crate::lib::Material::DiffuseAndGlossy, ior: 1.3, specular: SpecularProperties(25.0, 0.8, 0.2), diffuse_color: DVec3::splat(0.2)};
+        }], material: crate::lib::Material::DiffuseAndGlossy, ior: 1.3, specular: SpecularProperties(25.0, 0.8, 0.2), diffuse: Texture::checker(), ..Default::default() };
+        let sp = Sphere { center: DVec3::new(2., 0., 0.), radius: 1.6, radius2: 2.56, material: crate::lib::Material::DiffuseAndGlossy, ior: 1.3, specular: SpecularProperties(25.0, 0.8, 0.2), diffuse: Texture::Constant(DVec3::splat(0.2)), ..Default::default() };
         ObjectAppend::append(&mut sc, Box::new(sp));
         ObjectAppend::append(&mut sc, Box::new(tri));
-        let light = Light { org: DVec3::new(0., 0., 0.), inten: DVec3::new(0.88, 0.42, 0.)};
+        let light = Light { org: DVec3::new(0., 0., 0.), inten: DVec3::new(0.88, 0.42, 0.), ies: None };
         let dir = DVec3::new(0.88, 0.42, 0.);
-        let payload = trace(light, dir, sc.get_obj());
+        let payload = trace(light, dir, sc.get_obj(), 0.);
         dbg!(payload.is_some());
     }
     #[test]
     fn test_render(){
-        let mut sc = Scene::window(1280, 960);
+        let mut sc = Scene::window(160, 120);
         let sph1 = MeshTriangle {
             vertices: vec![
                 Triangle { v0: DVec3::new(5., -3., -6.), v1: DVec3::new(5., -3., -16.), v2: DVec3::new(-5., -3., -16.), s0: DVec2::new(0.8, 0.), s1: DVec2::new(0., 0.8), s2: DVec2::new(1., 1.) }
@@ -223,10 +615,273 @@ mod tests {
             material: Material::DiffuseAndGlossy,
             ior: 1.3,
             specular: SpecularProperties(25.0, 0.8, 0.2),
+            diffuse: Texture::checker(),
+            ..Default::default()
         };
         ObjectAppend::append(&mut sc, Box::new(sph1));
-        LightAppend::append(&mut sc, Light { org: DVec3::new(3., 3., 5.), inten: DVec3::splat(0.5)});
-        render(&mut sc);
+        // bright enough for the checker to show across the whole floor, neither black nor clipped
+        LightAppend::append(&mut sc, Light { org: DVec3::new(3., 3., 5.), inten: DVec3::splat(1500.), ies: None });
+        let img = encoded_image(160, 120, &render_to_memory(&mut sc).unwrap());
+        if let Err(e) = Golden::default().check("checker_floor", &img) {
+            panic!("{}", e);
+        }
+    }
+    #[test]
+    fn test_emission() {
+        let mut sc = Scene::create();
+        let lamp = Sphere { emission: Some(Emission { radiance: DVec3::new(4., 2., 1.), two_sided: false }), ..diffuse_ball(DVec3::new(0., 0., -5.), 1., DVec3::ZERO) };
+        ObjectAppend::append(&mut sc, Box::new(lamp));
+        // the emitter lights the scene as well as being seen
+        assert_eq!(sc.get_light().len(), 1);
+        let eye = Light { org: DVec3::ZERO, inten: DVec3::ONE, ies: None };
+        assert_eq!(cast_ray(eye, -DVec3::Z, &sc, 0, &PathState::default()), DVec3::new(4., 2., 1.));
+    }
+    #[test]
+    fn test_glossy_whitted() {
+        // no lights at all, a rough conductor can only show the red background it reflects
+        let mut sc = Scene::create();
+        sc.background_color = DVec3::X;
+        let ball = Sphere { material: Material::Custom(Arc::new(Conductor::silver(0.2))), ..diffuse_ball(DVec3::new(0., 0., -5.), 1., DVec3::ZERO) };
+        ObjectAppend::append(&mut sc, Box::new(ball));
+        let eye = Light { org: DVec3::ZERO, inten: DVec3::ONE, ies: None };
+        let n = 256;
+        let l = (0..n).fold(DVec3::ZERO, |l, _| l + cast_ray(eye.clone(), -DVec3::Z, &sc, 0, &PathState::default())) / n as f64;
+        assert!(l.x > 0.5 && l.y == 0. && l.z == 0., "{}", l);
+        // an emitter the glossy surface already sampled as a light is not counted again
+        let mut sc = Scene::create();
+        let lamp = Sphere { emission: Some(Emission { radiance: DVec3::ONE, two_sided: false }), ..diffuse_ball(DVec3::new(0., 0., -5.), 1., DVec3::ZERO) };
+        ObjectAppend::append(&mut sc, Box::new(lamp));
+        assert_eq!(cast_ray(eye, -DVec3::Z, &sc, 1, &PathState { lit: true, ..PathState::default() }), DVec3::ZERO);
+    }
+    #[test]
+    fn test_colored_and_nested_glass() {
+        let ball = |radius: f64, bsdf: Dielectric| Sphere { material: Material::Custom(Arc::new(bsdf)), ior: 1., ..diffuse_ball(DVec3::new(0., 0., -10.), radius, DVec3::ZERO) };
+        let look = |balls: Vec<Sphere>| {
+            let mut sc = Scene::create();
+            sc.background_color = DVec3::ONE;
+            balls.into_iter().for_each(|b| ObjectAppend::append(&mut sc, Box::new(b)));
+            cast_ray(Light { org: DVec3::ZERO, inten: DVec3::ONE, ies: None }, -DVec3::Z, &sc, 0, &PathState::default())
+        };
+        let tinted = Dielectric { ior: 1.5, absorption: DVec3::new(0., 0.3, 0.6), priority: 1, dispersion: None, medium: None };
+        let thin = look(vec![ball(0.5, tinted.clone())]);
+        let thick = look(vec![ball(2., tinted.clone())]);
+        // red is not absorbed, the rest fades with the distance travelled inside
+        assert!((thin.x - thick.x).abs() < 1e-3 && thick.y < thin.y && thick.z < thick.y);
+        // front reflection plus the light transmitted through both surfaces at normal incidence
+        let expected = 0.04 + 0.96 * 0.96 * (-0.6f64 * 4.).exp();
+        assert!((thick.z - expected).abs() < 1e-3, "{}", thick);
+        // a lower priority interior entirely inside the glass is not seen at all
+        let hidden = Dielectric { ior: 1.2, absorption: DVec3::splat(5.), priority: 0, dispersion: None, medium: None };
+        let nested = look(vec![ball(2., tinted), ball(1., hidden)]);
+        assert!((nested - thick).length() < 1e-4, "{} {}", nested, thick);
+    }
+    #[test]
+    fn test_spectral_dispersion() {
+        let look = |dispersion: Option<Dispersion>| {
+            let mut sc = Scene::create();
+            sc.background_color = DVec3::ONE;
+            let glass = Dielectric { ior: 1.5, absorption: DVec3::ZERO, priority: 0, dispersion, medium: None };
+            let ball = Sphere { material: Material::Custom(Arc::new(glass)), ior: 1., ..diffuse_ball(DVec3::new(0., 0., -10.), 2., DVec3::ZERO) };
+            ObjectAppend::append(&mut sc, Box::new(ball));
+            let state = PathState { wavelengths: Some(Wavelengths::sample(0.3)), ..PathState::default() };
+            cast_ray(Light { org: DVec3::ZERO, inten: DVec3::ONE, ies: None }, DVec3::new(0.05, 0.1, -1.).normalize(), &sc, 0, &state)
+        };
+        // plain glass carries all three wavelengths through
+        let clear = look(None);
+        assert!(clear.min_element() > 0.9, "{}", clear);
+        // dispersive glass terminates the secondary wavelengths, the hero counts thrice
+        let flint = look(Some(Dispersion::sf11()));
+        assert_eq!((flint.y, flint.z), (0., 0.));
+        assert!(flint.x > 2.5, "{}", flint);
+    }
+    #[test]
+    fn test_participating_media() {
+        let lamp = || Sphere { emission: Some(Emission { radiance: DVec3::new(4., 2., 1.), two_sided: false }), ..diffuse_ball(DVec3::new(0., 0., -5.), 1., DVec3::ZERO) };
+        let smoke = |radius: f64, medium: Medium| Sphere { material: Material::Custom(Arc::new(MediumBoundary { medium: Arc::new(medium), priority: 0 })), ior: 1., ..diffuse_ball(DVec3::new(0., 0., -5.), radius, DVec3::ZERO) };
+        let eye = Light { org: DVec3::ZERO, inten: DVec3::ONE, ies: None };
+        let mean = |sc: &mut Scene, dir: DVec3| (0..4000).map(|_| cast_ray(eye.clone(), dir, sc, 0, &PathState::default())).fold(DVec3::ZERO, |a, b| a + b) / 4000.;
+        // purely absorbing fog dims the lamp 4 units away
+        let mut sc = Scene::create();
+        ObjectAppend::append(&mut sc, Box::new(lamp()));
+        sc.fog = Some(Arc::new(Medium::homogeneous(DVec3::splat(0.1), DVec3::ZERO, 0.)));
+        let dimmed = mean(&mut sc, -DVec3::Z);
+        assert!((dimmed - DVec3::new(4., 2., 1.) * (-0.4f64).exp()).length() < 0.1, "{}", dimmed);
+        // scattering fog glows around the light, even looking away from it
+        sc.fog = Some(Arc::new(Medium::homogeneous(DVec3::ZERO, DVec3::splat(0.1), 0.3)));
+        assert!(mean(&mut sc, DVec3::X).min_element() > 0.);
+        // a boundary around nothing changes nothing, for camera and shadow rays alike
+        let mut sc = Scene::create();
+        ObjectAppend::append(&mut sc, Box::new(lamp()));
+        ObjectAppend::append(&mut sc, Box::new(smoke(2., Medium::homogeneous(DVec3::ZERO, DVec3::ZERO, 0.))));
+        assert_eq!(cast_ray(eye.clone(), -DVec3::Z, &sc, 0, &PathState::default()), DVec3::new(4., 2., 1.));
+        assert_eq!(shadow_transmittance(&sc, DVec3::ZERO, -DVec3::Z, 3.9, &PathState::default()), DVec3::ONE);
+        // absorbing smoke only where the sphere is, one unit in front of the lamp
+        let mut sc = Scene::create();
+        ObjectAppend::append(&mut sc, Box::new(lamp()));
+        ObjectAppend::append(&mut sc, Box::new(smoke(2., Medium::homogeneous(DVec3::new(0.5, 0., 0.), DVec3::ZERO, 0.))));
+        let smoked = mean(&mut sc, -DVec3::Z);
+        assert!((smoked - DVec3::new(4. * (-0.5f64).exp(), 2., 1.)).length() < 0.1, "{}", smoked);
+    }
+    #[test]
+    fn test_motion_blur() {
+        let mut sc = Scene::create();
+        sc.background_color = DVec3::ZERO;
+        let lamp = Sphere {
+            specular: SpecularProperties(25.0, 0.8, 0.),
+            motion: Some(Keyframes::new(vec![(0., DVec3::new(-4., 0., -5.)), (0.5, DVec3::new(0., 0., -5.)), (1., DVec3::new(0., 4., -5.))])),
+            emission: Some(Emission { radiance: DVec3::ONE, two_sided: false }),
+            ..diffuse_ball(DVec3::ZERO, 1., DVec3::ZERO)
+        };
+        ObjectAppend::append(&mut sc, Box::new(lamp));
+        let eye = Light { org: DVec3::ZERO, inten: DVec3::ONE, ies: None };
+        let at = |sc: &mut Scene, dir: DVec3, time: f64| cast_ray(eye.clone(), dir, sc, 0, &PathState { time, ..PathState::default() });
+        assert_eq!(at(&mut sc, -DVec3::Z, 0.), DVec3::ZERO);
+        assert_eq!(at(&mut sc, -DVec3::Z, 0.5), DVec3::ONE);
+        assert_eq!(at(&mut sc, -DVec3::Z, 0.9), DVec3::ZERO);
+        // halfway along the second segment
+        assert_eq!(at(&mut sc, DVec3::new(0., 2., -5.).normalize(), 0.75), DVec3::ONE);
+        assert_eq!(at(&mut sc, DVec3::new(-4., 0., -5.).normalize(), 0.), DVec3::ONE);
+    }
+    #[test]
+    fn test_adaptive_sampling() {
+        let mut sc = Scene::window(16, 12);
+        sc.background_color = DVec3::splat(0.5);
+        sc.adaptive = Some(Adaptive { min_spp: 4, max_spp: 64, threshold: 0.02 });
+        let ball = Sphere { specular: SpecularProperties(25.0, 0.8, 0.), ..diffuse_ball(DVec3::new(0., 0., -4.), 1.5, DVec3::splat(0.6)) };
+        ObjectAppend::append(&mut sc, Box::new(ball));
+        LightAppend::append(&mut sc, SphereLight { center: DVec3::new(3., 3., -2.), radius: 1.5, radiance: DVec3::splat(4.) });
+        let (frame, aux) = render_frame(&mut sc).unwrap();
+        // the flat background converges right away, the soft lit ball does not
+        assert_eq!(aux.samples[0], 4);
+        assert_eq!(frame[0], DVec3::splat(0.5));
+        assert!(aux.samples.iter().all(|n| (4..=64).contains(n)));
+        assert_eq!(aux.samples[6 * 16 + 8], 64);
+    }
+    #[test]
+    fn test_resume_from_checkpoint() {
+        let scene = || {
+            let mut sc = Scene::window(12, 9);
+            sc.spp = 5;
+            let ball = diffuse_ball(DVec3::new(0., 0., -4.), 1.5, DVec3::splat(0.6));
+            ObjectAppend::append(&mut sc, Box::new(ball));
+            LightAppend::append(&mut sc, SphereLight { center: DVec3::new(3., 3., -2.), radius: 1.5, radiance: DVec3::splat(4.) });
+            sc
+        };
+        let (whole, _) = render_frame(&mut scene()).unwrap();
+        // two passes in, then killed
+        let path = TempPath::new("resume.ck");
+        let mut sc = scene();
+        let mut acc = Accumulation::new(12, 9);
+        let mut tracker = ProgressTracker::new(0, 12 * 9 * 5);
+        assert!(render_pass(&mut sc, &mut acc, &mut tracker) && render_pass(&mut sc, &mut acc, &mut tracker));
+        acc.save(&path).unwrap();
+        sc.checkpoint = Some(Checkpoint { path: path.clone(), interval: Duration::ZERO });
+        let (resumed, aux) = render_frame(&mut sc).unwrap();
+        assert_eq!(resumed, whole);
+        assert!(aux.samples.iter().all(|n| *n == 5));
+        assert!(!path.exists());
+    }
+    #[test]
+    fn test_checkpoint_errors() {
+        let scene = |path: &Path| {
+            let mut sc = Scene::window(4, 3);
+            sc.checkpoint = Some(Checkpoint { path: path.to_path_buf(), interval: Duration::ZERO });
+            sc
+        };
+        // someone else's file is reported and left alone
+        let path = TempPath::new("not_ours.ck");
+        fs::write(&*path, b"P6\n1 1\n255\n\0\0\0").unwrap();
+        assert_eq!(render_frame(&mut scene(&path)).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(fs::read(&*path).unwrap(), b"P6\n1 1\n255\n\0\0\0");
+        // so is a checkpoint of another size
+        Accumulation::new(8, 6).save(&path).unwrap();
+        let err = render_frame(&mut scene(&path)).unwrap_err();
+        assert!(err.to_string().contains("8x6"), "{}", err);
+        // a checkpoint that cannot be written fails the render
+        let dir = TempPath::new("no_such_dir");
+        assert!(render_frame(&mut scene(&dir.join("out.ck"))).is_err());
+    }
+    #[test]
+    fn test_progress_and_cancel() {
+        // cancels the render after `after` rows
+        struct Recorder { seen: Arc<Mutex<Vec<Progress>>>, cancel: CancelToken, after: usize }
+        impl ProgressObserver for Recorder {
+            fn update(&mut self, progress: &Progress) {
+                let mut seen = self.seen.lock().unwrap();
+                seen.push(*progress);
+                if seen.len() == self.after {
+                    self.cancel.cancel();
+                }
+            }
+        }
+        let scene = |after: usize| {
+            let mut sc = Scene::window(8, 6);
+            sc.spp = 3;
+            let ball = diffuse_ball(DVec3::new(0., 0., -4.), 1.5, DVec3::splat(0.6));
+            ObjectAppend::append(&mut sc, Box::new(ball));
+            LightAppend::append(&mut sc, Light { org: DVec3::new(3., 3., 0.), inten: DVec3::splat(20.), ies: None });
+            let seen = Arc::new(Mutex::new(vec![]));
+            sc.progress = Box::new(Recorder { seen: seen.clone(), cancel: sc.cancel.clone(), after });
+            (sc, seen)
+        };
+        let (mut sc, seen) = scene(usize::MAX);
+        let (whole, _) = render_frame(&mut sc).unwrap();
+        let seen = seen.lock().unwrap();
+        // one report per row and pass
+        assert_eq!(seen.len(), 6 * 3);
+        assert!(seen.windows(2).all(|w| w[0].done < w[1].done && w[0].elapsed <= w[1].elapsed));
+        let last = seen.last().unwrap();
+        assert_eq!((last.done, last.total, last.eta), (8 * 6 * 3, 8 * 6 * 3, Some(Duration::ZERO)));
+        assert!(last.rays_per_second > 0.);
+        // stopped partway through the second pass, then resumed from the checkpoint
+        let path = TempPath::new("cancel.ck");
+        let (mut sc, seen) = scene(8);
+        sc.checkpoint = Some(Checkpoint { path: path.clone(), interval: Duration::from_secs(3600) });
+        let (_, aux) = render_frame(&mut sc).unwrap();
+        assert_eq!(seen.lock().unwrap().len(), 8);
+        assert_eq!(aux.samples.iter().sum::<u32>(), 8 * 8);
+        let (mut sc, _) = scene(usize::MAX);
+        sc.checkpoint = Some(Checkpoint { path: path.clone(), interval: Duration::from_secs(3600) });
+        let (resumed, _) = render_frame(&mut sc).unwrap();
+        assert_eq!(resumed, whole);
+        assert!(!path.exists());
+    }
+    #[test]
+    fn test_render_stats() {
+        let mut sc = Scene::window(8, 6);
+        let output = TempPath::new("render_stats.ppm");
+        sc.output = output.to_path_buf();
+        let glass = Dielectric { ior: 1.5, absorption: DVec3::ZERO, priority: 0, dispersion: None, medium: None };
+        let ball = Sphere { material: Material::Custom(Arc::new(glass)), ior: 1., ..diffuse_ball(DVec3::new(0., 0., -4.), 2., DVec3::ZERO) };
+        let floor = MeshTriangle::new(vec![Triangle { v0: DVec3::new(-10., -2., 0.), v1: DVec3::new(10., -2., 0.), v2: DVec3::new(0., -2., -20.), s0: DVec2::ZERO, s1: DVec2::X, s2: DVec2::Y }], Material::DiffuseAndGlossy);
+        ObjectAppend::append(&mut sc, Box::new(ball));
+        ObjectAppend::append(&mut sc, Box::new(floor));
+        LightAppend::append(&mut sc, Light { org: DVec3::new(3., 10., 0.), inten: DVec3::splat(100.), ies: None });
+        let stats = render(&mut sc).unwrap();
+        assert_eq!((stats.rays.primary, stats.paths), (48, 48));
+        assert!(stats.rays.shadow > 0 && stats.rays.reflection > 0 && stats.rays.refraction > 0, "{:?}", stats.rays);
+        let kinds: Vec<&str> = stats.intersections.iter().map(|(k, _)| *k).collect();
+        assert_eq!(kinds, vec!["sphere", "mesh"]);
+        assert!(stats.intersections.iter().all(|(_, c)| c.hits > 0 && c.hits <= c.calls));
+        // rays bouncing around inside the glass run into the depth limit
+        assert!(stats.max_depth_reached > 0);
+        assert!(stats.average_path_depth() > 1. && stats.average_path_depth() <= sc.max_depth as f64);
+        assert!(stats.timings.rendering > Duration::ZERO && stats.timings.output > Duration::ZERO);
+    }
+    #[test]
+    fn test_samplers() {
+        let frame = |name: &str, seed: u64, threads: usize| {
+            let mut sc = builtin_scene("soft").unwrap();
+            (sc.width, sc.height, sc.spp, sc.threads) = (16, 12, 4, threads);
+            sc.sampler = sampler_by_name(name, seed, sc.spp).unwrap();
+            render_frame(&mut sc).unwrap().0
+        };
+        SAMPLERS.iter().for_each(|name| {
+            // bit for bit the same on any number of threads, other with another seed
+            let once = frame(name, 1, 1);
+            assert_eq!(once, frame(name, 1, 3), "{}", name);
+            assert_ne!(once, frame(name, 2, 1), "{}", name);
+        });
     }
     #[test]
     fn test_write() {
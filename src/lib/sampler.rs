@@ -0,0 +1,345 @@
+use std::sync::{Arc, OnceLock};
+use glam::DVec2;
+
+// the random numbers of a render. every number is a function of the pixel,
+// the camera sample's index in it and the dimension, how many numbers the
+// sample had drawn before it, so a render comes out the same whichever thread
+// takes which pixel
+pub trait Sampler: Send + Sync {
+    // in [0, 1)
+    fn get_1d(&self, pixel: (u32, u32), index: u32, dimension: u32) -> f64;
+    // dimension and dimension + 1 as a point. samplers that stratify in two
+    // dimensions do so here
+    fn get_2d(&self, pixel: (u32, u32), index: u32, dimension: u32) -> DVec2 {
+        DVec2::new(self.get_1d(pixel, index, dimension), self.get_1d(pixel, index, dimension + 1))
+    }
+}
+
+// the splitmix64 finalizer
+pub fn mix(z: u64) -> u64 {
+    let z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    let z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+const GAMMA: u64 = 0x9e3779b97f4a7c15;
+fn hash(seed: u64, pixel: (u32, u32), dimension: u32) -> u64 {
+    mix(mix(mix(seed.wrapping_add(GAMMA) ^ pixel.0 as u64) ^ (pixel.1 as u64) << 32) ^ dimension as u64)
+}
+// top 53 bits, uniform in [0, 1)
+fn to_float(z: u64) -> f64 {
+    (z >> 11) as f64 / (1u64 << 53) as f64
+}
+// a 32 bit fraction
+fn fraction(x: u32) -> f64 {
+    x as f64 / (1u64 << 32) as f64
+}
+
+// uncorrelated numbers, a splitmix64 stream per sample
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Independent {
+    pub seed: u64,
+}
+impl Sampler for Independent {
+    fn get_1d(&self, pixel: (u32, u32), index: u32, dimension: u32) -> f64 {
+        let stream = hash(self.seed, pixel, 0) ^ (index as u64) << 32;
+        to_float(mix(stream.wrapping_add((dimension as u64 + 1).wrapping_mul(GAMMA))))
+    }
+}
+
+// jittered strata, samples of them per dimension in a random order for every
+// pixel and dimension, a grid as close to square as fits in two. the strata
+// are dealt out again after every samples indices
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stratified {
+    pub seed: u64,
+    pub samples: u32,
+}
+impl Stratified {
+    pub fn new(seed: u64, samples: u32) -> Self {
+        Self { seed, samples: samples.max(1) }
+    }
+    // the stratum of index out of count, and the jitter inside it
+    fn stratum(&self, pixel: (u32, u32), index: u32, dimension: u32, count: u32) -> (u32, f64) {
+        let round = hash(self.seed, pixel, dimension) ^ mix((index / count) as u64);
+        let stratum = permute(index % count, count, round as u32);
+        (stratum, to_float(mix(round ^ (index as u64) << 32)))
+    }
+}
+impl Sampler for Stratified {
+    fn get_1d(&self, pixel: (u32, u32), index: u32, dimension: u32) -> f64 {
+        let (stratum, jitter) = self.stratum(pixel, index, dimension, self.samples);
+        (stratum as f64 + jitter) / self.samples as f64
+    }
+    fn get_2d(&self, pixel: (u32, u32), index: u32, dimension: u32) -> DVec2 {
+        let side = ((self.samples as f64).sqrt() as u32).max(1);
+        let (stratum, jx) = self.stratum(pixel, index, dimension, side * side);
+        let jy = to_float(mix(hash(self.seed, pixel, dimension + 1) ^ index as u64));
+        DVec2::new((stratum % side) as f64 + jx, (stratum / side) as f64 + jy) / side as f64
+    }
+}
+// a pseudo random permutation of 0..len picked by p, Kensler's hash from
+// "correlated multi-jittered sampling"
+fn permute(i: u32, len: u32, p: u32) -> u32 {
+    // all ones down from the highest bit of len - 1
+    let w = u32::MAX >> (len - 1).max(1).leading_zeros();
+    let mut i = i;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < len {
+            return (i.wrapping_add(p)) % len;
+        }
+    }
+}
+
+const PRIMES: [u32; 32] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131];
+// the halton sequence, a prime base per dimension, Owen scrambled: every
+// digit is permuted by a hash of the pixel, the dimension and the digits
+// before it, so neighbouring pixels don't repeat each other and the few
+// first samples of a large base spread over the whole range. dimensions past
+// the 32nd are independent
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Halton {
+    pub seed: u64,
+}
+// the digits of index in base mirrored around the point, each permuted by
+// a hash of the seed and the digits before it, down to the precision of an f64
+pub fn owen_radical_inverse(base: u32, index: u32, seed: u64) -> f64 {
+    let (base64, inv) = (base as u64, 1. / base as f64);
+    let limit = u64::MAX / base64 - base64;
+    let (mut i, mut scale, mut digits) = (index as u64, 1., 0u64);
+    while 1. - scale < 1. && digits < limit {
+        let digit = permute((i % base64) as u32, base, mix(seed ^ digits) as u32);
+        digits = digits * base64 + digit as u64;
+        scale *= inv;
+        i /= base64;
+    }
+    (digits as f64 * scale).min(1. - f64::EPSILON / 2.)
+}
+impl Sampler for Halton {
+    fn get_1d(&self, pixel: (u32, u32), index: u32, dimension: u32) -> f64 {
+        match PRIMES.get(dimension as usize) {
+            Some(&base) => owen_radical_inverse(base, index, hash(self.seed, pixel, dimension)),
+            None => Independent { seed: self.seed }.get_1d(pixel, index, dimension),
+        }
+    }
+}
+
+// the first two dimensions of the sobol sequence, Owen scrambled, with the
+// index shuffled by another scramble for every pixel and dimension. Burley's
+// "practical hash-based Owen scrambling": every power of two prefix of a
+// pixel's samples stays stratified in both dimensions of a get_2d
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Sobol {
+    pub seed: u64,
+}
+fn sobol(index: u32, dimension: u32) -> u32 {
+    match dimension {
+        0 => index.reverse_bits(),
+        _ => {
+            let (mut r, mut v, mut i) = (0, 1u32 << 31, index);
+            while i != 0 {
+                if i & 1 != 0 {
+                    r ^= v;
+                }
+                i >>= 1;
+                v ^= v >> 1;
+            }
+            r
+        }
+    }
+}
+fn laine_karras(x: u32, seed: u32) -> u32 {
+    let mut x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x
+}
+pub fn owen_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras(x.reverse_bits(), seed).reverse_bits()
+}
+impl Sobol {
+    fn point(&self, pixel: (u32, u32), index: u32, dimension: u32) -> DVec2 {
+        let seed = hash(self.seed, pixel, dimension);
+        let shuffled = owen_scramble(index, seed as u32);
+        let coordinate = |d: u32| fraction(owen_scramble(sobol(shuffled, d), mix(seed ^ (d as u64 + 1)) as u32));
+        DVec2::new(coordinate(0), coordinate(1))
+    }
+}
+impl Sampler for Sobol {
+    fn get_1d(&self, pixel: (u32, u32), index: u32, dimension: u32) -> f64 {
+        self.point(pixel, index, dimension).x
+    }
+    fn get_2d(&self, pixel: (u32, u32), index: u32, dimension: u32) -> DVec2 {
+        self.point(pixel, index, dimension)
+    }
+}
+
+// a golden ratio sequence per dimension, R2 in two, started in every pixel
+// at the pixel's value in a blue noise mask. the mask is shifted by a random
+// amount per dimension. the error of the first samples is spread as high
+// frequency noise over the image instead of white noise
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BlueNoise {
+    pub seed: u64,
+}
+const MASK_SIZE: usize = 64;
+impl BlueNoise {
+    fn mask(&self, pixel: (u32, u32), dimension: u32) -> f64 {
+        let offset = hash(self.seed, (0, 0), dimension);
+        let x = (pixel.0 as usize + offset as usize) % MASK_SIZE;
+        let y = (pixel.1 as usize + (offset >> 32) as usize) % MASK_SIZE;
+        blue_noise_mask()[y * MASK_SIZE + x]
+    }
+}
+impl Sampler for BlueNoise {
+    fn get_1d(&self, pixel: (u32, u32), index: u32, dimension: u32) -> f64 {
+        // 1 / golden ratio
+        (self.mask(pixel, dimension) + index as f64 * 0.6180339887498949).fract()
+    }
+    fn get_2d(&self, pixel: (u32, u32), index: u32, dimension: u32) -> DVec2 {
+        // 1 / plastic number and its square
+        let alpha = DVec2::new(0.7548776662466927, 0.5698402909980532);
+        let start = DVec2::new(self.mask(pixel, dimension), self.mask(pixel, dimension + 1));
+        (start + alpha * index as f64).fract()
+    }
+}
+// ranks of a toroidal 64x64 tile in [0, 1), every value once. each pixel is
+// ranked by filling the largest void left by the ones before it, Ulichney's
+// void and cluster with a gaussian of sigma 1.5
+pub fn blue_noise_mask() -> &'static [f64] {
+    static MASK: OnceLock<Vec<f64>> = OnceLock::new();
+    MASK.get_or_init(|| {
+        let n = MASK_SIZE * MASK_SIZE;
+        let wrap = |d: usize| d.min(MASK_SIZE - d) as f64;
+        let kernel: Vec<f64> = (0..n).map(|k| {
+            let (dx, dy) = (wrap(k % MASK_SIZE), wrap(k / MASK_SIZE));
+            (-(dx * dx + dy * dy) / (2. * 1.5 * 1.5)).exp()
+        }).collect();
+        // a little noise so ties don't settle into a regular grid
+        let mut energy: Vec<f64> = (0..n).map(|k| to_float(mix(k as u64)) * 1e-9).collect();
+        let mut rank = vec![usize::MAX; n];
+        (0..n).for_each(|r| {
+            let (void, _) = energy.iter().enumerate()
+                .filter(|(k, _)| rank[*k] == usize::MAX)
+                .fold((0, f64::INFINITY), |best, (k, e)| if *e < best.1 { (k, *e) } else { best });
+            rank[void] = r;
+            let (vx, vy) = (void % MASK_SIZE, void / MASK_SIZE);
+            energy.iter_mut().enumerate().for_each(|(k, e)| {
+                let dx = (k % MASK_SIZE + MASK_SIZE - vx) % MASK_SIZE;
+                let dy = (k / MASK_SIZE + MASK_SIZE - vy) % MASK_SIZE;
+                *e += kernel[dy * MASK_SIZE + dx];
+            });
+        });
+        rank.iter().map(|r| (*r as f64 + 0.5) / n as f64).collect()
+    })
+}
+
+// names of the samplers for sampler_by_name
+pub const SAMPLERS: &[&str] = &["independent", "stratified", "halton", "sobol", "bluenoise"];
+// stratified renders stratify over spp samples
+pub fn sampler_by_name(name: &str, seed: u64, spp: u32) -> Option<Arc<dyn Sampler>> {
+    match name {
+        "independent" => Some(Arc::new(Independent { seed })),
+        "stratified" => Some(Arc::new(Stratified::new(seed, spp))),
+        "halton" => Some(Arc::new(Halton { seed })),
+        "sobol" => Some(Arc::new(Sobol { seed })),
+        "bluenoise" => Some(Arc::new(BlueNoise { seed })),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use super::{Sampler, Independent, Stratified, Halton, Sobol, BlueNoise, SAMPLERS, sampler_by_name, blue_noise_mask, MASK_SIZE};
+
+    // a samples estimate of the integral of sin(pi x) y over the unit square
+    fn estimate(sampler: &dyn Sampler, pixel: (u32, u32), samples: u32) -> f64 {
+        (0..samples).map(|i| {
+            let u = sampler.get_2d(pixel, i, 0);
+            (u.x * PI).sin() * u.y
+        }).sum::<f64>() / samples as f64
+    }
+    #[test]
+    fn test_deterministic() {
+        SAMPLERS.iter().for_each(|name| {
+            let (a, b) = (sampler_by_name(name, 7, 16).unwrap(), sampler_by_name(name, 8, 16).unwrap());
+            let values: Vec<f64> = (0..40).map(|d| a.get_1d((3, 5), 2, d)).collect();
+            assert!(values.iter().all(|v| (0. ..1.).contains(v)), "{}", name);
+            assert_eq!(values, (0..40).map(|d| a.get_1d((3, 5), 2, d)).collect::<Vec<_>>(), "{}", name);
+            // another seed, pixel or sample gives other numbers
+            assert_ne!(values[1], b.get_1d((3, 5), 2, 1), "{}", name);
+            assert_ne!(values[1], a.get_1d((4, 5), 2, 1), "{}", name);
+            assert_ne!(values[1], a.get_1d((3, 5), 3, 1), "{}", name);
+            let p = a.get_2d((3, 5), 2, 4);
+            assert!(p.min_element() >= 0. && p.max_element() < 1., "{}", name);
+        });
+        assert!(sampler_by_name("random", 0, 1).is_none());
+    }
+    #[test]
+    fn test_stratification() {
+        // one sample in every stratum, in one dimension and on a 4x4 grid
+        let cells = |points: Vec<(f64, f64)>, nx: f64, ny: f64| {
+            let mut seen: Vec<usize> = points.iter().map(|(x, y)| (y * ny) as usize * nx as usize + (x * nx) as usize).collect();
+            seen.sort();
+            seen == (0..(nx * ny) as usize).collect::<Vec<_>>()
+        };
+        let stratified = Stratified::new(3, 16);
+        assert!(cells((0..16).map(|i| (stratified.get_1d((1, 2), i, 5), 0.)).collect(), 16., 1.));
+        assert!(cells((16..32).map(|i| (stratified.get_1d((1, 2), i, 5), 0.)).collect(), 16., 1.));
+        assert!(cells((0..16).map(|i| stratified.get_2d((1, 2), i, 5).into()).collect(), 4., 4.));
+        // sobol points make every elementary interval of their power of two prefixes
+        let sobol = Sobol { seed: 3 };
+        let points: Vec<(f64, f64)> = (0..16).map(|i| sobol.get_2d((1, 2), i, 6).into()).collect();
+        [(16., 1.), (8., 2.), (4., 4.), (2., 8.), (1., 16.)].iter().for_each(|(nx, ny)| assert!(cells(points.clone(), *nx, *ny), "{}x{}", nx, ny));
+        assert!(cells((0..8).map(|i| (sobol.get_1d((1, 2), i, 9), 0.)).collect(), 8., 1.));
+        let halton = Halton { seed: 3 };
+        assert!(cells((0..8).map(|i| (halton.get_1d((1, 2), i, 0), 0.)).collect(), 8., 1.));
+        assert!(cells((0..9).map(|i| (halton.get_1d((1, 2), i, 1), 0.)).collect(), 9., 1.));
+        // the first few samples of a large base still spread out
+        let spread: Vec<f64> = (0..4).map(|i| halton.get_1d((1, 2), i, 31)).collect();
+        assert!(spread.iter().fold(0., |m: f64, v| m.max(*v)) - spread.iter().fold(1., |m: f64, v| m.min(*v)) > 0.2, "{:?}", spread);
+    }
+    #[test]
+    fn test_convergence() {
+        // the integral is 1 / pi. averaged over pixels the low discrepancy
+        // samplers are well ahead
+        let error = |sampler: &dyn Sampler| (0..32).map(|p| (estimate(sampler, (p, 0), 64) - 1. / PI).abs()).sum::<f64>() / 32.;
+        let independent = error(&Independent { seed: 1 });
+        [error(&Stratified::new(1, 64)), error(&Halton { seed: 1 }), error(&Sobol { seed: 1 }), error(&BlueNoise { seed: 1 })].iter().for_each(|e| {
+            assert!(*e < independent * 0.5, "{} against {}", e, independent);
+        });
+    }
+    #[test]
+    fn test_blue_noise_mask() {
+        let mask = blue_noise_mask();
+        let mut ranks: Vec<usize> = mask.iter().map(|v| (v * mask.len() as f64) as usize).collect();
+        ranks.sort();
+        assert_eq!(ranks, (0..MASK_SIZE * MASK_SIZE).collect::<Vec<_>>());
+        // neighbours are further apart than white noise's 1/3 on average
+        let step: f64 = (0..mask.len()).map(|k| (mask[k] - mask[(k + 1) % mask.len()]).abs()).sum::<f64>() / mask.len() as f64;
+        assert!(step > 0.4, "{}", step);
+        let noise = BlueNoise { seed: 0 };
+        assert_ne!(noise.get_1d((0, 0), 0, 0), noise.get_1d((0, 0), 0, 1));
+    }
+}
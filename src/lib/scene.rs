@@ -1,5 +1,6 @@
+use std::{sync::Arc, path::PathBuf};
 use glam::DVec3;
-use super::{Object, Light};
+use super::{Object, LightSource, EnvironmentMap, EnvironmentKind, Sky, Medium, DisplayTransform, Denoiser, Adaptive, Checkpoint, ProgressObserver, Silent, CancelToken, Integrator, ImageFormat, Sampler, Independent};
 
 pub trait ObjectClone {
     fn clone_box(&self) -> Box<dyn Object>;
@@ -26,49 +27,109 @@ pub struct Scene {
     pub background_color: DVec3,
     pub max_depth: i16,
     pub epsilon: f64,
+    // camera rays per pixel, jittered inside the pixel when above one
+    pub spp: u32,
+    pub integrator: Integrator,
+    // rows of the image are rendered on this many threads at once
+    pub threads: usize,
+    // per pixel sample counts driven by the noise, see Adaptive
+    pub adaptive: Option<Adaptive>,
+    // saves the render after sample passes so a killed one can resume
+    pub checkpoint: Option<Checkpoint>,
+    // told how the render is getting on, a bar on stdout unless replaced
+    pub progress: Box<dyn ProgressObserver>,
+    // stops the render between rows when cancelled from elsewhere
+    pub cancel: CancelToken,
+    // shadow rays per area light and shading point
+    pub shadow_samples: u32,
+    // trace sampled wavelengths instead of rgb, see Wavelengths
+    pub spectral: bool,
+    // camera shutter open and close times, every camera ray gets a random time in
+    // between that moving objects are intersected at. equal times, no motion blur
+    pub shutter: (f64, f64),
+    // filters the frame buffer before it is written out, see Denoiser
+    pub denoise: Option<Denoiser>,
+    // how the radiance in the frame buffer is turned into the bytes written out
+    pub display: DisplayTransform,
+    // where render writes the image and how it is encoded
+    pub output: PathBuf,
+    pub format: ImageFormat,
+    // participating medium filling the space outside of every object's interior
+    pub fog: Option<Arc<Medium>>,
+    // every random number of the render, by pixel, sample and dimension
+    pub sampler: Arc<dyn Sampler>,
+    // replaces background_color when set, see set_environment
+    environment: Option<Arc<EnvironmentMap>>,
     objects: Vec<Box<dyn Object>>,
-    lights: Vec<Light>,
+    lights: Vec<Box<dyn LightSource>>,
 }
 pub trait ObjectAppend {
     fn append(&mut self, obj: Box<dyn Object>);
 }
 pub trait LightAppend {
-    fn append(&mut self, light: Light);
+    fn append<L: LightSource + 'static>(&mut self, light: L);
 }
 #[allow(dead_code)]
 impl Scene {
-    pub fn new(width: i32, height: i32, fov: f64, background_color: DVec3, max_depth: i16, epsilon: f64, objects: Vec<Box<dyn Object>>, lights: Vec<Light>) -> Self {
-        Self { width, height, fov, background_color, max_depth, epsilon, objects, lights }
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(width: i32, height: i32, fov: f64, background_color: DVec3, max_depth: i16, epsilon: f64, objects: Vec<Box<dyn Object>>, lights: Vec<Box<dyn LightSource>>) -> Self {
+        Self { width, height, fov, background_color, max_depth, epsilon, spp: 1, integrator: Integrator::Whitted, threads: 1, adaptive: None, checkpoint: None, progress: Box::new(Silent), cancel: CancelToken::default(), shadow_samples: 1, spectral: false, shutter: (0., 0.), denoise: None, display: DisplayTransform::default(), output: PathBuf::from("binary.ppm"), format: ImageFormat::Ppm, fog: None, sampler: Arc::new(Independent::default()), environment: None, objects, lights }
     }
+    // the background is linear, (60, 172, 215) once srgb encoded
     pub fn create() -> Self {
-        Scene::new(1280, 960, 90., DVec3::new(0.235294, 0.67451, 0.843137), 5, 0.00001, Vec::<Box<dyn Object>>::new(), Vec::<Light>::new())
+        Scene::new(1280, 960, 90., DVec3::new(0.045186, 0.412543, 0.679542), 5, 0.00001, Vec::<Box<dyn Object>>::new(), Vec::<Box<dyn LightSource>>::new())
     }
     pub fn window(width: i32, height: i32) -> Self {
-        Scene::new(width, height, 90., DVec3::new(0.235294, 0.67451, 0.843137), 5, 0.00001, Vec::<Box<dyn Object>>::new(), Vec::<Light>::new())
+        Scene::new(width, height, 90., DVec3::new(0.045186, 0.412543, 0.679542), 5, 0.00001, Vec::<Box<dyn Object>>::new(), Vec::<Box<dyn LightSource>>::new())
     }
     pub fn get_obj(&self) -> &Vec<Box<dyn Object>> {
         &self.objects
     }
-    pub fn get_light(&self) -> &Vec<Light> {
+    pub fn get_light(&self) -> &Vec<Box<dyn LightSource>> {
         &self.lights
     }
+    // the map is shown as background and also registered as a light
+    pub fn set_environment(&mut self, env: EnvironmentMap) {
+        let env = Arc::new(env);
+        self.lights.push(Box::new(env.clone()));
+        self.environment = Some(env);
+    }
+    // daylight: the sky as environment plus its sun as a directional light
+    pub fn set_sky(&mut self, sky: Sky) {
+        self.lights.push(Box::new(sky.sun_light()));
+        self.set_environment(EnvironmentMap::new(EnvironmentKind::Sky(Box::new(sky)), 0., 1.));
+    }
+    pub fn get_environment(&self) -> Option<&EnvironmentMap> {
+        self.environment.as_deref()
+    }
+    // radiance arriving along a ray that escapes the scene
+    pub fn background(&self, dir: DVec3) -> DVec3 {
+        match &self.environment {
+            Some(env) => env.background(dir),
+            None => self.background_color,
+        }
+    }
 }
 impl ObjectAppend for Scene {
     fn append(&mut self, obj: Box<dyn Object>) {
+        if let Some(light) = obj.as_light() {
+            self.lights.push(light);
+        }
         self.objects.push(obj);
     }
 }
 impl LightAppend for Scene {
-    fn append(&mut self, light: Light) {
-        self.lights.push(light);
+    fn append<L: LightSource + 'static>(&mut self, light: L) {
+        self.lights.push(Box::new(light));
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
     use glam::{DVec3, DVec2};
 
-    use crate::lib::{Sphere, MeshTriangle, Triangle, Light, SpecularProperties};
+    use crate::lib::{Sphere, MeshTriangle, Triangle, Light, SpecularProperties, Texture, Image, EnvironmentMap, EnvironmentKind, Sky};
 
     use super::{Scene, ObjectAppend, LightAppend};
 
@@ -78,14 +139,14 @@ mod tests {
         let tri = MeshTriangle { vertices: vec![Triangle { 
             v0: DVec3::ZERO, v1: DVec3::ZERO, v2: DVec3::ZERO, s0: DVec2::ZERO, s1: DVec2::ZERO, s2: DVec2::ZERO 
         }],
-            material: crate::lib::Material::DiffuseAndGlossy, ior: 1.3, specular: SpecularProperties(25.0, 0.8, 0.2) 
+            material: crate::lib::Material::DiffuseAndGlossy, ior: 1.3, specular: SpecularProperties(25.0, 0.8, 0.2), diffuse: Texture::checker(), ..Default::default()
         };
-        let sp = Sphere { center: DVec3::new(2., 0., 0.), radius: 1.6, radius2: 2.56, material: crate::lib::Material::DiffuseAndGlossy, ior: 1.3, specular: SpecularProperties(25.0, 0.8, 0.2), diffuse_color: DVec3::splat(0.2)};
+        let sp = Sphere { center: DVec3::new(2., 0., 0.), radius: 1.6, radius2: 2.56, material: crate::lib::Material::DiffuseAndGlossy, ior: 1.3, specular: SpecularProperties(25.0, 0.8, 0.2), diffuse: Texture::Constant(DVec3::splat(0.2)), ..Default::default() };
         ObjectAppend::append(&mut sc, Box::new(sp));
         ObjectAppend::append(&mut sc, Box::new(tri));
-        let light = Light { org: DVec3::new(0., 0., 0.), inten: DVec3::new(0.88, 0.42, 0.)};
+        let light = Light { org: DVec3::new(0., 0., 0.), inten: DVec3::new(0.88, 0.42, 0.), ies: None };
         let (res, t, b1, b2) = sc.objects[0].intersection(light, DVec3::new(0.88, 0.42, 0.));
-        assert_eq!(res, true);
+        assert!(res);
         assert_eq!(t, 0.4683376845365324);
         assert_eq!(b1, 0);
         assert_eq!(b2, DVec2::ZERO);
@@ -95,15 +156,29 @@ mod tests {
         let mut sc = Scene::create();
         let tri = MeshTriangle { vertices: vec![Triangle { 
             v0: DVec3::ZERO, v1: DVec3::ZERO, v2: DVec3::ZERO, s0: DVec2::ZERO, s1: DVec2::ZERO, s2: DVec2::ZERO 
-        }], material: crate::lib::Material::DiffuseAndGlossy, ior: 1.3, specular: crate::lib::SpecularProperties(25.0, 0.8, 0.2) };
-        let light = Light { org: DVec3::new(0., 0., 0.), inten: DVec3::new(0.88, 0.42, 0.)};
-        let sp = Sphere { center: DVec3::new(2., 0., 0.), radius: 1.6, radius2: 2.56, material: crate::lib::Material::DiffuseAndGlossy, ior: 1.3, specular: SpecularProperties(25.0, 0.8, 0.2), diffuse_color: DVec3::splat(0.2)};
+        }], material: crate::lib::Material::DiffuseAndGlossy, ior: 1.3, specular: crate::lib::SpecularProperties(25.0, 0.8, 0.2), diffuse: Texture::checker(), ..Default::default() };
+        let light = Light { org: DVec3::new(0., 0., 0.), inten: DVec3::new(0.88, 0.42, 0.), ies: None };
+        let sp = Sphere { center: DVec3::new(2., 0., 0.), radius: 1.6, radius2: 2.56, material: crate::lib::Material::DiffuseAndGlossy, ior: 1.3, specular: SpecularProperties(25.0, 0.8, 0.2), diffuse: Texture::Constant(DVec3::splat(0.2)), ..Default::default() };
         ObjectAppend::append(&mut sc, Box::new(sp));
         ObjectAppend::append(&mut sc, Box::new(tri));
-        LightAppend::append(&mut sc, light);
+        LightAppend::append(&mut sc, light.clone());
         let obj1 = sc.get_obj();
-        obj1[0].intersection(sc.lights[0], DVec3::new(0.88, 0.42, 0.));
+        obj1[0].intersection(light.clone(), DVec3::new(0.88, 0.42, 0.));
         let obj2 = sc.get_obj();
-        obj2[0].intersection(sc.lights[0], DVec3::new(0.88, 0.42, 0.));
+        obj2[0].intersection(light, DVec3::new(0.88, 0.42, 0.));
+    }
+    #[test]
+    fn test_environment_background() {
+        let mut sc = Scene::create();
+        assert_eq!(sc.background(DVec3::Y), sc.background_color);
+        let img = Arc::new(Image::new(2, 1, vec![DVec3::ONE, DVec3::ONE]));
+        sc.set_environment(EnvironmentMap::new(EnvironmentKind::Equirect(img), 0., 3.));
+        assert_eq!(sc.background(DVec3::Y), DVec3::splat(3.));
+        assert_eq!(sc.get_light().len(), 1);
+        assert!(sc.get_environment().is_some());
+        let mut day = Scene::create();
+        day.set_sky(Sky::new(40., 135., 2.5, DVec3::splat(0.2)));
+        assert_eq!(day.get_light().len(), 2);
+        assert!(day.background(DVec3::Y).z > day.background(DVec3::Y).x);
     }
 }
@@ -0,0 +1,303 @@
+use std::{fmt, fs, io, path::Path, sync::Arc};
+use glam::{DVec2, DVec3};
+
+use super::{Scene, Sphere, MeshTriangle, Triangle, Material, Texture, Light, SpotLight, SphereLight, DirectionalLight, IesProfile, Conductor, RoughDielectric, ObjectAppend, LightAppend};
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(io::Error),
+    // with the line it is on
+    Format(usize, String),
+}
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io(e) => write!(f, "io error: {}", e),
+            SceneError::Format(line, s) => write!(f, "malformed scene file, line {}: {}", line, s),
+        }
+    }
+}
+impl From<io::Error> for SceneError {
+    fn from(e: io::Error) -> Self {
+        SceneError::Io(e)
+    }
+}
+
+// a line per statement, # starts a comment. vectors and colours are three
+// numbers, colours linear. the camera sits at the origin looking down -z
+//
+//   size <width> <height>
+//   fov <degrees>
+//   background <r g b>
+//   max_depth <n>
+//   spp <n>
+//   shadow_samples <n>
+//   sphere <center> <radius> <material>
+//   triangle <v0> <v1> <v2> <material>
+//   quad <v0> <v1> <v2> <v3> <material>
+//   light point <position> <intensity rgb> [ies <file>]
+//   light spot <position> <direction> <intensity rgb> <inner> <outer> [ies <file>]
+//   light sphere <center> <radius> <radiance rgb>
+//   light directional <direction> <irradiance rgb>
+//
+// where <material> is one of
+//
+//   diffuse <r g b>
+//   checker
+//   glass <ior>
+//   mirror
+//   metal <gold|silver|copper|aluminium> <roughness>
+//   rough_glass <ior> <roughness>
+//
+// roughness runs from 0, polished, to 1
+//
+// an ies file, relative to the working directory, shapes a point or spot light
+// with its measured profile, the intensity then scales the candela
+pub fn parse_scene(text: &str) -> Result<Scene, SceneError> {
+    let mut scene = Scene::create();
+    text.lines().enumerate().try_for_each(|(i, line)| {
+        let line = line.split('#').next().unwrap_or_default();
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if tokens.is_empty() {
+            return Ok(());
+        }
+        statement(&mut scene, &tokens).map_err(|e| SceneError::Format(i + 1, e))
+    })?;
+    Ok(scene)
+}
+pub fn load_scene<P: AsRef<Path>>(path: P) -> Result<Scene, SceneError> {
+    parse_scene(&fs::read_to_string(path)?)
+}
+// name, what it shows and its scene file
+pub const BUILTIN_SCENES: &[(&str, &str, &str)] = &[
+    ("spheres", "a diffuse and a glass sphere over a checkered floor, two point lights", SPHERES),
+    ("glass", "a large glass sphere in front of a checkered wall", GLASS),
+    ("soft", "a ball on a floor under a spherical area light, soft shadows", SOFT),
+];
+const SPHERES: &str = "\
+size 1280 960
+sphere -1 0 -12 2 diffuse 0.6 0.7 0.8
+sphere 0.5 -0.5 -8 1.5 glass 1.5
+quad -5 -3 -6  5 -3 -6  5 -3 -16  -5 -3 -16 checker
+light point -20 70 20 9500 9500 9500
+light point 30 50 -12 5300 5300 5300
+";
+const GLASS: &str = "\
+size 640 480
+max_depth 8
+sphere 0 0 -6 2 glass 1.5
+quad -20 -15 -14  20 -15 -14  20 15 -14  -20 15 -14 checker
+light point -10 14 6 900 900 900
+";
+const SOFT: &str = "\
+size 640 480
+shadow_samples 16
+sphere 0 -1 -6 1 diffuse 0.8 0.3 0.2
+quad -6 -2 -2  6 -2 -2  6 -2 -12  -6 -2 -12 diffuse 0.7 0.7 0.7
+light sphere 0 3.5 -6 2 3 3 3
+";
+pub fn builtin_scene(name: &str) -> Option<Scene> {
+    let (_, _, text) = BUILTIN_SCENES.iter().find(|(n, _, _)| *n == name)?;
+    // they are tested to parse
+    parse_scene(text).ok()
+}
+
+fn statement(scene: &mut Scene, tokens: &[&str]) -> Result<(), String> {
+    let mut args = Args { tokens: &tokens[1..] };
+    match tokens[0] {
+        "size" => match (args.parse()?, args.parse()?) {
+            (width, height) if width > 0 && height > 0 => (scene.width, scene.height) = (width, height),
+            (width, height) => return Err(format!("image size {}x{} is not positive", width, height)),
+        },
+        "fov" => scene.fov = args.number()?,
+        "background" => scene.background_color = args.vec3()?,
+        "max_depth" => scene.max_depth = args.parse()?,
+        "spp" => match args.parse()? {
+            0 => return Err("spp must be positive".to_string()),
+            spp => scene.spp = spp,
+        },
+        "shadow_samples" => scene.shadow_samples = args.parse()?,
+        "sphere" => {
+            let (center, radius) = (args.vec3()?, args.number()?);
+            let (material, ior, diffuse) = args.material()?;
+            let sphere = Sphere { ior, diffuse, ..Sphere::new(center, radius, material) };
+            ObjectAppend::append(scene, Box::new(sphere));
+        }
+        "triangle" | "quad" => {
+            let (v0, v1, v2) = (args.vec3()?, args.vec3()?, args.vec3()?);
+            let vertices = match tokens[0] {
+                "triangle" => vec![Triangle { v0, v1, v2, s0: DVec2::ZERO, s1: DVec2::X, s2: DVec2::Y }],
+                _ => {
+                    let v3 = args.vec3()?;
+                    vec![
+                        Triangle { v0, v1, v2: v3, s0: DVec2::ZERO, s1: DVec2::X, s2: DVec2::Y },
+                        Triangle { v0: v1, v1: v2, v2: v3, s0: DVec2::X, s1: DVec2::ONE, s2: DVec2::Y },
+                    ]
+                }
+            };
+            let (material, ior, diffuse) = args.material()?;
+            let mesh = MeshTriangle { ior, diffuse, ..MeshTriangle::new(vertices, material) };
+            ObjectAppend::append(scene, Box::new(mesh));
+        }
+        "light" => match args.word()? {
+            "point" => LightAppend::append(scene, Light { org: args.vec3()?, inten: args.vec3()?, ies: args.ies()? }),
+            "spot" => LightAppend::append(scene, SpotLight {
+                org: args.vec3()?, dir: args.vec3()?, inten: args.vec3()?, inner: args.number()?, outer: args.number()?, falloff: 1., ies: args.ies()?,
+            }),
+            "sphere" => LightAppend::append(scene, SphereLight { center: args.vec3()?, radius: args.number()?, radiance: args.vec3()? }),
+            "directional" => LightAppend::append(scene, DirectionalLight { dir: args.vec3()?, irradiance: args.vec3()?, angular_diameter: 0. }),
+            kind => return Err(format!("unknown light {}", kind)),
+        },
+        keyword => return Err(format!("unknown statement {}", keyword)),
+    }
+    match args.tokens {
+        [] => Ok(()),
+        rest => Err(format!("unexpected {}", rest.join(" "))),
+    }
+}
+struct Args<'a> {
+    tokens: &'a [&'a str],
+}
+impl<'a> Args<'a> {
+    fn word(&mut self) -> Result<&'a str, String> {
+        let (first, rest) = self.tokens.split_first().ok_or("missing argument")?;
+        self.tokens = rest;
+        Ok(first)
+    }
+    fn parse<T: std::str::FromStr>(&mut self) -> Result<T, String> {
+        let word = self.word()?;
+        word.parse().map_err(|_| format!("bad number {}", word))
+    }
+    fn number(&mut self) -> Result<f64, String> {
+        self.parse()
+    }
+    fn vec3(&mut self) -> Result<DVec3, String> {
+        Ok(DVec3::new(self.number()?, self.number()?, self.number()?))
+    }
+    // an optional trailing `ies <file>`
+    fn ies(&mut self) -> Result<Option<Arc<IesProfile>>, String> {
+        match self.tokens.first() {
+            Some(&"ies") => {
+                self.word()?;
+                let file = self.word()?;
+                IesProfile::open(file).map(|p| Some(Arc::new(p))).map_err(|e| format!("{}: {}", file, e))
+            }
+            _ => Ok(None),
+        }
+    }
+    fn roughness(&mut self) -> Result<f64, String> {
+        match self.number()? {
+            r if (0. ..=1.).contains(&r) => Ok(r),
+            r => Err(format!("roughness {} is not between 0 and 1", r)),
+        }
+    }
+    // the material, ior and diffuse texture of a shape
+    fn material(&mut self) -> Result<(Material, f64, Texture), String> {
+        match self.word()? {
+            "diffuse" => Ok((Material::DiffuseAndGlossy, 1.3, Texture::Constant(self.vec3()?))),
+            "checker" => Ok((Material::DiffuseAndGlossy, 1.3, Texture::checker())),
+            "glass" => Ok((Material::ReflectionAndRefraction, self.number()?, Texture::Constant(DVec3::splat(0.2)))),
+            "mirror" => Ok((Material::Reflection, 1.3, Texture::Constant(DVec3::splat(0.2)))),
+            "metal" => {
+                let metal = match self.word()? {
+                    "gold" => Conductor::gold,
+                    "silver" => Conductor::silver,
+                    "copper" => Conductor::copper,
+                    "aluminium" => Conductor::aluminium,
+                    m => return Err(format!("unknown metal {}", m)),
+                };
+                Ok((Material::Custom(Arc::new(metal(self.roughness()?))), 1.3, Texture::Constant(DVec3::splat(0.2))))
+            }
+            "rough_glass" => {
+                let ior = self.number()?;
+                Ok((Material::Custom(Arc::new(RoughDielectric::clear(ior, self.roughness()?))), ior, Texture::Constant(DVec3::splat(0.2))))
+            }
+            m => Err(format!("unknown material {}", m)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use glam::{DVec2, DVec3};
+
+    use crate::lib::{Material, TempPath};
+
+    use super::{parse_scene, builtin_scene, SceneError, BUILTIN_SCENES};
+
+    #[test]
+    fn test_parse_scene() {
+        let sc = parse_scene("
+            # a small test scene
+            size 320 240
+            fov 60
+            background 0 0 0
+            spp 4
+            sphere 0 0 -5 1.5 glass 1.5   # in front
+            quad -1 -1 -8  1 -1 -8  1 1 -8  -1 1 -8 diffuse 0.5 0.5 0.5
+            triangle 0 0 -9  1 0 -9  0 1 -9 mirror
+            light point 0 5 0 100 100 100
+            light sphere 0 3 -5 1 2 2 2
+        ").unwrap();
+        assert_eq!((sc.width, sc.height, sc.fov, sc.spp), (320, 240, 60., 4));
+        assert_eq!(sc.background_color, DVec3::ZERO);
+        assert_eq!(sc.get_obj().len(), 3);
+        assert_eq!(sc.get_light().len(), 2);
+        assert_eq!(sc.get_obj()[0].get_material_properties(), Material::ReflectionAndRefraction);
+        assert_eq!(sc.get_obj()[0].get_ior(), 1.5);
+    }
+    #[test]
+    fn test_microfacet_materials() {
+        let sc = parse_scene("
+            sphere 0 0 -5 1 metal gold 0.3
+            sphere 0 0 -9 1 metal silver 0
+            sphere 0 0 -13 1 rough_glass 1.5 0.2
+        ").unwrap();
+        let bsdfs: Vec<_> = sc.get_obj().iter().map(|o| o.get_bsdf()).collect();
+        assert!(bsdfs[0].is_glossy() && bsdfs[1].is_delta() && bsdfs[2].is_glossy());
+        assert!(bsdfs[2].interior().is_some() && bsdfs[0].interior().is_none());
+        assert_eq!(sc.get_obj()[2].get_ior(), 1.5);
+        ["metal gold", "metal tin 0.1", "metal gold 1.5", "rough_glass 1.5", "rough_glass 1.5 -0.1"].iter().for_each(|m| {
+            assert!(parse_scene(&format!("sphere 0 0 -5 1 {}", m)).is_err(), "{}", m);
+        });
+    }
+    #[test]
+    fn test_scene_errors() {
+        let line = |text: &str| match parse_scene(text) {
+            Err(SceneError::Format(line, _)) => line,
+            _ => panic!("{} parsed", text),
+        };
+        assert_eq!(line("size 10 10\nsphere 0 0 -5 x diffuse 1 1 1"), 2);
+        assert_eq!(line("\n\nsphere 0 0 -5 1 velvet"), 3);
+        assert_eq!(line("size 10"), 1);
+        assert_eq!(line("size 10 10 10"), 1);
+        assert_eq!(line("size 0 0"), 1);
+        assert_eq!(line("\nsize -4 3"), 2);
+        assert_eq!(line("spp 0"), 1);
+        assert_eq!(line("camera 0 0 0"), 1);
+        assert_eq!(line("light area 0 0 0"), 1);
+        assert!(matches!(super::load_scene("missing.scene"), Err(SceneError::Io(_))));
+    }
+    #[test]
+    fn test_ies_lights() {
+        let ies = TempPath::new("downlight.ies");
+        fs::write(&*ies, "IESNA:LM-63-2002\nTILT=NONE\n1 1000 1 2 1 1 2 0.1 0.1 0\n1.0 1.0 20\n0 90\n0\n1000 0\n").unwrap();
+        let sc = parse_scene(&format!("
+            light point 0 2 0 0.01 0.01 0.01 ies {0}
+            light spot 0 2 0 0 -1 0 0.01 0.01 0.01 30 40 ies {0}
+            light point 0 2 0 0.01 0.01 0.01
+        ", ies.display())).unwrap();
+        let below: Vec<DVec3> = sc.get_light().iter().map(|l| l.sample_li(DVec3::ZERO, DVec2::ZERO).unwrap().radiance).collect();
+        assert_eq!(below, vec![DVec3::splat(2.5), DVec3::splat(2.5), DVec3::splat(0.0025)]);
+        assert!(matches!(parse_scene("\nlight point 0 2 0 1 1 1 ies no/such.ies"), Err(SceneError::Format(2, _))));
+    }
+    #[test]
+    fn test_builtin_scenes() {
+        BUILTIN_SCENES.iter().for_each(|(name, _, text)| {
+            assert!(parse_scene(text).is_ok(), "{}", name);
+            assert!(builtin_scene(name).is_some());
+        });
+        assert!(builtin_scene("none").is_none());
+    }
+}
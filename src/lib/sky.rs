@@ -0,0 +1,170 @@
+use std::f64::consts::PI;
+use glam::DVec3;
+
+use super::{DirectionalLight, deg2rad};
+
+// scene orientation for outdoor lighting: +y up, -z north, +x east
+pub fn sun_direction(elevation: f64, azimuth: f64) -> DVec3 {
+    let (el, az) = (deg2rad(elevation), deg2rad(azimuth));
+    DVec3::new(el.cos() * az.sin(), el.sin(), -el.cos() * az.cos())
+}
+// solar (elevation, azimuth) in degrees for a utc time and a place (latitude
+// north, longitude east), noaa low accuracy equations; azimuth is clockwise from north
+#[allow(dead_code)]
+pub fn sun_position(year: i32, month: u32, day: u32, hour_utc: f64, latitude: f64, longitude: f64) -> (f64, f64) {
+    let leap = (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
+    let days_before = [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334];
+    let month = month.clamp(1, 12) as usize;
+    let doy = days_before[month - 1] + day as i32 + i32::from(leap && month > 2);
+    let year_len = if leap { 366. } else { 365. };
+    let g = 2. * PI / year_len * (doy as f64 - 1. + (hour_utc - 12.) / 24.);
+    let eqtime = 229.18 * (0.000075 + 0.001868 * g.cos() - 0.032077 * g.sin() - 0.014615 * (2. * g).cos() - 0.040849 * (2. * g).sin());
+    let decl = 0.006918 - 0.399912 * g.cos() + 0.070257 * g.sin() - 0.006758 * (2. * g).cos() + 0.000907 * (2. * g).sin() - 0.002697 * (3. * g).cos() + 0.00148 * (3. * g).sin();
+    let true_solar = hour_utc * 60. + eqtime + 4. * longitude;
+    let ha = deg2rad(true_solar / 4. - 180.);
+    let lat = deg2rad(latitude);
+    let cos_zenith = (lat.sin() * decl.sin() + lat.cos() * decl.cos() * ha.cos()).clamp(-1., 1.);
+    let elevation = 90. - cos_zenith.acos().to_degrees();
+    // measured from south towards west, then turned to north clockwise
+    let azimuth = ha.sin().atan2(ha.cos() * lat.sin() - decl.tan() * lat.cos()).to_degrees() + 180.;
+    (elevation, azimuth.rem_euclid(360.))
+}
+pub fn xyz_to_srgb(c: DVec3) -> DVec3 {
+    DVec3::new(
+        3.2406 * c.x - 1.5372 * c.y - 0.4986 * c.z,
+        -0.9689 * c.x + 1.8758 * c.y + 0.0415 * c.z,
+        0.0557 * c.x - 0.2040 * c.y + 1.0570 * c.z,
+    )
+}
+fn perez(theta: f64, gamma: f64, c: &[f64; 5]) -> f64 {
+    (1. + c[0] * (c[1] / theta.cos().max(0.01)).exp()) * (1. + c[2] * (c[3] * gamma).exp() + c[4] * gamma.cos().powi(2))
+}
+// preetham et al. 1999 clear sky. radiance comes out in kcd/m^2 times `intensity`,
+// the sun irradiance in klux times `intensity`, so both stay in proportion
+#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sky {
+    pub sun_dir: DVec3,
+    pub turbidity: f64,
+    pub ground_albedo: DVec3,
+    pub intensity: f64,
+    coeffs: [[f64; 5]; 3],
+    zenith: DVec3,
+    ground: DVec3,
+}
+#[allow(dead_code)]
+impl Sky {
+    pub const SUN_ANGULAR_DIAMETER: f64 = 0.53;
+    pub fn new(elevation: f64, azimuth: f64, turbidity: f64, ground_albedo: DVec3) -> Self {
+        let t = turbidity.clamp(1.7, 10.);
+        let sun_dir = sun_direction(elevation, azimuth);
+        let theta_s = sun_dir.y.clamp(0., 1.).acos();
+        let coeffs = [
+            [0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703],
+            [-0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452],
+            [-0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529],
+        ];
+        let chi = (4. / 9. - t / 120.) * (PI - 2. * theta_s);
+        let yz = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192).max(0.);
+        let (t2, s, s2, s3) = (t * t, theta_s, theta_s * theta_s, theta_s * theta_s * theta_s);
+        let xz = t2 * (0.00166 * s3 - 0.00375 * s2 + 0.00209 * s) + t * (-0.02903 * s3 + 0.06377 * s2 - 0.03202 * s + 0.00394) + (0.11693 * s3 - 0.21196 * s2 + 0.06052 * s + 0.25886);
+        let zy = t2 * (0.00275 * s3 - 0.00610 * s2 + 0.00317 * s) + t * (-0.04214 * s3 + 0.08970 * s2 - 0.04153 * s + 0.00516) + (0.15346 * s3 - 0.26756 * s2 + 0.06670 * s + 0.26688);
+        let mut sky = Self { sun_dir, turbidity: t, ground_albedo, intensity: 0.05, coeffs, zenith: DVec3::new(yz, xz, zy), ground: DVec3::ZERO };
+        sky.ground = sky.ground_radiance();
+        sky
+    }
+    // sky radiance above the horizon, a diffuse ground lit by sky and sun below it; no sun disc
+    pub fn eval(&self, dir: DVec3) -> DVec3 {
+        let d = dir.normalize();
+        if d.y <= 0. {
+            return self.ground * self.intensity;
+        }
+        self.sky_yxy(d) * self.intensity
+    }
+    // what a camera sees: eval plus the sun disc
+    pub fn eval_with_sun(&self, dir: DVec3) -> DVec3 {
+        let half = deg2rad(Sky::SUN_ANGULAR_DIAMETER * 0.5);
+        match dir.normalize().dot(self.sun_dir) >= half.cos() {
+            true => self.eval(dir) + self.sun_irradiance() / (2. * PI * (1. - half.cos())),
+            false => self.eval(dir),
+        }
+    }
+    fn sky_yxy(&self, d: DVec3) -> DVec3 {
+        let theta = d.y.clamp(0., 1.).acos();
+        let gamma = d.dot(self.sun_dir).clamp(-1., 1.).acos();
+        let theta_s = self.sun_dir.y.clamp(0., 1.).acos();
+        let [y, x, yy] = [0, 1, 2].map(|i| perez(theta, gamma, &self.coeffs[i]) / perez(0., theta_s, &self.coeffs[i]));
+        let (lum, cx, cy) = (self.zenith.x * y, self.zenith.y * x, self.zenith.z * yy);
+        match cy > 0. {
+            true => xyz_to_srgb(DVec3::new(cx * lum / cy, lum, (1. - cx - cy) * lum / cy)).max(DVec3::ZERO),
+            false => DVec3::ZERO,
+        }
+    }
+    // direct sun through the atmosphere: rayleigh and angstrom aerosol extinction
+    // evaluated at representative red, green and blue wavelengths (micrometres)
+    pub fn sun_irradiance(&self) -> DVec3 {
+        if self.sun_dir.y <= 0. {
+            return DVec3::ZERO;
+        }
+        let zenith_deg = self.sun_dir.y.acos().to_degrees();
+        let air_mass = 1. / (self.sun_dir.y + 0.15 * (93.885 - zenith_deg).powf(-1.253));
+        let beta = 0.04608 * self.turbidity - 0.04586;
+        let trans = |lambda: f64| (-air_mass * (0.008735 * lambda.powf(-4.08) + beta * lambda.powf(-1.3))).exp();
+        DVec3::new(trans(0.68), trans(0.55), trans(0.44)) * 100. * self.intensity
+    }
+    // the matching light, to be added next to the sky environment
+    pub fn sun_light(&self) -> DirectionalLight {
+        DirectionalLight { dir: -self.sun_dir, irradiance: self.sun_irradiance(), angular_diameter: Sky::SUN_ANGULAR_DIAMETER }
+    }
+    fn ground_radiance(&self) -> DVec3 {
+        // irradiance on the horizontal ground from the upper hemisphere, midpoint rule
+        let (nt, np) = (16, 32);
+        let mut e = DVec3::ZERO;
+        for i in 0..nt {
+            let theta = (i as f64 + 0.5) / nt as f64 * PI / 2.;
+            for j in 0..np {
+                let phi = (j as f64 + 0.5) / np as f64 * 2. * PI;
+                let d = DVec3::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin());
+                e += self.sky_yxy(d) * theta.cos() * theta.sin();
+            }
+        }
+        e *= (PI / 2. / nt as f64) * (2. * PI / np as f64);
+        let sun = self.sun_irradiance() / self.intensity * self.sun_dir.y.max(0.);
+        self.ground_albedo * (e + sun) / PI
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::DVec3;
+
+    use crate::lib::{Sky, sun_position, sun_direction};
+
+    #[test]
+    fn test_sun_position() {
+        // greenwich, spring equinox around solar noon: sun due south, 90 - 51.5 high
+        let (el, az) = sun_position(2024, 3, 20, 12.1, 51.48, 0.);
+        assert!((el - 38.5).abs() < 1., "{}", el);
+        assert!((az - 180.).abs() < 3., "{}", az);
+        // morning sun is in the east, evening in the west
+        assert!(sun_position(2024, 6, 21, 7., 51.48, 0.).1 < 180.);
+        assert!(sun_position(2024, 6, 21, 17., 51.48, 0.).1 > 180.);
+        assert!(sun_position(2024, 12, 21, 0., 51.48, 0.).0 < 0.);
+        assert!((sun_direction(0., 90.) - DVec3::X).length() < 1e-12);
+    }
+    #[test]
+    fn test_sky_radiance() {
+        let sky = Sky::new(30., 180., 3., DVec3::splat(0.3));
+        let zenith = sky.eval(DVec3::Y);
+        assert!(zenith.min_element() > 0.);
+        // blue sky: more blue than red overhead, brighter around the sun
+        assert!(zenith.z > zenith.x);
+        let near_sun = sky.eval(sky.sun_dir + DVec3::new(0., 0.05, 0.));
+        assert!(near_sun.y > zenith.y);
+        assert!(sky.eval(-DVec3::Y).max_element() > 0.);
+        // reddish sun at low elevation
+        let low = Sky::new(5., 180., 3., DVec3::splat(0.3)).sun_irradiance();
+        assert!(low.x > low.z);
+        assert!(sky.eval_with_sun(sky.sun_dir).y > 100. * sky.eval(sky.sun_dir).y);
+    }
+}
@@ -0,0 +1,174 @@
+use std::sync::OnceLock;
+use glam::DVec3;
+
+use super::xyz_to_srgb;
+
+// visible range traced in spectral mode, nanometres
+pub const LAMBDA_MIN: f64 = 380.;
+pub const LAMBDA_MAX: f64 = 720.;
+
+// piecewise gaussian used by the cie fit below
+fn lobe(x: f64, mu: f64, sigma_lo: f64, sigma_hi: f64) -> f64 {
+    let t = (x - mu) / if x < mu { sigma_lo } else { sigma_hi };
+    (-0.5 * t * t).exp()
+}
+// cie 1931 2 degree colour matching functions, multi-lobe fit of wyman et al. 2013
+pub fn cie_xyz(lambda: f64) -> DVec3 {
+    DVec3::new(
+        1.056 * lobe(lambda, 599.8, 37.9, 31.0) + 0.362 * lobe(lambda, 442.0, 16.0, 26.7) - 0.065 * lobe(lambda, 501.1, 20.4, 26.2),
+        0.821 * lobe(lambda, 568.8, 46.9, 40.5) + 0.286 * lobe(lambda, 530.9, 16.3, 31.1),
+        1.217 * lobe(lambda, 437.0, 11.8, 36.0) + 0.681 * lobe(lambda, 459.0, 26.0, 13.8),
+    )
+}
+// linear srgb of a constant unit spectrum, what spectral results are balanced against
+fn white_rgb() -> DVec3 {
+    static WHITE: OnceLock<DVec3> = OnceLock::new();
+    *WHITE.get_or_init(|| {
+        let n = 1000;
+        let step = (LAMBDA_MAX - LAMBDA_MIN) / n as f64;
+        let xyz = (0..n).map(|i| cie_xyz(LAMBDA_MIN + (i as f64 + 0.5) * step)).fold(DVec3::ZERO, |a, b| a + b) * step;
+        xyz_to_srgb(xyz)
+    })
+}
+// smits 1999 basis spectra, ten bins evenly spread over the visible range
+const SMITS_WHITE: [f64; 10] = [1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000];
+const SMITS_CYAN: [f64; 10] = [0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000];
+const SMITS_MAGENTA: [f64; 10] = [1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959];
+const SMITS_YELLOW: [f64; 10] = [0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840];
+const SMITS_RED: [f64; 10] = [0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149];
+const SMITS_GREEN: [f64; 10] = [0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025];
+const SMITS_BLUE: [f64; 10] = [1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496];
+
+fn smits_bin(table: &[f64; 10], lambda: f64) -> f64 {
+    // linear between bin centres, constant past the outer ones
+    let x = ((lambda - LAMBDA_MIN) / (LAMBDA_MAX - LAMBDA_MIN) * 10. - 0.5).clamp(0., 9.);
+    let i = (x as usize).min(8);
+    let t = x - i as f64;
+    table[i] * (1. - t) + table[i + 1] * t
+}
+// value at `lambda` of a smooth spectrum with (roughly) the given linear srgb colour
+pub fn rgb_to_spectrum(rgb: DVec3, lambda: f64) -> f64 {
+    let s = |table: &[f64; 10]| smits_bin(table, lambda);
+    let (r, g, b) = (rgb.x, rgb.y, rgb.z);
+    if r <= g && r <= b {
+        r * s(&SMITS_WHITE) + match g <= b {
+            true => (g - r) * s(&SMITS_CYAN) + (b - g) * s(&SMITS_BLUE),
+            false => (b - r) * s(&SMITS_CYAN) + (g - b) * s(&SMITS_GREEN),
+        }
+    } else if g <= r && g <= b {
+        g * s(&SMITS_WHITE) + match r <= b {
+            true => (r - g) * s(&SMITS_MAGENTA) + (b - r) * s(&SMITS_BLUE),
+            false => (b - g) * s(&SMITS_MAGENTA) + (r - b) * s(&SMITS_RED),
+        }
+    } else {
+        b * s(&SMITS_WHITE) + match r <= g {
+            true => (r - b) * s(&SMITS_YELLOW) + (g - r) * s(&SMITS_GREEN),
+            false => (g - b) * s(&SMITS_YELLOW) + (r - g) * s(&SMITS_RED),
+        }
+    }
+}
+// hero wavelength sampling: a uniformly chosen hero plus two more rotated by a third
+// of the range, carried in the three channels cast_ray otherwise uses for rgb.
+// once a path has refracted dispersively only the hero is still meaningful
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Wavelengths {
+    pub lambda: DVec3,
+    pub terminated: bool,
+}
+impl Wavelengths {
+    pub fn sample(u: f64) -> Self {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let at = |k: f64| LAMBDA_MIN + (u + k / 3.).fract() * range;
+        Self { lambda: DVec3::new(at(0.), at(1.), at(2.)), terminated: false }
+    }
+    pub fn hero(&self) -> f64 {
+        self.lambda.x
+    }
+    pub fn terminate(&self) -> Self {
+        Self { terminated: true, ..*self }
+    }
+    // spectral values of an rgb quantity at the three wavelengths
+    pub fn spectrum_of(self, rgb: DVec3) -> DVec3 {
+        DVec3::new(rgb_to_spectrum(rgb, self.lambda.x), rgb_to_spectrum(rgb, self.lambda.y), rgb_to_spectrum(rgb, self.lambda.z))
+    }
+    // one sample's contribution to the pixel: xyz estimate, then linear srgb
+    // balanced so that a constant spectrum comes out white
+    pub fn to_rgb(self, l: DVec3) -> DVec3 {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let xyz = (cie_xyz(self.lambda.x) * l.x + cie_xyz(self.lambda.y) * l.y + cie_xyz(self.lambda.z) * l.z) * range / 3.;
+        xyz_to_srgb(xyz) / white_rgb()
+    }
+}
+// wavelength dependent refractive index, wavelengths in micrometres inside
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dispersion {
+    // n = a + b / lambda^2
+    Cauchy { a: f64, b: f64 },
+    // n^2 = 1 + sum b_i lambda^2 / (lambda^2 - c_i)
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+#[allow(dead_code)]
+impl Dispersion {
+    pub fn bk7() -> Self {
+        Dispersion::Sellmeier { b: [1.03961212, 0.231792344, 1.01046945], c: [0.00600069867, 0.0200179144, 103.560653] }
+    }
+    pub fn fused_silica() -> Self {
+        Dispersion::Sellmeier { b: [0.6961663, 0.4079426, 0.8974794], c: [0.004679148, 0.013512063, 97.93400254] }
+    }
+    // dense flint, strong rainbows
+    pub fn sf11() -> Self {
+        Dispersion::Sellmeier { b: [1.73759695, 0.313747346, 1.89878101], c: [0.013188707, 0.0623068142, 155.23629] }
+    }
+    pub fn ior(&self, lambda_nm: f64) -> f64 {
+        let l2 = (lambda_nm * 1e-3).powi(2);
+        match self {
+            Dispersion::Cauchy { a, b } => a + b / l2,
+            Dispersion::Sellmeier { b, c } => (1. + (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum::<f64>()).sqrt(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::DVec3;
+
+    use super::{Wavelengths, Dispersion, cie_xyz, rgb_to_spectrum};
+
+    // average of many hero samples of a spectrum given as rgb
+    fn round_trip(rgb: DVec3) -> DVec3 {
+        let n = 2000;
+        (0..n).map(|i| {
+            let wl = Wavelengths::sample((i as f64 + 0.5) / n as f64);
+            wl.to_rgb(wl.spectrum_of(rgb))
+        }).fold(DVec3::ZERO, |a, b| a + b) / n as f64
+    }
+    #[test]
+    fn test_color_matching() {
+        assert!(cie_xyz(555.).y > 0.99);
+        assert!(cie_xyz(450.).z > cie_xyz(450.).y);
+        let wl = Wavelengths::sample(0.9);
+        assert!((wl.lambda.y - 380. - 0.2333333 * 340.).abs() < 1e-3);
+        assert!(wl.lambda.z > wl.lambda.y && wl.lambda.x > wl.lambda.z);
+    }
+    #[test]
+    fn test_rgb_round_trip() {
+        assert!((round_trip(DVec3::ONE) - DVec3::ONE).length() < 1e-3);
+        assert!((round_trip(DVec3::splat(0.3)) - DVec3::splat(0.3)).length() < 1e-3);
+        // saturated primaries keep their hue, if not their exact saturation
+        for (i, c) in [DVec3::X, DVec3::Y, DVec3::Z].into_iter().enumerate() {
+            let back = round_trip(c);
+            assert!(back[i] > 0.6 && back[i] > 2. * back[(i + 1) % 3] && back[i] > 2. * back[(i + 2) % 3], "{}", back);
+        }
+        assert!((rgb_to_spectrum(DVec3::new(0.2, 0.5, 0.9), 600.) - rgb_to_spectrum(DVec3::new(0.2, 0.5, 0.9), 450.)).abs() > 0.1);
+    }
+    #[test]
+    fn test_dispersion() {
+        // catalogue value at the helium d line
+        assert!((Dispersion::bk7().ior(587.56) - 1.5168).abs() < 1e-4);
+        let flint = Dispersion::sf11();
+        assert!(flint.ior(450.) > flint.ior(650.));
+        let cauchy = Dispersion::Cauchy { a: 1.5, b: 0.004 };
+        assert!((cauchy.ior(400.) - 1.525).abs() < 1e-12);
+    }
+}
@@ -1,9 +1,10 @@
+use std::f64::consts::PI;
 use glam::{DVec3,DVec2};
 use crate::lib::triangle::Object;
 
-use super::{Material, SpecularProperties};
+use super::{Material, SpecularProperties, ShapeBsdf, Texture, NormalMap, Emission, LightSource, SphereLight, Light, Aabb, Keyframes, coordinate_system, deg2rad};
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Sphere {
     pub center: DVec3,
     pub radius: f64,
@@ -11,7 +12,63 @@ pub struct Sphere {
     pub material: Material,
     pub ior: f64,
     pub specular: SpecularProperties,
-    pub diffuse_color: DVec3,
+    pub diffuse: Texture,
+    // uv parameterization: v runs from the -pole (0) to the +pole (1),
+    // the u = 0 seam is rotated by `seam` degrees around the pole
+    pub pole: DVec3,
+    pub seam: f64,
+    pub normal_map: Option<NormalMap>,
+    // spheres only emit outwards, two_sided is ignored
+    pub emission: Option<Emission>,
+    // keyframed centre over the shutter, replaces `center` when set. as an emitter
+    // the sphere still lights the scene from `center`
+    pub motion: Option<Keyframes<DVec3>>,
+}
+impl Sphere {
+    // grey diffuse sphere, the remaining fields can be set with struct update syntax
+    pub fn new(center: DVec3, radius: f64, material: Material) -> Self {
+        Sphere {
+            center,
+            radius,
+            radius2: radius * radius,
+            material,
+            ior: 1.3,
+            specular: SpecularProperties(25.0, 0.8, 0.2),
+            diffuse: Texture::Constant(DVec3::splat(0.2)),
+            pole: DVec3::Y,
+            seam: 0.,
+            normal_map: None,
+            emission: None,
+            motion: None,
+        }
+    }
+    pub fn center_at(&self, time: f64) -> DVec3 {
+        self.motion.as_ref().map_or(self.center, |m| m.at(time))
+    }
+    // (t, b, pole) frame the longitude is measured in
+    fn frame(&self) -> (DVec3, DVec3, DVec3) {
+        let pole = self.pole.normalize();
+        let (t, b) = coordinate_system(pole);
+        (t, b, pole)
+    }
+    // polar angle from the pole and longitude in [0, 2pi)
+    fn spherical(&self, p: DVec3) -> (f64, f64) {
+        let (t, b, pole) = self.frame();
+        let d = (p - self.center).normalize();
+        let theta = d.dot(pole).clamp(-1., 1.).acos();
+        let phi = (d.dot(b).atan2(d.dot(t)) + deg2rad(self.seam)).rem_euclid(2. * PI);
+        (theta, phi)
+    }
+}
+impl Default for Sphere {
+    fn default() -> Self {
+        Sphere::new(DVec3::ZERO, 1., Material::DiffuseAndGlossy)
+    }
+}
+// single colour diffuse sphere for tests
+#[cfg(test)]
+pub fn diffuse_ball(center: DVec3, radius: f64, albedo: DVec3) -> Sphere {
+    Sphere { diffuse: Texture::Constant(albedo), ..Sphere::new(center, radius, Material::DiffuseAndGlossy) }
 }
 #[derive(Debug)]
 pub enum SolveError {
@@ -22,9 +79,9 @@ pub fn solve_quadratic(a: f64, b: f64, c: f64) -> Result<(f64, f64), SolveError>
     let discr = b * b - 4. * a * c;
     let x0;let x1;
     match discr {
-        discr if discr == 0. => {
+        0. => {
             x0 = -0.5 * b / a;
-            x1 = x0.clone();
+            x1 = x0;
         },
         discr if discr < 0. => {
             return Err(SolveError::NoSolution)
@@ -42,8 +99,14 @@ pub fn solve_quadratic(a: f64, b: f64, c: f64) -> Result<(f64, f64), SolveError>
 }
 
 impl Object for Sphere {
-    fn intersection(&self, light: super::Light, dir: DVec3) -> (bool, f64, usize, DVec2) {
-        let l = light.org - self.center;
+    fn kind(&self) -> &'static str {
+        "sphere"
+    }
+    fn intersection(&self, light: Light, dir: DVec3) -> (bool, f64, usize, DVec2) {
+        self.intersection_at(light, dir, 0.)
+    }
+    fn intersection_at(&self, light: Light, dir: DVec3, time: f64) -> (bool, f64, usize, DVec2) {
+        let l = light.org - self.center_at(time);
         let a = dir.dot(dir);
         let b = 2. * l.dot(dir);
         let c = l.dot(l) - self.radius2;
@@ -61,11 +124,35 @@ impl Object for Sphere {
         }
     }
 
+    fn at_time(&self, time: f64) -> Box<dyn Object> {
+        Box::new(Sphere { center: self.center_at(time), motion: None, ..self.clone() })
+    }
+    // a sphere moving along a segment stays in the hull of its ends
+    fn bounds(&self) -> Aabb {
+        match &self.motion {
+            Some(m) => m.keys().iter().fold(Aabb::empty(), |b, (_, c)| b.union(&Aabb::around(*c, self.radius))),
+            None => Aabb::around(self.center, self.radius),
+        }
+    }
     fn get_surface_properties(&self, p:DVec3, _px:DVec3, _idx:usize, _uv:DVec2) -> (DVec3, DVec2) {
-        ((p - self.center).normalize(), DVec2::new(0., 0.))
+        let (theta, phi) = self.spherical(p);
+        ((p - self.center).normalize(), DVec2::new(phi / (2. * PI), 1. - theta / PI))
+    }
+    fn get_tangents(&self, p: DVec3, _idx: usize, _uv: DVec2) -> (DVec3, DVec3) {
+        let (t, b, pole) = self.frame();
+        let (theta, phi) = self.spherical(p);
+        let phi = phi - deg2rad(self.seam);
+        let (st, ct) = theta.sin_cos();
+        let (sp, cp) = phi.sin_cos();
+        let dpdu = 2. * PI * self.radius * st * (-sp * t + cp * b);
+        let dpdv = -PI * self.radius * (ct * cp * t + ct * sp * b - st * pole);
+        (dpdu, dpdv)
     }
     fn get_material_properties(&self) -> super::Material {
-        self.material
+        self.material.clone()
+    }
+    fn get_bsdf(&self) -> ShapeBsdf<'_> {
+        self.material.bsdf(&self.diffuse, self.ior, self.specular)
     }
     fn get_ior(&self) -> f64 {
         self.ior
@@ -73,15 +160,25 @@ impl Object for Sphere {
     fn get_specular_properties(&self) -> SpecularProperties {
         self.specular
     }
-    fn eval_diffuse_color(&self, _vx: DVec2) -> DVec3 {
-        self.diffuse_color
+    fn eval_diffuse_color(&self, vx: DVec2, p: DVec3) -> DVec3 {
+        self.diffuse.eval(vx, p)
+    }
+    fn get_normal_map(&self) -> Option<&NormalMap> {
+        self.normal_map.as_ref()
+    }
+    fn get_emission(&self) -> Option<Emission> {
+        self.emission
+    }
+    fn as_light(&self) -> Option<Box<dyn LightSource>> {
+        let e = self.emission?;
+        Some(Box::new(SphereLight { center: self.center, radius: self.radius, radiance: e.radiance }))
     }
 }
 #[cfg(test)]
 mod tests {
     use glam::{DVec3, DVec2};
 
-    use crate::lib::{Light, solve_quadratic, Object, Material, SpecularProperties};
+    use crate::lib::{Light, solve_quadratic, Object, Material, SpecularProperties, Texture};
 
     use super::Sphere;
 
@@ -100,10 +197,10 @@ mod tests {
     }
     #[test]
     fn test_intersection() {
-        let light = Light { org: DVec3::new(0., 0., 0.), inten: DVec3::new(0.88, 0.42, 0.)};
-        let sp = Sphere { center: DVec3::new(2., 0., 0.), radius: 1.6, radius2: 2.56, material: Material::DiffuseAndGlossy, ior: 1.3, specular: SpecularProperties(25.0, 0.8, 0.2), diffuse_color: DVec3::splat(0.2)};
+        let light = Light { org: DVec3::new(0., 0., 0.), inten: DVec3::new(0.88, 0.42, 0.), ies: None };
+        let sp = Sphere { center: DVec3::new(2., 0., 0.), radius: 1.6, radius2: 2.56, material: Material::DiffuseAndGlossy, ior: 1.3, specular: SpecularProperties(25.0, 0.8, 0.2), diffuse: Texture::Constant(DVec3::splat(0.2)), ..Default::default() };
         let (res, t, b1, b2) = sp.intersection(light, DVec3::new(0.88, 0.42, 0.));
-        assert_eq!(res, true);
+        assert!(res);
         assert_eq!(t, 0.4683376845365324);
         assert_eq!(b1, 0);
         assert_eq!(b2, DVec2::ZERO);
@@ -111,16 +208,44 @@ mod tests {
     #[test]
     fn test_eval_diffuse_color() {
         // let light = Light { org: DVec3::new(0., 0., 0.), dir: DVec3::new(0.88, 0.42, 0.)};
-        let sp = Sphere { center: DVec3::new(2., 0., 0.), radius: 1.6, radius2: 2.56, material: Material::DiffuseAndGlossy, ior: 1.3, specular: SpecularProperties(25., 0.8, 0.2), diffuse_color: DVec3::splat(0.2) };
-        let ss = sp.eval_diffuse_color(DVec2::new(1.2, 3.4));
+        let sp = Sphere { center: DVec3::new(2., 0., 0.), radius: 1.6, radius2: 2.56, material: Material::DiffuseAndGlossy, ior: 1.3, specular: SpecularProperties(25., 0.8, 0.2), diffuse: Texture::checker(), ..Default::default() };
+        let ss = sp.eval_diffuse_color(DVec2::new(1.2, 3.4), DVec3::ZERO);
         // dbg!(ss);
         assert_eq!(DVec3::new(0.815, 0.235, 0.031), ss);
     }
     #[test]
     fn test_get_surface_properties() {
         // let light = Light { org: DVec3::new(0., 0., 0.), dir: DVec3::new(0.88, 0.42, 0.)};
-        let sp = Sphere { center: DVec3::new(2., 0., 0.), radius: 1.6, radius2: 2.56, material: Material::DiffuseAndGlossy, ior: 1.3, specular: SpecularProperties(25.0, 0.8, 0.2), diffuse_color: DVec3::splat(0.2) };
+        let sp = Sphere { center: DVec3::new(2., 0., 0.), radius: 1.6, radius2: 2.56, material: Material::DiffuseAndGlossy, ior: 1.3, specular: SpecularProperties(25.0, 0.8, 0.2), diffuse: Texture::Constant(DVec3::splat(0.2)), ..Default::default() };
         let p = DVec3::new(0., 0., 0.);
         assert_eq!(sp.get_surface_properties(p, DVec3::new(0., 0., 0.), 0usize, DVec2::new(0., 0.)).0, DVec3::new(-1., 0., 0.))
     }
-}
\ No newline at end of file
+    #[test]
+    fn test_spherical_uv() {
+        let mut sp = Sphere { center: DVec3::new(2., 0., 0.), radius: 1.6, radius2: 2.56, material: Material::DiffuseAndGlossy, ior: 1.3, specular: SpecularProperties(25.0, 0.8, 0.2), diffuse: Texture::checker(), ..Default::default() };
+        let (_, st) = sp.get_surface_properties(DVec3::new(2., 1.6, 0.), DVec3::ZERO, 0, DVec2::ZERO);
+        assert!((st.y - 1.).abs() < 1e-12);
+        let (_, st) = sp.get_surface_properties(DVec3::new(3.6, 0., 0.), DVec3::ZERO, 0, DVec2::ZERO);
+        assert!((st - DVec2::new(0., 0.5)).length() < 1e-12);
+        sp.seam = 90.;
+        let (_, st) = sp.get_surface_properties(DVec3::new(3.6, 0., 0.), DVec3::ZERO, 0, DVec2::ZERO);
+        assert!((st.x - 0.25).abs() < 1e-12);
+        sp.pole = DVec3::Z;
+        let (_, st) = sp.get_surface_properties(DVec3::new(2., 0., -1.6), DVec3::ZERO, 0, DVec2::ZERO);
+        assert!(st.y.abs() < 1e-12);
+    }
+    #[test]
+    fn test_tangents() {
+        let sp = Sphere { diffuse: Texture::checker(), pole: DVec3::new(1., 1., 0.), seam: 30., ..Sphere::new(DVec3::ZERO, 2., Material::DiffuseAndGlossy) };
+        let p = DVec3::new(0.3, -1.1, 1.6).normalize() * 2.;
+        let (n, st) = sp.get_surface_properties(p, DVec3::ZERO, 0, DVec2::ZERO);
+        let (dpdu, dpdv) = sp.get_tangents(p, 0, DVec2::ZERO);
+        assert!(dpdu.dot(n).abs() < 1e-9 && dpdv.dot(n).abs() < 1e-9);
+        assert!(dpdu.cross(dpdv).dot(n) > 0.);
+        // finite difference along u agrees with dpdu
+        let h = 1e-6;
+        let q = (p + dpdu * h).normalize() * 2.;
+        let (_, st2) = sp.get_surface_properties(q, DVec3::ZERO, 0, DVec2::ZERO);
+        assert!(((st2.x - st.x) / h - 1.).abs() < 1e-3);
+    }
+}
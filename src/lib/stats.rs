@@ -0,0 +1,199 @@
+use std::{cell::RefCell, fmt, time::Duration};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RayKind {
+    Primary,
+    Shadow,
+    Reflection,
+    Refraction,
+}
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RayCounts {
+    pub primary: u64,
+    pub shadow: u64,
+    pub reflection: u64,
+    pub refraction: u64,
+}
+impl RayCounts {
+    pub fn total(&self) -> u64 {
+        self.primary + self.shadow + self.reflection + self.refraction
+    }
+}
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IntersectionCounts {
+    pub calls: u64,
+    pub hits: u64,
+}
+// wall clock time of each part of a render
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timings {
+    // checkpoint loading and setting up the frame
+    pub prep: Duration,
+    // building acceleration structures. objects are only culled by their
+    // bounds so far, there is nothing to build and this stays zero
+    pub acceleration: Duration,
+    pub rendering: Duration,
+    // denoising, encoding and writing the image files
+    pub output: Duration,
+}
+// what a render did, returned by render
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RenderStats {
+    pub rays: RayCounts,
+    // Object::intersection calls and hits by Object::kind, in first seen order,
+    // the denoiser's first hits included. objects whose bounds a ray misses
+    // are not called
+    pub intersections: Vec<(&'static str, IntersectionCounts)>,
+    // camera samples and the deepest bounce each reached
+    pub paths: u64,
+    pub path_depth: u64,
+    // rays dropped for going over Scene::max_depth
+    pub max_depth_reached: u64,
+    pub timings: Timings,
+}
+impl RenderStats {
+    // adds up the counts and times of two parts of a render
+    pub fn merge(&mut self, other: &RenderStats) {
+        let (a, b) = (&mut self.rays, &other.rays);
+        (a.primary, a.shadow, a.reflection, a.refraction) = (a.primary + b.primary, a.shadow + b.shadow, a.reflection + b.reflection, a.refraction + b.refraction);
+        other.intersections.iter().for_each(|(kind, c)| match self.intersections.iter_mut().find(|(k, _)| k == kind) {
+            Some((_, mine)) => {
+                mine.calls += c.calls;
+                mine.hits += c.hits;
+            }
+            None => self.intersections.push((kind, *c)),
+        });
+        self.paths += other.paths;
+        self.path_depth += other.path_depth;
+        self.max_depth_reached += other.max_depth_reached;
+        let (a, b) = (&mut self.timings, &other.timings);
+        (a.prep, a.acceleration, a.rendering, a.output) = (a.prep + b.prep, a.acceleration + b.acceleration, a.rendering + b.rendering, a.output + b.output);
+    }
+    pub fn average_path_depth(&self) -> f64 {
+        match self.paths {
+            0 => 0.,
+            n => self.path_depth as f64 / n as f64,
+        }
+    }
+}
+impl fmt::Display for RenderStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ms = |d: Duration| d.as_secs_f64() * 1e3;
+        writeln!(f, "{:<24}{:>14}", "rays", self.rays.total())?;
+        [("  primary", self.rays.primary), ("  shadow", self.rays.shadow), ("  reflection", self.rays.reflection), ("  refraction", self.rays.refraction)]
+            .iter().try_for_each(|(name, n)| writeln!(f, "{:<24}{:>14}", name, n))?;
+        writeln!(f, "{:<24}{:>14}{:>14}", "intersections", "calls", "hits")?;
+        self.intersections.iter().try_for_each(|(kind, c)| writeln!(f, "  {:<22}{:>14}{:>14}", kind, c.calls, c.hits))?;
+        writeln!(f, "{:<24}{:>14}", "paths", self.paths)?;
+        writeln!(f, "{:<24}{:>14.3}", "  average depth", self.average_path_depth())?;
+        writeln!(f, "{:<24}{:>14}", "  max depth reached", self.max_depth_reached)?;
+        writeln!(f, "{:<24}{:>14}", "time (ms)", "")?;
+        [("  prep", self.timings.prep), ("  acceleration", self.timings.acceleration), ("  rendering", self.timings.rendering), ("  output", self.timings.output)]
+            .iter().try_for_each(|(name, d)| writeln!(f, "{:<24}{:>14.1}", name, ms(*d)))
+    }
+}
+// the counters of the render running on this thread
+#[derive(Debug, Clone, Default)]
+struct Counters {
+    stats: RenderStats,
+    // deepest bounce of the current camera sample
+    depth: u64,
+}
+thread_local! {
+    static COUNTERS: RefCell<Counters> = RefCell::new(Counters::default());
+}
+fn with_counters(f: impl FnOnce(&mut Counters)) {
+    COUNTERS.with(|c| f(&mut c.borrow_mut()));
+}
+pub fn count_ray(kind: RayKind) {
+    with_counters(|c| {
+        let rays = &mut c.stats.rays;
+        match kind {
+            RayKind::Primary => rays.primary += 1,
+            RayKind::Shadow => rays.shadow += 1,
+            RayKind::Reflection => rays.reflection += 1,
+            RayKind::Refraction => rays.refraction += 1,
+        }
+    });
+}
+pub fn count_intersection(kind: &'static str, hit: bool) {
+    with_counters(|c| {
+        let list = &mut c.stats.intersections;
+        let i = match list.iter().position(|(k, _)| *k == kind) {
+            Some(i) => i,
+            None => {
+                list.push((kind, IntersectionCounts::default()));
+                list.len() - 1
+            }
+        };
+        list[i].1.calls += 1;
+        list[i].1.hits += hit as u64;
+    });
+}
+// cast_ray got to `depth` on the current camera sample
+pub fn count_depth(depth: u64) {
+    with_counters(|c| c.depth = c.depth.max(depth));
+}
+pub fn count_max_depth_reached() {
+    with_counters(|c| c.stats.max_depth_reached += 1);
+}
+// closes the current camera sample
+pub fn count_path() {
+    with_counters(|c| {
+        c.stats.paths += 1;
+        c.stats.path_depth += c.depth;
+        c.depth = 0;
+    });
+}
+pub fn add_time(f: impl FnOnce(&mut Timings) -> &mut Duration, d: Duration) {
+    with_counters(|c| *f(&mut c.stats.timings) += d);
+}
+// counts of another thread's part of the render
+pub fn merge_stats(other: &RenderStats) {
+    with_counters(|c| c.stats.merge(other));
+}
+pub fn rays_traced() -> u64 {
+    COUNTERS.with(|c| c.borrow().stats.rays.total())
+}
+// everything counted on this thread since the last call
+pub fn take_stats() -> RenderStats {
+    COUNTERS.with(|c| c.take().stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{take_stats, count_ray, count_intersection, count_depth, count_path, count_max_depth_reached, add_time, rays_traced, RayKind, IntersectionCounts};
+
+    #[test]
+    fn test_counters() {
+        take_stats();
+        count_ray(RayKind::Primary);
+        count_ray(RayKind::Shadow);
+        count_ray(RayKind::Shadow);
+        count_ray(RayKind::Refraction);
+        assert_eq!(rays_traced(), 4);
+        count_intersection("sphere", true);
+        count_intersection("mesh", false);
+        count_intersection("sphere", false);
+        count_depth(3);
+        count_depth(1);
+        count_path();
+        count_depth(2);
+        count_path();
+        count_max_depth_reached();
+        add_time(|t| &mut t.rendering, Duration::from_millis(5));
+        add_time(|t| &mut t.rendering, Duration::from_millis(5));
+        let stats = take_stats();
+        assert_eq!((stats.rays.primary, stats.rays.shadow, stats.rays.reflection, stats.rays.refraction), (1, 2, 0, 1));
+        assert_eq!(stats.intersections, vec![("sphere", IntersectionCounts { calls: 2, hits: 1 }), ("mesh", IntersectionCounts { calls: 1, hits: 0 })]);
+        assert_eq!(stats.average_path_depth(), 2.5);
+        assert_eq!(stats.max_depth_reached, 1);
+        assert_eq!(stats.timings.rendering, Duration::from_millis(10));
+        // taking them starts over
+        assert_eq!(take_stats(), Default::default());
+        let table = stats.to_string();
+        assert!(table.contains("sphere") && table.contains("average depth"));
+    }
+}
@@ -0,0 +1,28 @@
+use std::{env, fs, ops::Deref, path::PathBuf, process, sync::atomic::{AtomicUsize, Ordering}};
+
+static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+// a path in the system temp dir no other test or test run uses, whatever was
+// written there, file or directory, goes when the guard is dropped, on a
+// failed assert as well
+#[derive(Debug)]
+pub struct TempPath(PathBuf);
+impl TempPath {
+    // the name ends the path, so extensions still pick formats
+    pub fn new(name: &str) -> Self {
+        let n = NEXT.fetch_add(1, Ordering::Relaxed);
+        TempPath(env::temp_dir().join(format!("rs-render-{}-{}-{}", process::id(), n, name)))
+    }
+}
+impl Deref for TempPath {
+    type Target = PathBuf;
+    fn deref(&self) -> &PathBuf {
+        &self.0
+    }
+}
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
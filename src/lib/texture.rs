@@ -0,0 +1,558 @@
+use std::{fmt, fs::File, io::{self, BufRead, BufReader, Read}, path::Path, sync::Arc, f64::consts::PI};
+use glam::{DVec2, DVec3};
+
+use super::{fbm, simplex_fbm, perlin, turbulence, worley};
+
+#[derive(Debug)]
+pub enum TextureError {
+    Io(io::Error),
+    Format(String),
+    Unsupported(String),
+}
+impl fmt::Display for TextureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TextureError::Io(e) => write!(f, "io error: {}", e),
+            TextureError::Format(s) => write!(f, "malformed image: {}", s),
+            TextureError::Unsupported(s) => write!(f, "unsupported image format: {}", s),
+        }
+    }
+}
+impl From<io::Error> for TextureError {
+    fn from(e: io::Error) -> Self {
+        TextureError::Io(e)
+    }
+}
+impl From<png::DecodingError> for TextureError {
+    fn from(e: png::DecodingError) -> Self {
+        TextureError::Format(e.to_string())
+    }
+}
+
+pub fn srgb_to_linear(c: f64) -> f64 {
+    match c <= 0.04045 {
+        true => c / 12.92,
+        false => ((c + 0.055) / 1.055).powf(2.4),
+    }
+}
+
+// linear rgb texels, row 0 is the top of the image
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<DVec3>,
+}
+#[allow(dead_code)]
+impl Image {
+    pub fn new(width: usize, height: usize, data: Vec<DVec3>) -> Self {
+        assert_eq!(width * height, data.len());
+        Self { width, height, data }
+    }
+    pub fn texel(&self, x: usize, y: usize) -> DVec3 {
+        self.data[y * self.width + x]
+    }
+    // bilinear filter at continuous pixel coordinates (texel centers at +0.5)
+    pub fn bilinear(&self, x: f64, y: f64, wrap_x: WrapMode, wrap_y: WrapMode) -> DVec3 {
+        let (x, y) = (x - 0.5, y - 0.5);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let at = |dx: i64, dy: i64| {
+            self.texel(wrap_x.texel_index(x0 as i64 + dx, self.width), wrap_y.texel_index(y0 as i64 + dy, self.height))
+        };
+        at(0, 0).lerp(at(1, 0), fx).lerp(at(0, 1).lerp(at(1, 1), fx), fy)
+    }
+    // 8 and 16 bit formats are decoded from sRGB, hdr is already linear
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Image, TextureError> {
+        Image::load_as(path, true)
+    }
+    // for data maps (normals, heights) whose values must not be decoded
+    pub fn load_linear<P: AsRef<Path>>(path: P) -> Result<Image, TextureError> {
+        Image::load_as(path, false)
+    }
+    fn load_as<P: AsRef<Path>>(path: P, srgb: bool) -> Result<Image, TextureError> {
+        let ext = path.as_ref().extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+        let mut fp = BufReader::new(File::open(path.as_ref())?);
+        let img = match ext.as_str() {
+            "ppm" => Image::read_ppm(&mut fp)?,
+            "png" => Image::read_png(fp)?,
+            "hdr" | "pic" => return Image::read_hdr(&mut fp),
+            _ => return Err(TextureError::Unsupported(ext)),
+        };
+        match srgb {
+            true => Ok(img.srgb_decoded()),
+            false => Ok(img),
+        }
+    }
+    pub fn srgb_decoded(mut self) -> Image {
+        self.data.iter_mut().for_each(|c| *c = DVec3::new(srgb_to_linear(c.x), srgb_to_linear(c.y), srgb_to_linear(c.z)));
+        self
+    }
+    // P3 / P6, 8 or 16 bit, values normalized to [0, 1] but not decoded
+    pub fn read_ppm<R: BufRead>(fp: &mut R) -> Result<Image, TextureError> {
+        let magic = ppm_token(fp)?;
+        let width: usize = parse_token(&ppm_token(fp)?)?;
+        let height: usize = parse_token(&ppm_token(fp)?)?;
+        let maxval: u32 = parse_token(&ppm_token(fp)?)?;
+        if maxval == 0 || maxval > 65535 {
+            return Err(TextureError::Format(format!("bad maxval {}", maxval)));
+        }
+        let mut raw = Vec::with_capacity(width * height * 3);
+        match magic.as_str() {
+            "P3" => {
+                for _ in 0..width * height * 3 {
+                    raw.push(parse_token::<u32>(&ppm_token(fp)?)?);
+                }
+            },
+            "P6" => {
+                let bpc = if maxval < 256 { 1 } else { 2 };
+                let mut buf = vec![0u8; width * height * 3 * bpc];
+                fp.read_exact(&mut buf)?;
+                match bpc {
+                    1 => raw.extend(buf.iter().map(|&b| b as u32)),
+                    _ => raw.extend(buf.chunks_exact(2).map(|b| u16::from_be_bytes([b[0], b[1]]) as u32)),
+                }
+            },
+            m => return Err(TextureError::Unsupported(m.to_string())),
+        }
+        let data = raw.chunks_exact(3).map(|c| {
+            DVec3::new(c[0] as f64, c[1] as f64, c[2] as f64) / maxval as f64
+        }).collect();
+        Ok(Image::new(width, height, data))
+    }
+    pub fn read_png<R: Read>(fp: R) -> Result<Image, TextureError> {
+        let mut decoder = png::Decoder::new(fp);
+        decoder.set_transformations(png::Transformations::EXPAND);
+        let mut reader = decoder.read_info()?;
+        let mut buf = vec![0u8; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf)?;
+        let channels = match info.color_type {
+            png::ColorType::Grayscale => 1,
+            png::ColorType::GrayscaleAlpha => 2,
+            png::ColorType::Rgb => 3,
+            png::ColorType::Rgba => 4,
+            png::ColorType::Indexed => return Err(TextureError::Unsupported("indexed png".to_string())),
+        };
+        let (width, height) = (info.width as usize, info.height as usize);
+        let wide = info.bit_depth == png::BitDepth::Sixteen;
+        let maxval = if wide { 65535. } else { 255. };
+        let mut data = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let line = &buf[y * info.line_size..(y + 1) * info.line_size];
+            for x in 0..width {
+                let sample = |c: usize| {
+                    let i = x * channels + c;
+                    let v = match wide {
+                        true => u16::from_be_bytes([line[2 * i], line[2 * i + 1]]) as f64,
+                        false => line[i] as f64,
+                    };
+                    v / maxval
+                };
+                data.push(match channels {
+                    1 | 2 => DVec3::splat(sample(0)),
+                    _ => DVec3::new(sample(0), sample(1), sample(2)),
+                });
+            }
+        }
+        Ok(Image::new(width, height, data))
+    }
+    // radiance rgbe, flat or new-style run length encoded scanlines
+    pub fn read_hdr<R: BufRead>(fp: &mut R) -> Result<Image, TextureError> {
+        let mut line = String::new();
+        fp.read_line(&mut line)?;
+        if !line.starts_with("#?") {
+            return Err(TextureError::Format("missing radiance header".to_string()));
+        }
+        loop {
+            line.clear();
+            if fp.read_line(&mut line)? == 0 {
+                return Err(TextureError::Format("unexpected end of header".to_string()));
+            }
+            let l = line.trim();
+            if l.is_empty() {
+                break;
+            }
+            if let Some(fmt) = l.strip_prefix("FORMAT=") {
+                if fmt != "32-bit_rle_rgbe" {
+                    return Err(TextureError::Unsupported(fmt.to_string()));
+                }
+            }
+        }
+        line.clear();
+        fp.read_line(&mut line)?;
+        let res: Vec<&str> = line.split_whitespace().collect();
+        let (height, width) = match res.as_slice() {
+            ["-Y", h, "+X", w] => (parse_token::<usize>(h)?, parse_token::<usize>(w)?),
+            _ => return Err(TextureError::Unsupported(line.trim().to_string())),
+        };
+        let mut data = Vec::with_capacity(width * height);
+        let mut scan = vec![[0u8; 4]; width];
+        for _ in 0..height {
+            read_hdr_scanline(fp, &mut scan)?;
+            data.extend(scan.iter().map(|&[r, g, b, e]| match e {
+                0 => DVec3::ZERO,
+                _ => DVec3::new(r as f64, g as f64, b as f64) * 2f64.powi(e as i32 - 136),
+            }));
+        }
+        Ok(Image::new(width, height, data))
+    }
+}
+fn ppm_token<R: BufRead>(fp: &mut R) -> Result<String, TextureError> {
+    let mut tok = String::new();
+    let mut byte = [0u8; 1];
+    loop {
+        if fp.read(&mut byte)? == 0 {
+            break;
+        }
+        match byte[0] {
+            b'#' if tok.is_empty() => {
+                let mut comment = Vec::new();
+                fp.read_until(b'\n', &mut comment)?;
+            },
+            b if b.is_ascii_whitespace() => {
+                if !tok.is_empty() {
+                    break;
+                }
+            },
+            b => tok.push(b as char),
+        }
+    }
+    match tok.is_empty() {
+        true => Err(TextureError::Format("unexpected end of file".to_string())),
+        false => Ok(tok),
+    }
+}
+fn parse_token<T: std::str::FromStr>(tok: &str) -> Result<T, TextureError> {
+    tok.parse().map_err(|_| TextureError::Format(format!("bad number {}", tok)))
+}
+fn read_hdr_scanline<R: Read>(fp: &mut R, scan: &mut [[u8; 4]]) -> Result<(), TextureError> {
+    let width = scan.len();
+    let mut head = [0u8; 4];
+    fp.read_exact(&mut head)?;
+    let rle = (8..0x8000).contains(&width) && head[0] == 2 && head[1] == 2 && head[2] & 0x80 == 0;
+    if !rle {
+        scan[0] = head;
+        for px in scan.iter_mut().skip(1) {
+            fp.read_exact(px)?;
+        }
+        return Ok(());
+    }
+    if ((head[2] as usize) << 8 | head[3] as usize) != width {
+        return Err(TextureError::Format("scanline width mismatch".to_string()));
+    }
+    for c in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let mut count = [0u8; 1];
+            fp.read_exact(&mut count)?;
+            let (run, n) = match count[0] > 128 {
+                true => (true, count[0] as usize - 128),
+                false => (false, count[0] as usize),
+            };
+            if n == 0 || x + n > width {
+                return Err(TextureError::Format("bad scanline run".to_string()));
+            }
+            let mut v = [0u8; 1];
+            match run {
+                true => {
+                    fp.read_exact(&mut v)?;
+                    scan[x..x + n].iter_mut().for_each(|px| px[c] = v[0]);
+                },
+                false => for px in scan[x..x + n].iter_mut() {
+                    fp.read_exact(&mut v)?;
+                    px[c] = v[0];
+                },
+            }
+            x += n;
+        }
+    }
+    Ok(())
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum WrapMode {
+    Repeat,
+    Clamp,
+    Mirror,
+}
+impl WrapMode {
+    pub fn texel_index(&self, i: i64, n: usize) -> usize {
+        let n = n as i64;
+        match self {
+            WrapMode::Repeat => i.rem_euclid(n) as usize,
+            WrapMode::Clamp => i.clamp(0, n - 1) as usize,
+            WrapMode::Mirror => {
+                let m = i.rem_euclid(2 * n);
+                (if m >= n { 2 * n - 1 - m } else { m }) as usize
+            },
+        }
+    }
+}
+// st' = rotate(st * scale) + offset, rotation in degrees
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct UvTransform {
+    pub scale: DVec2,
+    pub offset: DVec2,
+    pub rotation: f64,
+}
+impl UvTransform {
+    pub fn identity() -> Self {
+        Self { scale: DVec2::ONE, offset: DVec2::ZERO, rotation: 0. }
+    }
+    pub fn apply(&self, st: DVec2) -> DVec2 {
+        let (s, c) = (self.rotation * PI / 180.).sin_cos();
+        let p = st * self.scale;
+        DVec2::new(c * p.x - s * p.y, s * p.x + c * p.y) + self.offset
+    }
+}
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageTexture {
+    pub image: Arc<Image>,
+    pub wrap: WrapMode,
+    pub transform: UvTransform,
+}
+#[allow(dead_code)]
+impl ImageTexture {
+    pub fn new(image: Arc<Image>) -> Self {
+        Self { image, wrap: WrapMode::Repeat, transform: UvTransform::identity() }
+    }
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, TextureError> {
+        Ok(ImageTexture::new(Arc::new(Image::load(path)?)))
+    }
+    pub fn open_linear<P: AsRef<Path>>(path: P) -> Result<Self, TextureError> {
+        Ok(ImageTexture::new(Arc::new(Image::load_linear(path)?)))
+    }
+    // bilinear lookup, t = 0 is the bottom row
+    pub fn sample(&self, st: DVec2) -> DVec3 {
+        let st = self.transform.apply(st);
+        self.image.bilinear(st.x * self.image.width as f64, (1. - st.y) * self.image.height as f64, self.wrap, self.wrap)
+    }
+}
+// where procedural patterns are evaluated: (s, t, 0) or the hit point scaled
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TextureSpace {
+    Uv,
+    World(f64),
+}
+impl TextureSpace {
+    pub fn point(&self, st: DVec2, p: DVec3) -> DVec3 {
+        match self {
+            TextureSpace::Uv => st.extend(0.),
+            TextureSpace::World(scale) => p * *scale,
+        }
+    }
+}
+// scalar patterns return a grey color, use eval_scalar to read them back
+#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Texture {
+    Constant(DVec3),
+    Image(ImageTexture),
+    Checker { scale: f64, even: Box<Texture>, odd: Box<Texture>, space: TextureSpace },
+    Grid { scale: f64, width: f64, line: Box<Texture>, fill: Box<Texture>, space: TextureSpace },
+    Noise { scale: f64, octaves: u32, space: TextureSpace },
+    Simplex { scale: f64, octaves: u32, space: TextureSpace },
+    Turbulence { scale: f64, octaves: u32, space: TextureSpace },
+    Marble { scale: f64, octaves: u32, warp: f64, a: Box<Texture>, b: Box<Texture>, space: TextureSpace },
+    Wood { scale: f64, warp: f64, a: Box<Texture>, b: Box<Texture>, space: TextureSpace },
+    Voronoi { scale: f64, space: TextureSpace },
+    Mix { a: Box<Texture>, b: Box<Texture>, factor: Box<Texture> },
+    Product(Box<Texture>, Box<Texture>),
+}
+#[allow(dead_code)]
+impl Texture {
+    // the orange / yellow board objects used to hard-code
+    pub fn checker() -> Self {
+        Texture::Checker {
+            scale: 10.,
+            even: Box::new(Texture::Constant(DVec3::new(0.815, 0.235, 0.031))),
+            odd: Box::new(Texture::Constant(DVec3::new(0.937, 0.937, 0.231))),
+            space: TextureSpace::Uv,
+        }
+    }
+    pub fn eval(&self, st: DVec2, p: DVec3) -> DVec3 {
+        match self {
+            Texture::Constant(c) => *c,
+            Texture::Image(tex) => tex.sample(st),
+            Texture::Checker { scale, even, odd, space } => {
+                let q = (space.point(st, p) * *scale).floor();
+                match (q.x + q.y + q.z).rem_euclid(2.) < 1. {
+                    true => even.eval(st, p),
+                    false => odd.eval(st, p),
+                }
+            },
+            Texture::Grid { scale, width, line, fill, space } => {
+                let q = space.point(st, p) * *scale;
+                let f = q - q.floor();
+                let on = |v: f64| v < *width;
+                let hit = match space {
+                    TextureSpace::Uv => on(f.x) || on(f.y),
+                    TextureSpace::World(_) => on(f.x) || on(f.y) || on(f.z),
+                };
+                match hit {
+                    true => line.eval(st, p),
+                    false => fill.eval(st, p),
+                }
+            },
+            Texture::Noise { scale, octaves, space } => {
+                DVec3::splat((0.5 + 0.5 * fbm(space.point(st, p) * *scale, *octaves)).clamp(0., 1.))
+            },
+            Texture::Simplex { scale, octaves, space } => {
+                DVec3::splat((0.5 + 0.5 * simplex_fbm(space.point(st, p) * *scale, *octaves)).clamp(0., 1.))
+            },
+            Texture::Turbulence { scale, octaves, space } => {
+                DVec3::splat(turbulence(space.point(st, p) * *scale, *octaves).clamp(0., 1.))
+            },
+            Texture::Marble { scale, octaves, warp, a, b, space } => {
+                let q = space.point(st, p) * *scale;
+                let t = 0.5 + 0.5 * (q.x + *warp * turbulence(q, *octaves)).sin();
+                a.eval(st, p).lerp(b.eval(st, p), t)
+            },
+            Texture::Wood { scale, warp, a, b, space } => {
+                let q = space.point(st, p) * *scale;
+                let r = (q.x * q.x + q.z * q.z).sqrt() + *warp * perlin(q);
+                let t = (r - r.floor()).powi(3);
+                a.eval(st, p).lerp(b.eval(st, p), t)
+            },
+            Texture::Voronoi { scale, space } => {
+                DVec3::splat(worley(space.point(st, p) * *scale).0.clamp(0., 1.))
+            },
+            Texture::Mix { a, b, factor } => a.eval(st, p).lerp(b.eval(st, p), factor.eval_scalar(st, p)),
+            Texture::Product(a, b) => a.eval(st, p) * b.eval(st, p),
+        }
+    }
+    pub fn eval_scalar(&self, st: DVec2, p: DVec3) -> f64 {
+        self.eval(st, p).dot(DVec3::new(0.2126, 0.7152, 0.0722))
+    }
+}
+
+// perturbs the shading normal, the geometric normal is left alone
+#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum NormalMap {
+    // rgb encoded tangent space normal, load it with open_linear
+    Tangent { map: Texture, strength: f64 },
+    // scalar height field, strength in world units per unit height
+    Bump { height: Texture, strength: f64 },
+}
+impl NormalMap {
+    pub fn perturb(&self, n: DVec3, dpdu: DVec3, dpdv: DVec3, st: DVec2, p: DVec3) -> DVec3 {
+        match self {
+            NormalMap::Tangent { map, strength } => {
+                let t = (dpdu - n * n.dot(dpdu)).normalize();
+                // keep the bitangent on the side dpdv points to, st maps may be mirrored
+                let b = match n.cross(t).dot(dpdv) < 0. {
+                    true => -n.cross(t),
+                    false => n.cross(t),
+                };
+                let m = map.eval(st, p) * 2. - DVec3::ONE;
+                let ns = (t * m.x * *strength + b * m.y * *strength + n * m.z).normalize();
+                match ns.is_finite() {
+                    true => ns,
+                    false => n,
+                }
+            },
+            NormalMap::Bump { height, strength } => {
+                let delta = 0.0005;
+                let h = height.eval_scalar(st, p);
+                let du = (height.eval_scalar(st + DVec2::new(delta, 0.), p + dpdu * delta) - h) / delta;
+                let dv = (height.eval_scalar(st + DVec2::new(0., delta), p + dpdv * delta) - h) / delta;
+                let ns = (dpdu + n * du * *strength).cross(dpdv + n * dv * *strength).normalize();
+                match (ns.is_finite(), ns.dot(n) < 0.) {
+                    (false, _) => n,
+                    (true, true) => -ns,
+                    (true, false) => ns,
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Cursor, sync::Arc};
+
+    use glam::{DVec2, DVec3};
+
+    use super::{Image, ImageTexture, WrapMode, UvTransform, Texture, TextureSpace, NormalMap};
+
+    #[test]
+    fn test_wrap_modes() {
+        assert_eq!(WrapMode::Repeat.texel_index(-1, 4), 3);
+        assert_eq!(WrapMode::Repeat.texel_index(5, 4), 1);
+        assert_eq!(WrapMode::Clamp.texel_index(-3, 4), 0);
+        assert_eq!(WrapMode::Clamp.texel_index(9, 4), 3);
+        assert_eq!(WrapMode::Mirror.texel_index(-1, 4), 0);
+        assert_eq!(WrapMode::Mirror.texel_index(4, 4), 3);
+        assert_eq!(WrapMode::Mirror.texel_index(6, 4), 1);
+    }
+    #[test]
+    fn test_read_ppm() {
+        let mut fp = Cursor::new(b"P3\n# comment\n2 1\n255\n255 0 0  0 0 255\n".to_vec());
+        let img = Image::read_ppm(&mut fp).unwrap();
+        assert_eq!((img.width, img.height), (2, 1));
+        assert_eq!(img.texel(0, 0), DVec3::new(1., 0., 0.));
+        assert_eq!(img.texel(1, 0), DVec3::new(0., 0., 1.));
+        let mut bin = b"P6 1 1 255\n".to_vec();
+        bin.extend([0u8, 255, 0]);
+        let img = Image::read_ppm(&mut Cursor::new(bin)).unwrap();
+        assert_eq!(img.texel(0, 0), DVec3::new(0., 1., 0.));
+    }
+    #[test]
+    fn test_read_hdr() {
+        let mut buf = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 2\n".to_vec();
+        buf.extend([128u8, 64, 0, 129, 0, 0, 0, 0]);
+        let img = Image::read_hdr(&mut Cursor::new(buf)).unwrap();
+        assert_eq!(img.texel(0, 0), DVec3::new(1., 0.5, 0.));
+        assert_eq!(img.texel(1, 0), DVec3::ZERO);
+    }
+    #[test]
+    fn test_bilinear() {
+        let img = Arc::new(Image::new(2, 1, vec![DVec3::ZERO, DVec3::ONE]));
+        let mut tex = ImageTexture::new(img);
+        tex.wrap = WrapMode::Clamp;
+        assert_eq!(tex.sample(DVec2::new(0.5, 0.5)), DVec3::splat(0.5));
+        assert_eq!(tex.sample(DVec2::new(0., 0.5)), DVec3::ZERO);
+        tex.transform = UvTransform { scale: DVec2::ONE, offset: DVec2::new(0.5, 0.), rotation: 0. };
+        assert_eq!(Texture::Image(tex).eval(DVec2::new(0.25, 0.), DVec3::ZERO), DVec3::ONE);
+    }
+    #[test]
+    fn test_checker() {
+        let tex = Texture::checker();
+        assert_eq!(tex.eval(DVec2::new(0.01, 0.01), DVec3::ZERO), DVec3::new(0.815, 0.235, 0.031));
+        assert_eq!(tex.eval(DVec2::new(0.11, 0.01), DVec3::ZERO), DVec3::new(0.937, 0.937, 0.231));
+        let world = Texture::Checker { scale: 1., even: Box::new(Texture::Constant(DVec3::ZERO)), odd: Box::new(Texture::Constant(DVec3::ONE)), space: TextureSpace::World(0.5) };
+        assert_eq!(world.eval(DVec2::ZERO, DVec3::new(2.5, 0.2, -0.2)), DVec3::ZERO);
+        assert_eq!(world.eval(DVec2::ZERO, DVec3::new(2.5, 0.2, 0.2)), DVec3::ONE);
+    }
+    #[test]
+    fn test_composition() {
+        let white = Box::new(Texture::Constant(DVec3::ONE));
+        let black = Box::new(Texture::Constant(DVec3::ZERO));
+        let half = Box::new(Texture::Constant(DVec3::splat(0.5)));
+        let mix = Texture::Mix { a: black, b: white.clone(), factor: half.clone() };
+        assert!((mix.eval_scalar(DVec2::ZERO, DVec3::ZERO) - 0.5).abs() < 1e-12);
+        let prod = Texture::Product(white, half);
+        assert_eq!(prod.eval(DVec2::ZERO, DVec3::ZERO), DVec3::splat(0.5));
+        let marble = Texture::Marble { scale: 4., octaves: 4, warp: 2., a: Box::new(Texture::Constant(DVec3::ZERO)), b: Box::new(Texture::Constant(DVec3::ONE)), space: TextureSpace::World(1.) };
+        let v = marble.eval_scalar(DVec2::ZERO, DVec3::new(0.3, 0.7, 0.1));
+        assert!((0. ..=1.).contains(&v));
+    }
+    #[test]
+    fn test_normal_map() {
+        let n = DVec3::Z;
+        let (dpdu, dpdv) = (DVec3::X, DVec3::Y);
+        let flat = NormalMap::Tangent { map: Texture::Constant(DVec3::new(0.5, 0.5, 1.)), strength: 1. };
+        assert!((flat.perturb(n, dpdu, dpdv, DVec2::ZERO, DVec3::ZERO) - n).length() < 1e-12);
+        let tilted = NormalMap::Tangent { map: Texture::Constant(DVec3::new(1., 0.5, 0.5)), strength: 1. };
+        assert!((tilted.perturb(n, dpdu, dpdv, DVec2::ZERO, DVec3::ZERO) - DVec3::X).length() < 1e-12);
+        let level = NormalMap::Bump { height: Texture::Constant(DVec3::splat(0.3)), strength: 1. };
+        assert!((level.perturb(n, dpdu, dpdv, DVec2::ZERO, DVec3::ZERO) - n).length() < 1e-12);
+        // wood rings in uv space rise along u, the normal leans back against the slope
+        let grad = NormalMap::Bump { height: Texture::Wood { scale: 1., warp: 0., a: Box::new(Texture::Constant(DVec3::ZERO)), b: Box::new(Texture::Constant(DVec3::ONE)), space: TextureSpace::Uv }, strength: 0.1 };
+        let ns = grad.perturb(n, dpdu, dpdv, DVec2::new(0.6, 0.), DVec3::ZERO);
+        assert!(ns.x < 0. && ns.z > 0.);
+    }
+}
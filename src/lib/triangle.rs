@@ -1,7 +1,7 @@
-use std::f64::INFINITY;
 use core::marker::Copy;
-use glam::{DVec3, DVec2};
-use super::{Light, ObjectClone};
+use std::{collections::HashMap, sync::Arc};
+use glam::{DQuat, DVec3, DVec2};
+use super::{Light, ObjectClone, Texture, NormalMap, LightSource, TriangleMeshLight, Bsdf, ShapeBsdf, Aabb, coordinate_system};
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Triangle {
@@ -12,14 +12,31 @@ pub struct Triangle {
     pub s1: DVec2,
     pub s2: DVec2,
 }
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Clone)]
 pub enum Material {
     DiffuseAndGlossy,
     ReflectionAndRefraction,
     Reflection,
+    // anything else, the shape's ior, specular and diffuse fields are not used
+    Custom(Arc<dyn Bsdf>),
+}
+impl PartialEq for Material {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Material::Custom(a), Material::Custom(b)) => Arc::ptr_eq(a, b),
+            (Material::Custom(_), _) | (_, Material::Custom(_)) => false,
+            _ => std::mem::discriminant(self) == std::mem::discriminant(other),
+        }
+    }
 }
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct SpecularProperties(pub f64,pub f64,pub f64);
+// emitted radiance (W/sr/m^2) leaving the front face, or both faces
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Emission {
+    pub radiance: DVec3,
+    pub two_sided: bool,
+}
 #[allow(dead_code)]
 #[derive(Debug, Clone, PartialEq)]
 pub struct MeshTriangle {
@@ -27,23 +44,103 @@ pub struct MeshTriangle {
     pub material: Material,
     pub ior: f64,
     pub specular: SpecularProperties,
+    pub diffuse: Texture,
+    pub normal_map: Option<NormalMap>,
+    // per-vertex dpdu for each triangle, see compute_tangents; empty uses face tangents
+    pub tangents: Vec<[DVec3; 3]>,
+    pub emission: Option<Emission>,
+}
+#[allow(dead_code)]
+impl MeshTriangle {
+    // checkered diffuse mesh
+    pub fn new(vertices: Vec<Triangle>, material: Material) -> Self {
+        MeshTriangle {
+            vertices,
+            material,
+            ior: 1.3,
+            specular: SpecularProperties(25.0, 0.8, 0.2),
+            diffuse: Texture::checker(),
+            normal_map: None,
+            tangents: vec![],
+            emission: None,
+        }
+    }
+    // dpdu and dpdv of one face, from its positions and st coordinates
+    fn face_tangents(&self, idx: usize) -> (DVec3, DVec3) {
+        let tri = self.vertices[idx];
+        let (dp1, dp2) = (tri.v1 - tri.v0, tri.v2 - tri.v0);
+        let (duv1, duv2) = (tri.s1 - tri.s0, tri.s2 - tri.s0);
+        let det = duv1.x * duv2.y - duv1.y * duv2.x;
+        match det.abs() < 1e-12 {
+            // degenerate st mapping, any frame around the face normal will do
+            true => coordinate_system(dp1.cross(dp2).normalize()),
+            false => ((duv2.y * dp1 - duv1.y * dp2) / det, (duv1.x * dp2 - duv2.x * dp1) / det),
+        }
+    }
+    // smooth tangents: average face dpdu over triangles sharing a vertex position
+    pub fn compute_tangents(&mut self) {
+        // keyed on the bits, + 0. folds -0. into 0. as == would
+        let key = |v: DVec3| (v + DVec3::ZERO).to_array().map(f64::to_bits);
+        let mut shared: HashMap<[u64; 3], DVec3> = HashMap::new();
+        (0..self.vertices.len()).for_each(|i| {
+            let (dpdu, _) = self.face_tangents(i);
+            let tri = self.vertices[i];
+            [tri.v0, tri.v1, tri.v2].iter().for_each(|v| *shared.entry(key(*v)).or_insert(DVec3::ZERO) += dpdu);
+        });
+        let lookup = |v: DVec3| shared.get(&key(v)).copied().unwrap_or(DVec3::ZERO);
+        self.tangents = self.vertices.iter().map(|tri| [lookup(tri.v0), lookup(tri.v1), lookup(tri.v2)]).collect();
+    }
+}
+impl Default for MeshTriangle {
+    fn default() -> Self {
+        MeshTriangle::new(vec![], Material::DiffuseAndGlossy)
+    }
 }
-pub trait Object: ObjectClone {
+pub trait Object: ObjectClone + Send + Sync {
     fn intersection(&self, light: Light, dir: DVec3) -> (bool, f64, usize, DVec2);
-    fn eval_diffuse_color(&self, vx: DVec2) -> DVec3  {
-        let scale = 5f64;
-        let pattern = ((vx.x * scale) % 1f64 > 0.5) ^ ((vx.y * scale) % 1f64 > 0.5);
-        DVec3::new(0.815, 0.235, 0.031).lerp(DVec3::new(0.937, 0.937, 0.231), f64::from(u32::from(pattern)))
+    // what the render statistics file intersections under
+    fn kind(&self) -> &'static str {
+        "object"
     }
+    // motion blur: the intersection with the object where it is at `time`
+    fn intersection_at(&self, light: Light, dir: DVec3, _time: f64) -> (bool, f64, usize, DVec2) {
+        self.intersection(light, dir)
+    }
+    // the object frozen where it is at `time`, what a hit is shaded with
+    fn at_time(&self, _time: f64) -> Box<dyn Object> {
+        self.clone_box()
+    }
+    // world space box around everything the object covers during the shutter
+    fn bounds(&self) -> Aabb {
+        Aabb::infinite()
+    }
+    fn eval_diffuse_color(&self, vx: DVec2, p: DVec3) -> DVec3;
     fn get_surface_properties(&self, p:DVec3, px:DVec3, idx:usize, uv:DVec2) -> (DVec3, DVec2);
+    // partial derivatives of the surface position with respect to the texture coordinates
+    fn get_tangents(&self, p:DVec3, idx:usize, uv:DVec2) -> (DVec3, DVec3);
     fn get_material_properties(&self) -> Material;
     fn get_ior(&self) -> f64;
     fn get_specular_properties(&self) -> SpecularProperties;
+    // what cast_ray shades with, built from the fields above unless the material is custom
+    fn get_bsdf(&self) -> ShapeBsdf<'_>;
+    fn get_normal_map(&self) -> Option<&NormalMap> {
+        None
+    }
+    fn get_emission(&self) -> Option<Emission> {
+        None
+    }
+    // emitters hand out a light that samples their surface, registered by ObjectAppend
+    fn as_light(&self) -> Option<Box<dyn LightSource>> {
+        None
+    }
 }
 #[allow(dead_code)]
 impl Object for MeshTriangle {
+    fn kind(&self) -> &'static str {
+        "mesh"
+    }
     fn intersection(&self, light: Light, dir: DVec3) -> (bool, f64, usize, DVec2) {
-        let mut isec = false;let mut t = INFINITY;let mut b1 = 0.;let mut b2 = 0.;let mut ix: usize = 0;
+        let mut isec = false;let mut t = f64::INFINITY;let mut b1 = 0.;let mut b2 = 0.;let mut ix: usize = 0;
         self.vertices.iter().enumerate().for_each(|(i, x)| {
             let (cond, tn, b1t, b2t) = light.intersection(x.v0, x.v1, x.v2, dir);
             if cond && t > tn {
@@ -56,6 +153,9 @@ impl Object for MeshTriangle {
         });
         (isec, t, ix, DVec2::new(b1, b2))
     }
+    fn bounds(&self) -> Aabb {
+        self.vertices.iter().fold(Aabb::empty(), |b, t| b.with(t.v0).with(t.v1).with(t.v2))
+    }
     fn get_ior(&self) -> f64 {
         self.ior
     }
@@ -67,16 +167,89 @@ impl Object for MeshTriangle {
         let st = (1. - uv.x - uv.y) * tri.s0 + uv.x * tri.s1 + uv.y * tri.s2;
         (n, st)
     }
+    fn get_tangents(&self, _p:DVec3, idx: usize, uv: DVec2) -> (DVec3, DVec3) {
+        let (dpdu, dpdv) = self.face_tangents(idx);
+        // smooth tangents only turn the face frame within the face, the lengths
+        // bump maps scale their slopes by stay the same
+        if let Some([t0, t1, t2]) = self.tangents.get(idx) {
+            let tri = self.vertices[idx];
+            let n = (tri.v1 - tri.v0).cross(tri.v2 - tri.v0).normalize();
+            let t = (1. - uv.x - uv.y) * *t0 + uv.x * *t1 + uv.y * *t2;
+            let t = (t - n * n.dot(t)).normalize();
+            let u = (dpdu - n * n.dot(dpdu)).normalize();
+            if t.is_finite() && u.is_finite() {
+                let turn = DQuat::from_axis_angle(n, u.cross(t).dot(n).atan2(u.dot(t)));
+                return (turn * dpdu, turn * dpdv);
+            }
+        }
+        (dpdu, dpdv)
+    }
     fn get_material_properties(&self) -> Material {
-        self.material
+        self.material.clone()
+    }
+    fn get_bsdf(&self) -> ShapeBsdf<'_> {
+        self.material.bsdf(&self.diffuse, self.ior, self.specular)
     }
     fn get_specular_properties(&self) -> SpecularProperties {
         self.specular
     }
+    fn eval_diffuse_color(&self, vx: DVec2, p: DVec3) -> DVec3 {
+        self.diffuse.eval(vx, p)
+    }
+    fn get_normal_map(&self) -> Option<&NormalMap> {
+        self.normal_map.as_ref()
+    }
+    fn get_emission(&self) -> Option<Emission> {
+        self.emission
+    }
+    fn as_light(&self) -> Option<Box<dyn LightSource>> {
+        let e = self.emission?;
+        let tris = self.vertices.iter().map(|t| [t.v0, t.v1, t.v2]).collect();
+        Some(Box::new(TriangleMeshLight::new(tris, e.radiance, e.two_sided)))
+    }
 }
 
 // impl Copy for MeshTriangle {
     // fn copy(&self) -> &Self {
         // todo!()
     // }
-// }
\ No newline at end of file
+// }
+#[cfg(test)]
+mod tests {
+    use glam::{DVec2, DVec3};
+
+    use crate::lib::{Object, NormalMap, Texture, TextureSpace, Material};
+
+    use super::{MeshTriangle, Triangle};
+
+    #[test]
+    fn test_smooth_tangents_keep_bump_scale() {
+        // skewed st so dpdu and dpdv are neither unit nor orthogonal
+        let tri = Triangle { v0: DVec3::ZERO, v1: DVec3::new(4., 0., 0.), v2: DVec3::new(1., 0., -3.), s0: DVec2::ZERO, s1: DVec2::new(0.5, 0.1), s2: DVec2::new(0.2, 0.9) };
+        let height = Texture::Wood { scale: 1., warp: 0., a: Box::new(Texture::Constant(DVec3::ZERO)), b: Box::new(Texture::Constant(DVec3::ONE)), space: TextureSpace::Uv };
+        let mut mesh = MeshTriangle { normal_map: Some(NormalMap::Bump { height, strength: 0.1 }), ..MeshTriangle::new(vec![tri], Material::DiffuseAndGlossy) };
+        let shade = |mesh: &MeshTriangle, uv: DVec2| {
+            let p = tri.v0 * (1. - uv.x - uv.y) + tri.v1 * uv.x + tri.v2 * uv.y;
+            let (n, st) = mesh.get_surface_properties(p, -DVec3::Y, 0, uv);
+            let (dpdu, dpdv) = mesh.get_tangents(p, 0, uv);
+            mesh.get_normal_map().unwrap().perturb(n, dpdu, dpdv, st, p)
+        };
+        let uvs = [DVec2::new(0.2, 0.3), DVec2::new(0.6, 0.1), DVec2::new(0.1, 0.7)];
+        let before: Vec<DVec3> = uvs.iter().map(|uv| shade(&mesh, *uv)).collect();
+        mesh.compute_tangents();
+        assert_eq!(mesh.tangents.len(), 1);
+        uvs.iter().zip(before).for_each(|(uv, ns)| assert!((shade(&mesh, *uv) - ns).length() < 1e-9, "{} {}", shade(&mesh, *uv), ns));
+    }
+    #[test]
+    fn test_compute_tangents_shares_vertices() {
+        // two faces of a fold share the edge v1 v2, their tangents average there
+        let a = Triangle { v0: DVec3::ZERO, v1: DVec3::X, v2: DVec3::Z, s0: DVec2::ZERO, s1: DVec2::X, s2: DVec2::Y };
+        let b = Triangle { v0: DVec3::new(1., 1., 1.), v1: DVec3::Z, v2: DVec3::X, s0: DVec2::ONE, s1: DVec2::Y, s2: DVec2::X };
+        let mut mesh = MeshTriangle::new(vec![a, b], Material::DiffuseAndGlossy);
+        mesh.compute_tangents();
+        let (ua, _) = mesh.face_tangents(0);
+        let (ub, _) = mesh.face_tangents(1);
+        assert_eq!(mesh.tangents[0], [ua, ua + ub, ua + ub]);
+        assert_eq!(mesh.tangents[1], [ub, ua + ub, ua + ub]);
+    }
+}
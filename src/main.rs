@@ -1,48 +1,235 @@
-use glam::{DVec3, DVec2};
-use lib::{Scene, Sphere, ObjectAppend, SpecularProperties, MeshTriangle, Triangle, LightAppend, render};
+#![allow(special_module_name)]
+use std::{env, path::PathBuf, process::ExitCode, thread};
+use lib::{Scene, Integrator, ImageFormat, TerminalBar, BUILTIN_SCENES, SAMPLERS, builtin_scene, load_scene, sampler_by_name, render};
 
 mod lib;
 
-fn main() {
-    let mut sc = Scene::window(1280, 960);
-    let sph1 = Sphere { center: DVec3::new(-1., 0., -12.), radius: 2., radius2: 4., material: lib::Material::DiffuseAndGlossy, ior: 1.3, specular: SpecularProperties(25.0, 0.8, 0.2), diffuse_color: DVec3::new(0.6, 0.7, 0.8)};
-    let sph2 = Sphere { center: DVec3::new(0.5, -0.5, -8.), radius: 1.5, radius2: 2.25, material: lib::Material::ReflectionAndRefraction, ior: 1.5, specular: SpecularProperties(25.0, 0.8, 0.2), diffuse_color: DVec3::splat(0.2)};
-    
-    ObjectAppend::append(&mut sc, Box::new(sph1));
-    ObjectAppend::append(&mut sc, Box::new(sph2));
-    let verts = vec![
-        DVec3::new(-5., -3., -6.),
-        DVec3::new(5., -3., -6.),
-        DVec3::new(5., -3., -16.),
-        DVec3::new(-5., -3., -16.)
-    ];
-    let st = vec![
-        DVec2::new(0., 0.),
-        DVec2::new(1., 0.),
-        DVec2::new(1., 1.),
-        DVec2::new(0., 1.),
-    ];
-    let vertsx = vec![
-        Triangle { v0: verts[0], v1: verts[1], v2: verts[3], s0: st[0], s1: st[1], s2: st[3] },
-        Triangle { v0: verts[1], v1: verts[2], v2: verts[3], s0: st[1], s1: st[2], s2: st[3] },
-    ];
-    let mesh = MeshTriangle {
-        vertices: vertsx,
-        material: lib::Material::DiffuseAndGlossy,
-        ior: 1.3,
-        specular: SpecularProperties(25.0, 0.8, 0.2),
+// exit codes
+const OK: u8 = 0;
+const RENDER_FAILED: u8 = 1;
+const BAD_ARGUMENTS: u8 = 2;
+const SCENE_FAILED: u8 = 3;
+
+#[derive(Debug, Clone, PartialEq)]
+struct Options {
+    // a scene file or one of BUILTIN_SCENES
+    scene: String,
+    width: Option<i32>,
+    height: Option<i32>,
+    fov: Option<f64>,
+    spp: Option<u32>,
+    max_depth: Option<i16>,
+    integrator: Integrator,
+    // one of SAMPLERS, independent unless given
+    sampler: Option<String>,
+    seed: Option<u64>,
+    threads: usize,
+    output: PathBuf,
+    format: Option<ImageFormat>,
+    quiet: bool,
+    verbose: bool,
+    help: bool,
+}
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            scene: "spheres".to_string(), width: None, height: None, fov: None, spp: None, max_depth: None, integrator: Integrator::Whitted, sampler: None, seed: None,
+            threads: thread::available_parallelism().map_or(1, |n| n.get()), output: PathBuf::from("binary.ppm"), format: None, quiet: false, verbose: false, help: false,
+        }
+    }
+}
+fn usage() -> String {
+    let mut s = "\
+usage: rs-render [options] [scene]
+
+  scene                  a scene file or a built-in scene, spheres if left out
+  --width <n>            image width, overrides the scene's
+  --height <n>           image height, overrides the scene's
+  --fov <degrees>        horizontal field of view, overrides the scene's
+  --spp <n>              camera rays per pixel, overrides the scene's
+  --max-depth <n>        bounce limit, overrides the scene's
+  --integrator <name>    whitted (default), albedo, normal or depth
+  --sampler <name>       independent (default), stratified, halton, sobol or
+                         bluenoise
+  --seed <n>             the sampler's seed, 0 by default
+  -j, --threads <n>      render threads, all cores by default
+  -o, --output <path>    image to write, binary.ppm by default
+  --format <ppm|png>     image format, by the output's extension by default
+  -q, --quiet            no progress bar
+  -v, --verbose          print render statistics when done
+  -h, --help             this text
+
+built-in scenes:
+".to_string();
+    BUILTIN_SCENES.iter().for_each(|(name, about, _)| s += &format!("  {:<23}{}\n", name, about));
+    s += "
+exit status: 0 rendered, 1 render or output failed, 2 bad arguments,
+3 scene could not be loaded
+";
+    s
+}
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut scene = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+        fn number<T: std::str::FromStr>(arg: &str, v: &str) -> Result<T, String> {
+            v.parse().map_err(|_| format!("bad value {} for {}", v, arg))
+        }
+        match arg.as_str() {
+            "--width" => options.width = Some(number(arg, value()?)?),
+            "--height" => options.height = Some(number(arg, value()?)?),
+            "--fov" => options.fov = Some(number(arg, value()?)?),
+            "--spp" => options.spp = Some(number(arg, value()?)?),
+            "--max-depth" => options.max_depth = Some(number(arg, value()?)?),
+            "--integrator" => options.integrator = match value()?.as_str() {
+                "whitted" => Integrator::Whitted,
+                "albedo" => Integrator::Albedo,
+                "normal" => Integrator::Normal,
+                "depth" => Integrator::Depth,
+                v => return Err(format!("unknown integrator {}", v)),
+            },
+            "--sampler" => options.sampler = match value()? {
+                v if SAMPLERS.contains(&v.as_str()) => Some(v.clone()),
+                v => return Err(format!("unknown sampler {}", v)),
+            },
+            "--seed" => options.seed = Some(number(arg, value()?)?),
+            "-j" | "--threads" => options.threads = number(arg, value()?)?,
+            "-o" | "--output" => options.output = PathBuf::from(value()?),
+            "--format" => options.format = match value()?.as_str() {
+                "ppm" => Some(ImageFormat::Ppm),
+                "png" => Some(ImageFormat::Png),
+                v => return Err(format!("unknown format {}", v)),
+            },
+            "-q" | "--quiet" => options.quiet = true,
+            "-v" | "--verbose" => options.verbose = true,
+            "-h" | "--help" => options.help = true,
+            a if a.starts_with('-') => return Err(format!("unknown option {}", a)),
+            a => match scene {
+                None => scene = Some(a.to_string()),
+                Some(_) => return Err(format!("more than one scene given, {}", a)),
+            },
+        }
+    }
+    if options.threads == 0 || options.width.is_some_and(|w| w <= 0) || options.height.is_some_and(|h| h <= 0) || options.spp == Some(0) {
+        return Err("threads, width, height and spp must be positive".to_string());
+    }
+    options.scene = scene.unwrap_or(options.scene);
+    Ok(options)
+}
+// the scene with the options applied
+fn scene(options: &Options) -> Result<Scene, String> {
+    let mut scene = match builtin_scene(&options.scene) {
+        Some(scene) => scene,
+        None => load_scene(&options.scene).map_err(|e| format!("{}: {}", options.scene, e))?,
+    };
+    scene.width = options.width.unwrap_or(scene.width);
+    scene.height = options.height.unwrap_or(scene.height);
+    scene.fov = options.fov.unwrap_or(scene.fov);
+    scene.spp = options.spp.unwrap_or(scene.spp);
+    scene.max_depth = options.max_depth.unwrap_or(scene.max_depth);
+    scene.integrator = options.integrator;
+    if options.sampler.is_some() || options.seed.is_some() {
+        let name = options.sampler.as_deref().unwrap_or("independent");
+        // stratified over the spp set above
+        scene.sampler = sampler_by_name(name, options.seed.unwrap_or(0), scene.spp).ok_or(format!("unknown sampler {}", name))?;
+    }
+    scene.threads = options.threads;
+    scene.output = options.output.clone();
+    scene.format = options.format.or_else(|| ImageFormat::from_path(&options.output)).unwrap_or_default();
+    if !options.quiet {
+        scene.progress = Box::new(TerminalBar::default());
+    }
+    Ok(scene)
+}
+fn run(args: &[String]) -> u8 {
+    let options = match parse_args(args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, usage());
+            return BAD_ARGUMENTS;
+        }
+    };
+    if options.help {
+        print!("{}", usage());
+        return OK;
+    }
+    let mut sc = match scene(&options) {
+        Ok(sc) => sc,
+        Err(e) => {
+            eprintln!("{}", e);
+            return SCENE_FAILED;
+        }
     };
-    ObjectAppend::append(&mut sc, Box::new(mesh));
-    LightAppend::append(&mut sc, lib::Light { org: DVec3::new(-20., 70., 20.), inten: DVec3::splat(0.5) });
-    LightAppend::append(&mut sc, lib::Light { org: DVec3::new(30., 50., -12.), inten: DVec3::splat(0.5) });
-    render(&mut sc);
+    match render(&mut sc) {
+        Ok(stats) => {
+            if options.verbose {
+                println!("{}", stats);
+            }
+            OK
+        }
+        Err(e) => {
+            eprintln!("{}: {}", options.output.display(), e);
+            RENDER_FAILED
+        }
+    }
+}
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    ExitCode::from(run(&args))
 }
 #[cfg(test)]
 mod tests {
-    use crate::main;
+    use std::{fs, path::PathBuf};
+
+    use crate::lib::{Integrator, ImageFormat, TempPath};
+
+    use super::{run, parse_args, usage, scene, OK, RENDER_FAILED, BAD_ARGUMENTS, SCENE_FAILED};
 
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(String::from).collect()
+    }
     #[test]
     fn test_main() {
-        main();
+        // the default scene, small and out of the way of a real render
+        let out = TempPath::new("main.ppm");
+        assert_eq!(run(&args(&format!("--quiet --width 160 --height 120 -o {}", out.display()))), OK);
+        let ppm = fs::read(&*out).unwrap();
+        assert!(ppm.starts_with(b"P6\n160 120\n255\n"));
+    }
+    #[test]
+    fn test_parse_args() {
+        let o = parse_args(&args("glass --width 64 --height 48 --fov 60 --spp 2 --max-depth 3 --integrator depth -j 3 -o out.png -q -v")).unwrap();
+        assert_eq!((o.scene.as_str(), o.width, o.height, o.fov, o.spp, o.max_depth), ("glass", Some(64), Some(48), Some(60.), Some(2), Some(3)));
+        assert_eq!((o.integrator, o.threads, o.output.clone(), o.quiet, o.verbose), (Integrator::Depth, 3, PathBuf::from("out.png"), true, true));
+        let sc = scene(&o).unwrap();
+        assert_eq!((sc.width, sc.height, sc.spp, sc.max_depth, sc.threads, sc.format), (64, 48, 2, 3, 3, ImageFormat::Png));
+        assert_eq!(scene(&parse_args(&args("--format ppm -o out.png")).unwrap()).unwrap().format, ImageFormat::Ppm);
+        let o = parse_args(&args("--sampler sobol --seed 9")).unwrap();
+        assert_eq!((o.sampler.as_deref(), o.seed), (Some("sobol"), Some(9)));
+        assert!(scene(&o).is_ok());
+        // the scene's own settings unless overridden
+        assert_eq!(scene(&parse_args(&[]).unwrap()).unwrap().width, 1280);
+        ["--spp", "--spp x", "--spp 0", "--width 0", "-j 0", "--integrator path", "--format jpg", "--sampler random", "--seed -1", "--bogus", "a b"].iter().for_each(|a| {
+            assert!(parse_args(&args(a)).is_err(), "{}", a);
+        });
+        assert!(usage().contains("spheres") && usage().contains("soft"));
     }
-}
\ No newline at end of file
+    #[test]
+    fn test_exit_codes() {
+        assert_eq!(run(&args("--help")), OK);
+        assert_eq!(run(&args("--spp")), BAD_ARGUMENTS);
+        assert_eq!(run(&args("no/such.scene")), SCENE_FAILED);
+        let (file, out) = (TempPath::new("exit_codes.scene"), TempPath::new("exit_codes.png"));
+        fs::write(&*file, "size 8 6\nsphere 0 0 -4 1 bogus").unwrap();
+        assert_eq!(run(&args(&file.display().to_string())), SCENE_FAILED);
+        fs::write(&*file, "size 0 0\nsphere 0 0 -4 1 diffuse 1 1 1").unwrap();
+        assert_eq!(run(&args(&file.display().to_string())), SCENE_FAILED);
+        fs::write(&*file, "size 8 6\nsphere 0 0 -4 1 diffuse 1 1 1\nlight point 0 4 0 50 50 50").unwrap();
+        assert_eq!(run(&args(&format!("{} -q -o no/such/dir/out.ppm", file.display()))), RENDER_FAILED);
+        assert_eq!(run(&args(&format!("{} -q -j 2 -o {}", file.display(), out.display()))), OK);
+        let png = fs::read(&*out).unwrap();
+        assert_eq!(&png[1..4], b"PNG");
+    }
+}